@@ -100,6 +100,31 @@ pub fn decrypt(package: &[u8], aont_mask_key: &[u8; 32]) -> Result<Vec<u8>> {
     Ok(plaintext)
 }
 
+/// Re-protects an AONT package under a new mask key without touching the ciphertext.
+///
+/// The ephemeral key is recovered with `old_mask_key` and verified by a full
+/// decryption, then re-masked: $X' = K_{rand} \oplus \text{BLAKE3}_{new}(C)$.
+pub fn rekey(package: &[u8], old_mask_key: &[u8; 32], new_mask_key: &[u8; 32]) -> Result<Vec<u8>> {
+    // Refuse to re-mask anything that does not authenticate under the old key.
+    decrypt(package, old_mask_key)?;
+
+    let split_idx = package.len() - BLOCK_SIZE;
+    let (c_part, x_part) = package.split_at(split_idx);
+    let old_hash = blake3::keyed_hash(old_mask_key, c_part);
+    let new_hash = blake3::keyed_hash(new_mask_key, c_part);
+
+    let mut rekeyed = Vec::with_capacity(package.len());
+    rekeyed.extend_from_slice(c_part);
+    rekeyed.extend(
+        x_part
+            .iter()
+            .zip(old_hash.as_bytes())
+            .zip(new_hash.as_bytes())
+            .map(|((x, old), new)| x ^ old ^ new),
+    );
+    Ok(rekeyed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,4 +184,19 @@ mod tests {
         let res = decrypt(&package, &WRONG_MASK_KEY);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_rekey_moves_package_to_new_mask_key() {
+        let data = b"rotate me";
+        let package = encrypt(data, &TEST_MASK_KEY).expect("encrypt");
+        let rekeyed = rekey(&package, &TEST_MASK_KEY, &WRONG_MASK_KEY).expect("rekey");
+
+        assert_eq!(
+            package[..package.len() - BLOCK_SIZE],
+            rekeyed[..rekeyed.len() - BLOCK_SIZE]
+        );
+        assert_eq!(decrypt(&rekeyed, &WRONG_MASK_KEY).expect("decrypt"), data);
+        assert!(decrypt(&rekeyed, &TEST_MASK_KEY).is_err());
+        assert!(rekey(&package, &WRONG_MASK_KEY, &TEST_MASK_KEY).is_err());
+    }
 }
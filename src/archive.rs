@@ -0,0 +1,361 @@
+use crate::backend::ShardBackend;
+use crate::block_store::BlockStore;
+use crate::io_guard::IoOptions;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+const ARCHIVE_MAGIC: [u8; 8] = *b"IRCLDARC";
+const ARCHIVE_VERSION: u16 = 1;
+const HEADER_LEN: u64 = ARCHIVE_MAGIC.len() as u64 + 2;
+/// Index offset, index length, index hash and the magic again.
+const FOOTER_LEN: u64 = 8 + 8 + 32 + ARCHIVE_MAGIC.len() as u64;
+
+/// One stored object inside an archive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub offset: u64,
+    pub length: u64,
+    /// BLAKE3 of the object's bytes.
+    pub hash: [u8; 32],
+}
+
+/// Table of contents written after the objects, so an archive can be
+/// produced in one pass.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveIndex {
+    /// Epoch whose manifest quorum the archive carries.
+    pub epoch: u64,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl ArchiveIndex {
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.length).sum()
+    }
+}
+
+/// Outcome of writing an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportReport {
+    pub epoch: u64,
+    pub objects: usize,
+    pub bytes: u64,
+    /// Shard files the manifest references that were not found; the archive
+    /// is still usable as long as every block keeps enough shards.
+    pub missing: Vec<String>,
+}
+
+/// Outcome of installing an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    pub epoch: u64,
+    pub objects: usize,
+    pub bytes: u64,
+    pub degraded_blocks: usize,
+}
+
+/// Writes the named objects as an archive. `load` returns an object's bytes,
+/// or `None` for one that is gone, which is then left out and reported.
+pub(crate) fn write_archive<W: Write>(
+    mut writer: W,
+    epoch: u64,
+    names: Vec<String>,
+    load: impl Fn(&str) -> Result<Option<Vec<u8>>>,
+) -> Result<ExportReport> {
+    writer.write_all(&ARCHIVE_MAGIC)?;
+    writer.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+    let mut offset = HEADER_LEN;
+    let mut entries = Vec::new();
+    let mut missing = Vec::new();
+    for name in names {
+        let Some(bytes) = load(&name)? else {
+            missing.push(name);
+            continue;
+        };
+        writer.write_all(&bytes)?;
+        entries.push(ArchiveEntry {
+            name,
+            offset,
+            length: bytes.len() as u64,
+            hash: *blake3::hash(&bytes).as_bytes(),
+        });
+        offset += bytes.len() as u64;
+    }
+
+    let index = ArchiveIndex { epoch, entries };
+    let index_bytes = bincode::serde::encode_to_vec(&index, bincode::config::standard())?;
+    writer.write_all(&index_bytes)?;
+    writer.write_all(&offset.to_le_bytes())?;
+    writer.write_all(&(index_bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(blake3::hash(&index_bytes).as_bytes())?;
+    writer.write_all(&ARCHIVE_MAGIC)?;
+    writer.flush()?;
+
+    Ok(ExportReport {
+        epoch,
+        objects: index.entries.len(),
+        bytes: index.total_bytes(),
+        missing,
+    })
+}
+
+/// Reads and checks the header, footer and index of an archive.
+pub fn read_index<R: Read + Seek>(reader: &mut R) -> Result<ArchiveIndex> {
+    let size = reader.seek(SeekFrom::End(0))?;
+    if size < HEADER_LEN + FOOTER_LEN {
+        return Err(anyhow!("Not an ironclad archive: file too short"));
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    if header[..8] != ARCHIVE_MAGIC {
+        return Err(anyhow!("Not an ironclad archive: bad magic"));
+    }
+    let version = u16::from_le_bytes([header[8], header[9]]);
+    if version != ARCHIVE_VERSION {
+        return Err(anyhow!("Unsupported archive version {}", version));
+    }
+
+    reader.seek(SeekFrom::Start(size - FOOTER_LEN))?;
+    let mut footer = [0u8; FOOTER_LEN as usize];
+    reader.read_exact(&mut footer)?;
+    if footer[48..] != ARCHIVE_MAGIC {
+        return Err(anyhow!("Archive is truncated: footer missing"));
+    }
+    let index_offset = u64::from_le_bytes(footer[..8].try_into()?);
+    let index_len = u64::from_le_bytes(footer[8..16].try_into()?);
+    if index_offset < HEADER_LEN
+        || index_offset
+            .checked_add(index_len)
+            .is_none_or(|end| end != size - FOOTER_LEN)
+    {
+        return Err(anyhow!("Archive footer points outside the file"));
+    }
+
+    reader.seek(SeekFrom::Start(index_offset))?;
+    let mut index_bytes = vec![0u8; usize::try_from(index_len)?];
+    reader.read_exact(&mut index_bytes)?;
+    if blake3::hash(&index_bytes).as_bytes()[..] != footer[16..48] {
+        return Err(anyhow!("Archive index checksum mismatch"));
+    }
+    let (index, used) = bincode::serde::decode_from_slice::<ArchiveIndex, _>(
+        &index_bytes,
+        bincode::config::standard(),
+    )?;
+    if used != index_bytes.len() {
+        return Err(anyhow!("Unexpected trailing bytes in archive index"));
+    }
+
+    let mut names = HashSet::new();
+    for entry in &index.entries {
+        if !names.insert(entry.name.as_str()) {
+            return Err(anyhow!("Archive lists '{}' twice", entry.name));
+        }
+        if !BlockStore::is_managed_file(&entry.name)
+            || entry.name.contains(['/', '\\'])
+            || entry.name.starts_with("manifest_")
+        {
+            return Err(anyhow!(
+                "Archive entry '{}' is not a dataset object",
+                entry.name
+            ));
+        }
+        let in_bounds = entry.offset >= HEADER_LEN
+            && entry
+                .offset
+                .checked_add(entry.length)
+                .is_some_and(|end| end <= index_offset);
+        if !in_bounds {
+            return Err(anyhow!(
+                "Archive entry '{}' lies outside the data",
+                entry.name
+            ));
+        }
+    }
+    Ok(index)
+}
+
+/// Read-only view of an archive's objects, so it can be opened and checked
+/// like a dataset before anything is installed. Every object is checked
+/// against its checksum when it is read.
+struct ArchiveBackend<R> {
+    reader: Mutex<R>,
+    entries: BTreeMap<String, ArchiveEntry>,
+}
+
+impl<R> fmt::Debug for ArchiveBackend<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveBackend")
+            .field("objects", &self.entries.len())
+            .finish()
+    }
+}
+
+impl<R: Read + Seek + Send> ShardBackend for ArchiveBackend<R> {
+    fn put(&self, name: &str, _bytes: &[u8]) -> Result<()> {
+        Err(anyhow!("Archive is read-only; cannot store {}", name))
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let Some(entry) = self.entries.get(name) else {
+            return Ok(None);
+        };
+        let mut reader = self
+            .reader
+            .lock()
+            .map_err(|_| anyhow!("Archive reader poisoned"))?;
+        reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut bytes = vec![0u8; usize::try_from(entry.length)?];
+        reader.read_exact(&mut bytes)?;
+        if *blake3::hash(&bytes).as_bytes() != entry.hash {
+            return Err(anyhow!("Archive entry '{}' checksum mismatch", name));
+        }
+        Ok(Some(bytes))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        Err(anyhow!("Archive is read-only; cannot delete {}", name))
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(self.entries.keys().cloned().collect())
+    }
+
+    fn location(&self) -> String {
+        "archive".to_string()
+    }
+}
+
+/// Installs the archive into `backend`, which must not hold a dataset yet.
+/// The archive is first opened where it is and must be a dataset `root_key`
+/// can open at the archived epoch with every block still decodable; only
+/// then are its objects copied, each checked against its checksum. If a copy
+/// fails, the objects already stored are removed again.
+pub fn import<R: Read + Seek + Send + 'static>(
+    mut reader: R,
+    backend: Arc<dyn ShardBackend>,
+    root_key: [u8; 32],
+    io_options: IoOptions,
+) -> Result<ImportReport> {
+    if BlockStore::is_initialized(backend.as_ref())? {
+        return Err(anyhow!(
+            "{} already holds a dataset; import into an empty location",
+            backend.location()
+        ));
+    }
+    let index = read_index(&mut reader)?;
+    let archive = Arc::new(ArchiveBackend {
+        reader: Mutex::new(reader),
+        entries: index
+            .entries
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.clone()))
+            .collect(),
+    });
+
+    let store = BlockStore::open_with_backend(archive.clone(), root_key, io_options)
+        .map_err(|err| anyhow!("Archive holds no valid manifest quorum: {:#}", err))?;
+    if store.manifest.epoch != index.epoch {
+        return Err(anyhow!(
+            "Archive index is for epoch {} but its manifest quorum is for epoch {}",
+            index.epoch,
+            store.manifest.epoch
+        ));
+    }
+    let scrub = store.scrub(false)?;
+    if scrub.unrecoverable_blocks() > 0 {
+        return Err(anyhow!(
+            "Archive is missing shards: {} block(s) cannot be reconstructed",
+            scrub.unrecoverable_blocks()
+        ));
+    }
+    drop(store);
+
+    let mut stored = Vec::new();
+    let copied = (|| -> Result<()> {
+        for entry in &index.entries {
+            let bytes = archive
+                .get(&entry.name)?
+                .ok_or_else(|| anyhow!("Archive entry '{}' vanished", entry.name))?;
+            backend.put(&entry.name, &bytes)?;
+            stored.push(&entry.name);
+        }
+        Ok(())
+    })();
+    if let Err(err) = copied {
+        for name in stored {
+            let _ = backend.delete(name);
+        }
+        return Err(err);
+    }
+    Ok(ImportReport {
+        epoch: index.epoch,
+        objects: index.entries.len(),
+        bytes: index.total_bytes(),
+        degraded_blocks: scrub.degraded_blocks(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn archive(objects: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let names = objects.iter().map(|(name, _)| name.to_string()).collect();
+        let report = write_archive(&mut bytes, 4, names, |name| {
+            Ok(objects
+                .iter()
+                .find(|(candidate, _)| *candidate == name)
+                .map(|(_, data)| data.to_vec()))
+        })
+        .unwrap();
+        assert_eq!(report.objects, objects.len());
+        bytes
+    }
+
+    #[test]
+    fn test_index_round_trip_and_missing_objects() {
+        let bytes = archive(&[("block_1_0.bin", b"abc"), ("meta_4_0.bin", b"")]);
+        let index = read_index(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(index.epoch, 4);
+        assert_eq!(index.entries.len(), 2);
+        assert_eq!(index.entries[0].offset, HEADER_LEN);
+        assert_eq!(index.total_bytes(), 3);
+
+        let mut out = Vec::new();
+        let names = vec!["block_1_0.bin".to_string(), "block_1_1.bin".to_string()];
+        let report = write_archive(&mut out, 1, names, |name| {
+            Ok((name == "block_1_0.bin").then(|| b"x".to_vec()))
+        })
+        .unwrap();
+        assert_eq!(report.missing, vec!["block_1_1.bin".to_string()]);
+    }
+
+    #[test]
+    fn test_damaged_archives_are_rejected() {
+        let bytes = archive(&[("block_1_0.bin", b"abc")]);
+        let err = read_index(&mut Cursor::new(&bytes[..bytes.len() - 1])).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+
+        let mut corrupt_index = bytes.clone();
+        let index_start = HEADER_LEN as usize + 3;
+        corrupt_index[index_start + 2] ^= 1;
+        let err = read_index(&mut Cursor::new(&corrupt_index)).unwrap_err();
+        assert!(err.to_string().contains("checksum"));
+
+        let mut not_archive = bytes;
+        not_archive[0] = b'X';
+        assert!(read_index(&mut Cursor::new(&not_archive)).is_err());
+
+        let escaping = archive(&[("../block_1_0.bin", b"abc")]);
+        let err = read_index(&mut Cursor::new(&escaping)).unwrap_err();
+        assert!(err.to_string().contains("not a dataset object"));
+    }
+}
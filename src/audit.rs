@@ -0,0 +1,551 @@
+use crate::backend::ShardBackend;
+use crate::block_store::BlockStore;
+use crate::chunk_format;
+use crate::integrity::Manifest;
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use anyhow::{Result, anyhow};
+use blake3::CHUNK_LEN;
+use blake3::hazmat::{
+    ChainingValue, HasherExt, Mode, left_subtree_len, merge_subtrees_non_root, merge_subtrees_root,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Key the verifier keeps to itself. It authenticates the challenges it
+/// issues, so a challenge can be stored alongside the data it audits.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AuditSecret(pub [u8; 32]);
+
+impl AuditSecret {
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        AuditSecret(secret)
+    }
+
+    pub fn from_hex(value: &str) -> Result<Self> {
+        Ok(AuditSecret(parse_hash(value.trim())?))
+    }
+
+    pub fn to_hex(self) -> String {
+        to_hex(&self.0)
+    }
+}
+
+/// One sampled shard and the hash and length its payload must have.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChallengeItem {
+    pub block_id: usize,
+    pub shard_index: usize,
+    pub shard_hash: String,
+    /// Fixed when the challenge is issued, so the storage side cannot pick
+    /// a length that steers the challenged chunk to data it kept.
+    pub shard_len: u64,
+}
+
+/// Random shards the storage side must prove it still holds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub epoch: u64,
+    /// Fresh per challenge; also decides which chunk of each shard is asked for.
+    pub nonce: String,
+    pub items: Vec<ChallengeItem>,
+    /// Keyed BLAKE3 of the fields above under the verifier's secret.
+    pub tag: String,
+}
+
+impl Challenge {
+    /// Samples up to `samples` distinct shards of every block `manifest`
+    /// references, snapshots included, uniformly at random. `shard_len`
+    /// gives the trusted payload length of each sampled shard.
+    pub fn new(
+        secret: &AuditSecret,
+        manifest: &Manifest,
+        samples: usize,
+        shard_len: impl Fn(&ChallengeItem) -> Result<u64>,
+    ) -> Result<Self> {
+        if samples == 0 {
+            return Err(anyhow!("An audit needs at least one sample"));
+        }
+        let mut seen = HashSet::new();
+        let mut shards = Vec::new();
+        for block in manifest.referenced_blocks() {
+            if !seen.insert(block.id) {
+                continue;
+            }
+            for (shard_index, shard_hash) in block.shard_hashes.iter().enumerate() {
+                shards.push(ChallengeItem {
+                    block_id: block.id,
+                    shard_index,
+                    shard_hash: shard_hash.clone(),
+                    shard_len: 0,
+                });
+            }
+        }
+        if shards.is_empty() {
+            return Err(anyhow!("Dataset holds no shards to audit"));
+        }
+
+        let mut nonce = [0u8; 32];
+        OsRng.fill_bytes(&mut nonce);
+        // Partial Fisher-Yates driven by the secret, so the sample cannot be
+        // predicted from the nonce alone.
+        let mut stream = blake3::Hasher::new_keyed(&secret.0)
+            .update(&nonce)
+            .finalize_xof();
+        let count = samples.min(shards.len());
+        for i in 0..count {
+            let mut word = [0u8; 8];
+            stream.fill(&mut word);
+            let remaining = (shards.len() - i) as u64;
+            let pick = i + (u64::from_le_bytes(word) % remaining) as usize;
+            shards.swap(i, pick);
+        }
+        shards.truncate(count);
+        for item in &mut shards {
+            item.shard_len = shard_len(item)?;
+        }
+
+        let mut challenge = Challenge {
+            epoch: manifest.epoch,
+            nonce: to_hex(&nonce),
+            items: shards,
+            tag: String::new(),
+        };
+        challenge.tag = to_hex(&challenge.compute_tag(secret)?);
+        Ok(challenge)
+    }
+
+    fn compute_tag(&self, secret: &AuditSecret) -> Result<[u8; 32]> {
+        let signed = (self.epoch, &self.nonce, &self.items);
+        let bytes = bincode::serde::encode_to_vec(signed, bincode::config::standard())?;
+        Ok(*blake3::keyed_hash(&secret.0, &bytes).as_bytes())
+    }
+
+    /// Answers the challenge from the shard files on `backend`. Needs no key:
+    /// shards that cannot be read are listed as missing instead of failing
+    /// the whole response.
+    pub fn respond(&self, backend: &dyn ShardBackend) -> Result<AuditResponse> {
+        let nonce = parse_hash(&self.nonce)?;
+        let mut proofs = Vec::new();
+        let mut missing = Vec::new();
+        for item in &self.items {
+            match Self::prove_shard(backend, &nonce, item) {
+                Ok(proof) => proofs.push(proof),
+                Err(err) => missing.push(MissingShard {
+                    block_id: item.block_id,
+                    shard_index: item.shard_index,
+                    reason: format!("{:#}", err),
+                }),
+            }
+        }
+        Ok(AuditResponse {
+            nonce: self.nonce.clone(),
+            proofs,
+            missing,
+        })
+    }
+
+    fn prove_shard(
+        backend: &dyn ShardBackend,
+        nonce: &[u8; 32],
+        item: &ChallengeItem,
+    ) -> Result<ShardProof> {
+        let name = BlockStore::shard_name(item.block_id, item.shard_index);
+        let bytes = backend
+            .get(&name)?
+            .ok_or_else(|| anyhow!("Shard file {} not found", name))?;
+        let envelope = chunk_format::decode_envelope_unauthenticated(&bytes)?;
+        if envelope.block_id != Some(item.block_id)
+            || envelope.shard_index != Some(item.shard_index)
+        {
+            return Err(anyhow!("Shard file {} holds a different shard", name));
+        }
+        let payload = envelope.payload;
+        if payload.len() as u64 != item.shard_len {
+            return Err(anyhow!(
+                "Shard file {} holds {} bytes, expected {}",
+                name,
+                payload.len(),
+                item.shard_len
+            ));
+        }
+        let chunk = challenged_chunk(nonce, item);
+        let start = chunk * CHUNK_LEN as u64;
+        let mut siblings = Vec::new();
+        collect_siblings(&payload, 0, start, &mut siblings);
+        let start = start as usize;
+        let end = (start + CHUNK_LEN).min(payload.len());
+        Ok(ShardProof {
+            block_id: item.block_id,
+            shard_index: item.shard_index,
+            shard_len: payload.len() as u64,
+            chunk: to_hex(&payload[start..end]),
+            siblings: siblings.iter().map(|cv| to_hex(cv)).collect(),
+        })
+    }
+
+    /// Checks `response` against this challenge. Fails only if the challenge
+    /// itself was not issued under `secret` or belongs to another response;
+    /// bad or missing shards are counted in the report.
+    pub fn verify(&self, secret: &AuditSecret, response: &AuditResponse) -> Result<AuditReport> {
+        let tag = parse_hash(&self.tag)?;
+        if self.compute_tag(secret)? != tag {
+            return Err(anyhow!("Challenge was not issued under this audit secret"));
+        }
+        if response.nonce != self.nonce {
+            return Err(anyhow!("Response answers a different challenge"));
+        }
+        let nonce = parse_hash(&self.nonce)?;
+        let proofs: HashMap<(usize, usize), &ShardProof> = response
+            .proofs
+            .iter()
+            .map(|proof| ((proof.block_id, proof.shard_index), proof))
+            .collect();
+
+        let mut report = AuditReport {
+            epoch: self.epoch,
+            samples: self.items.len(),
+            passed: 0,
+            failures: Vec::new(),
+        };
+        for item in &self.items {
+            let outcome = match proofs.get(&(item.block_id, item.shard_index)) {
+                Some(proof) => check_proof(&nonce, item, proof),
+                None => Err(response
+                    .missing
+                    .iter()
+                    .find(|m| m.block_id == item.block_id && m.shard_index == item.shard_index)
+                    .map_or_else(
+                        || anyhow!("No answer"),
+                        |missing| anyhow!("{}", missing.reason),
+                    )),
+            };
+            match outcome {
+                Ok(()) => report.passed += 1,
+                Err(err) => report.failures.push(MissingShard {
+                    block_id: item.block_id,
+                    shard_index: item.shard_index,
+                    reason: format!("{:#}", err),
+                }),
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// One chunk of a shard with the BLAKE3 chaining values that link it to the
+/// shard's hash.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShardProof {
+    pub block_id: usize,
+    pub shard_index: usize,
+    pub shard_len: u64,
+    /// Hex bytes of the challenged chunk.
+    pub chunk: String,
+    /// Hex chaining values of the sibling subtrees, root first.
+    pub siblings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MissingShard {
+    pub block_id: usize,
+    pub shard_index: usize,
+    pub reason: String,
+}
+
+/// What the storage side sends back for a challenge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditResponse {
+    pub nonce: String,
+    pub proofs: Vec<ShardProof>,
+    pub missing: Vec<MissingShard>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    pub epoch: u64,
+    pub samples: usize,
+    pub passed: usize,
+    pub failures: Vec<MissingShard>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Chance that an audit of this size fails if `lost_fraction` of all
+    /// shards were gone, e.g. 0.01 for one shard in a hundred.
+    pub fn detection_probability(&self, lost_fraction: f64) -> f64 {
+        1.0 - (1.0 - lost_fraction).powi(self.samples as i32)
+    }
+}
+
+/// Chunk of the shard a challenge asks for, fixed by the nonce and the shard
+/// but unknown to the storage side until the challenge arrives.
+fn challenged_chunk(nonce: &[u8; 32], item: &ChallengeItem) -> u64 {
+    let chunks = item.shard_len.div_ceil(CHUNK_LEN as u64).max(1);
+    let mut hasher = blake3::Hasher::new_keyed(nonce);
+    hasher.update(&(item.block_id as u64).to_le_bytes());
+    hasher.update(&(item.shard_index as u64).to_le_bytes());
+    let mut word = [0u8; 8];
+    hasher.finalize_xof().fill(&mut word);
+    u64::from_le_bytes(word) % chunks
+}
+
+/// Chaining values of the subtrees next to the path from the root of
+/// `data`'s BLAKE3 tree down to the chunk at byte `target`, root first.
+/// `offset` is where `data` starts in the whole input.
+fn collect_siblings(data: &[u8], offset: u64, target: u64, siblings: &mut Vec<ChainingValue>) {
+    if data.len() <= CHUNK_LEN {
+        return;
+    }
+    let left_len = left_subtree_len(data.len() as u64) as usize;
+    let (left, right) = data.split_at(left_len);
+    let right_offset = offset + left_len as u64;
+    if target < right_offset {
+        siblings.push(subtree_cv(right, right_offset));
+        collect_siblings(left, offset, target, siblings);
+    } else {
+        siblings.push(subtree_cv(left, offset));
+        collect_siblings(right, right_offset, target, siblings);
+    }
+}
+
+fn subtree_cv(data: &[u8], offset: u64) -> ChainingValue {
+    blake3::Hasher::new()
+        .set_input_offset(offset)
+        .update(data)
+        .finalize_non_root()
+}
+
+fn check_proof(nonce: &[u8; 32], item: &ChallengeItem, proof: &ShardProof) -> Result<()> {
+    let expected = parse_hash(&item.shard_hash)?;
+    let chunk = parse_bytes(&proof.chunk)?;
+    let siblings = proof
+        .siblings
+        .iter()
+        .map(|hex| parse_hash(hex))
+        .collect::<Result<Vec<_>>>()?;
+    if proof.shard_len != item.shard_len {
+        return Err(anyhow!(
+            "Proof is for a {}-byte shard, but the shard holds {} bytes",
+            proof.shard_len,
+            item.shard_len
+        ));
+    }
+    let start = challenged_chunk(nonce, item) * CHUNK_LEN as u64;
+    let expected_chunk_len = (item.shard_len - start).min(CHUNK_LEN as u64);
+    if chunk.len() as u64 != expected_chunk_len {
+        return Err(anyhow!("Answered the wrong chunk"));
+    }
+
+    let mut siblings = siblings.iter();
+    let root = if item.shard_len <= CHUNK_LEN as u64 {
+        *blake3::hash(&chunk).as_bytes()
+    } else {
+        let len = item.shard_len;
+        let left_len = left_subtree_len(len);
+        let sibling = siblings
+            .next()
+            .ok_or_else(|| anyhow!("Proof path is too short"))?;
+        let root = if start < left_len {
+            let left = climb(&chunk, left_len, 0, start, &mut siblings)?;
+            merge_subtrees_root(&left, sibling, Mode::Hash)
+        } else {
+            let right = climb(&chunk, len - left_len, left_len, start, &mut siblings)?;
+            merge_subtrees_root(sibling, &right, Mode::Hash)
+        };
+        *root.as_bytes()
+    };
+    if siblings.next().is_some() {
+        return Err(anyhow!("Proof path is too long"));
+    }
+    if root != expected {
+        return Err(anyhow!("Chunk does not match the shard hash"));
+    }
+    Ok(())
+}
+
+/// Chaining value of the `len`-byte subtree at `offset` that contains the
+/// chunk at `target`, rebuilt from the chunk and the remaining siblings.
+fn climb<'a>(
+    chunk: &[u8],
+    len: u64,
+    offset: u64,
+    target: u64,
+    siblings: &mut impl Iterator<Item = &'a ChainingValue>,
+) -> Result<ChainingValue> {
+    if len <= CHUNK_LEN as u64 {
+        return Ok(subtree_cv(chunk, offset));
+    }
+    let left_len = left_subtree_len(len);
+    let sibling = siblings
+        .next()
+        .ok_or_else(|| anyhow!("Proof path is too short"))?;
+    let right_offset = offset + left_len;
+    Ok(if target < right_offset {
+        let left = climb(chunk, left_len, offset, target, siblings)?;
+        merge_subtrees_non_root(&left, sibling, Mode::Hash)
+    } else {
+        let right = climb(chunk, len - left_len, right_offset, target, siblings)?;
+        merge_subtrees_non_root(sibling, &right, Mode::Hash)
+    })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_bytes(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(anyhow!("Invalid hex string"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("Invalid hex string")))
+        .collect()
+}
+
+fn parse_hash(hex: &str) -> Result<[u8; 32]> {
+    parse_bytes(hex)?
+        .try_into()
+        .map_err(|_| anyhow!("Expected 32 bytes of hex"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::chunk_format::{ChunkEnvelope, encode_envelope};
+    use crate::compression::Compression;
+    use crate::integrity::BlockMetadata;
+
+    const SIZES: [usize; 6] = [0, 1, 1024, 1025, 5000, 300 * 1024 + 7];
+
+    /// A backend holding one block whose shards have the lengths in `SIZES`,
+    /// and a manifest describing it.
+    fn fixture() -> (MemoryBackend, Manifest) {
+        let backend = MemoryBackend::new();
+        let mut hashes = Vec::new();
+        for (index, &size) in SIZES.iter().enumerate() {
+            let payload: Vec<u8> = (0..size).map(|i| (i * 31 + index) as u8).collect();
+            hashes.push(blake3::hash(&payload).to_hex().to_string());
+            let envelope =
+                ChunkEnvelope::data_shard(7, index, 3, 3, payload, 1, [0u8; 32], vec![1]);
+            let bytes = encode_envelope(&envelope, &[9u8; 32]).unwrap();
+            backend
+                .put(&BlockStore::shard_name(7, index), &bytes)
+                .unwrap();
+        }
+        let mut manifest = Manifest::new("f");
+        manifest.files[0].add_block(BlockMetadata {
+            id: 7,
+            original_size: 10,
+            data_shards: 3,
+            parity_shards: 3,
+            shard_hashes: hashes,
+            content_key: None,
+            compression: Compression::None,
+        });
+        (backend, manifest.with_epoch(1))
+    }
+
+    fn issue(secret: &AuditSecret, manifest: &Manifest, samples: usize) -> Challenge {
+        Challenge::new(secret, manifest, samples, |item| {
+            Ok(SIZES[item.shard_index] as u64)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_honest_storage_passes_for_every_shard_size() {
+        let (backend, manifest) = fixture();
+        let secret = AuditSecret::generate();
+        for _ in 0..4 {
+            let challenge = issue(&secret, &manifest, 100);
+            assert_eq!(challenge.items.len(), SIZES.len());
+            let response = challenge.respond(&backend).unwrap();
+            let report = challenge.verify(&secret, &response).unwrap();
+            assert!(report.is_clean(), "{:?}", report.failures);
+            assert_eq!(report.passed, SIZES.len());
+        }
+    }
+
+    #[test]
+    fn test_lost_or_altered_shards_fail_the_audit() {
+        let (backend, manifest) = fixture();
+        let secret = AuditSecret::generate();
+        let challenge = issue(&secret, &manifest, 100);
+
+        backend.delete(&BlockStore::shard_name(7, 2)).unwrap();
+        let mut response = challenge.respond(&backend).unwrap();
+        assert_eq!(response.missing.len(), 1);
+        let big = response
+            .proofs
+            .iter_mut()
+            .find(|proof| proof.shard_index == 5)
+            .unwrap();
+        big.chunk = big.chunk.replacen('0', "1", 1).replacen('a', "b", 1);
+
+        let report = challenge.verify(&secret, &response).unwrap();
+        assert_eq!(report.passed, SIZES.len() - 2);
+        let mut failures = report.failures.clone();
+        failures.sort_by_key(|failure| failure.shard_index);
+        let failed: Vec<usize> = failures.iter().map(|f| f.shard_index).collect();
+        assert_eq!(failed, vec![2, 5]);
+        assert!(failures[0].reason.contains("not found"));
+        assert!(report.detection_probability(0.01) > 0.05);
+    }
+
+    #[test]
+    fn test_challenge_is_bound_to_secret_and_response_to_nonce() {
+        let (backend, manifest) = fixture();
+        let secret = AuditSecret::generate();
+        let challenge = issue(&secret, &manifest, 2);
+        assert_eq!(challenge.items.len(), 2);
+        let response = challenge.respond(&backend).unwrap();
+
+        let mut easier = challenge.clone();
+        easier.items.pop();
+        assert!(easier.verify(&secret, &response).is_err());
+        assert!(
+            challenge
+                .verify(&AuditSecret::generate(), &response)
+                .is_err()
+        );
+
+        let other = issue(&secret, &manifest, 2);
+        assert!(other.verify(&secret, &response).is_err());
+        assert_eq!(AuditSecret::from_hex(&secret.to_hex()).unwrap().0, secret.0);
+    }
+
+    #[test]
+    fn test_proofs_for_another_shard_length_are_rejected() {
+        let (backend, manifest) = fixture();
+        let secret = AuditSecret::generate();
+        let challenge = issue(&secret, &manifest, 100);
+        let mut response = challenge.respond(&backend).unwrap();
+        let big = response
+            .proofs
+            .iter_mut()
+            .find(|proof| proof.shard_index == 5)
+            .unwrap();
+        // Same left subtree, so an honest left-half chunk would still hash
+        // to the shard's root under this length.
+        big.shard_len -= 1;
+        let report = challenge.verify(&secret, &response).unwrap();
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].reason.contains("shard holds"));
+
+        // A shorter stored shard is reported instead of proven.
+        let name = BlockStore::shard_name(7, 4);
+        let envelope = ChunkEnvelope::data_shard(7, 4, 3, 3, vec![0; 10], 1, [0u8; 32], vec![1]);
+        backend
+            .put(&name, &encode_envelope(&envelope, &[9u8; 32]).unwrap())
+            .unwrap();
+        let response = challenge.respond(&backend).unwrap();
+        assert!(response.missing[0].reason.contains("expected 5000"));
+    }
+}
@@ -0,0 +1,467 @@
+use crate::lock::{self, DatasetLock, LOCK_FILE_NAME, LockMode, LockTimeout, MemoryLock};
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Flat namespace of named objects that a dataset's shards and metadata live in.
+///
+/// Implementations must make `put` atomic: a reader sees either the previous
+/// object or the complete new one, never a partial write.
+pub trait ShardBackend: std::fmt::Debug + Send + Sync {
+    /// Stores `bytes` under `name`, replacing any existing object.
+    fn put(&self, name: &str, bytes: &[u8]) -> Result<()>;
+
+    /// Returns the object stored under `name`, or `None` if there is none.
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Removes the object stored under `name`; removing a missing object is not an error.
+    fn delete(&self, name: &str) -> Result<()>;
+
+    /// Names of every stored object, in no particular order.
+    fn list(&self) -> Result<Vec<String>>;
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.get(name)?.is_some())
+    }
+
+    /// Human-readable location used in error messages.
+    fn location(&self) -> String;
+
+    /// Number of independent failure domains objects are spread over, or
+    /// `None` when the backend does not spread them.
+    fn failure_domains(&self) -> Option<usize> {
+        None
+    }
+
+    /// Takes the dataset-wide advisory lock in `mode`, waiting up to `timeout`.
+    /// Backends without a lock primitive return a handle that excludes nobody,
+    /// leaving coordination between writers to the caller.
+    fn lock(&self, mode: LockMode, _timeout: Duration) -> Result<DatasetLock> {
+        Ok(DatasetLock::unlocked(mode))
+    }
+}
+
+/// Stores each object as a regular file directly inside one directory.
+#[derive(Debug, Clone)]
+pub struct LocalDirBackend {
+    root_path: PathBuf,
+    durability_sync: bool,
+}
+
+impl LocalDirBackend {
+    /// `durability_sync` fsyncs each file and its directory on `put`.
+    pub fn new(root_path: PathBuf, durability_sync: bool) -> Self {
+        Self {
+            root_path,
+            durability_sync,
+        }
+    }
+
+    pub fn root_path(&self) -> &Path {
+        &self.root_path
+    }
+
+    fn object_path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.contains('/') || name.contains('\\') || name == ".." {
+            return Err(anyhow!("Invalid object name '{}'", name));
+        }
+        Ok(self.root_path.join(name))
+    }
+
+    fn temp_path(&self, name: &str) -> Result<PathBuf> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| anyhow!("System time error: {}", e))?;
+        let nonce = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        let temp_name = format!(
+            ".{}.tmp.{}.{}.{}.{}",
+            name,
+            std::process::id(),
+            now.as_secs(),
+            now.subsec_nanos(),
+            nonce
+        );
+        Ok(self.root_path.join(temp_name))
+    }
+
+    fn sync_root_dir(&self) -> Result<()> {
+        let dir = OpenOptions::new().read(true).open(&self.root_path)?;
+        dir.sync_all()?;
+        Ok(())
+    }
+}
+
+impl ShardBackend for LocalDirBackend {
+    fn put(&self, name: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.object_path(name)?;
+        let temp_path = self.temp_path(name)?;
+
+        let result = (|| -> Result<()> {
+            let mut file = OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(&temp_path)?;
+            file.write_all(bytes)?;
+            if self.durability_sync {
+                file.sync_data()?;
+            }
+            drop(file);
+
+            fs::rename(&temp_path, &path)?;
+
+            if self.durability_sync {
+                self.sync_root_dir()?;
+            }
+            Ok(())
+        })();
+
+        let _ = fs::remove_file(&temp_path);
+        result
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.object_path(name)?) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.object_path(name)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.root_path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && entry.file_name() != LOCK_FILE_NAME {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.object_path(name)?.exists())
+    }
+
+    fn location(&self) -> String {
+        self.root_path.display().to_string()
+    }
+
+    fn lock(&self, mode: LockMode, timeout: Duration) -> Result<DatasetLock> {
+        lock::lock_file(&self.root_path.join(LOCK_FILE_NAME), mode, timeout)
+    }
+}
+
+/// Keeps every object in memory. Clones share the same objects, so a store can
+/// be dropped and reopened against a clone of the backend.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    objects: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    lock: Arc<MemoryLock>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn objects(&self) -> Result<std::sync::MutexGuard<'_, BTreeMap<String, Vec<u8>>>> {
+        self.objects
+            .lock()
+            .map_err(|_| anyhow!("Memory backend lock poisoned"))
+    }
+}
+
+impl ShardBackend for MemoryBackend {
+    fn put(&self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.objects()?.insert(name.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.objects()?.get(name).cloned())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        self.objects()?.remove(name);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(self.objects()?.keys().cloned().collect())
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.objects()?.contains_key(name))
+    }
+
+    fn location(&self) -> String {
+        "<memory>".to_string()
+    }
+
+    fn lock(&self, mode: LockMode, timeout: Duration) -> Result<DatasetLock> {
+        self.lock.acquire(mode, timeout)
+    }
+}
+
+/// Spreads objects over several backends acting as independent failure domains.
+///
+/// Shard `i` of every block is placed on domain `i % n`, and metadata fallback
+/// copy `k` on domain `k % n`, so losing one domain costs each block at most
+/// `ceil((data + parity) / n)` shards. Reads and listings consult every domain
+/// and tolerate domains that have become unavailable.
+#[derive(Debug, Clone)]
+pub struct StripedBackend {
+    domains: Vec<Arc<dyn ShardBackend>>,
+}
+
+impl StripedBackend {
+    pub fn new(domains: Vec<Arc<dyn ShardBackend>>) -> Result<Self> {
+        if domains.is_empty() {
+            return Err(anyhow!("Striped backend needs at least one domain"));
+        }
+        Ok(Self { domains })
+    }
+
+    /// One `LocalDirBackend` per directory, in the given order.
+    pub fn local(roots: &[PathBuf], durability_sync: bool) -> Result<Self> {
+        Self::new(
+            roots
+                .iter()
+                .map(|root| {
+                    Arc::new(LocalDirBackend::new(root.clone(), durability_sync))
+                        as Arc<dyn ShardBackend>
+                })
+                .collect(),
+        )
+    }
+
+    pub fn domain_count(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Index of the domain that `name` is written to.
+    pub fn domain_for(&self, name: &str) -> usize {
+        Self::placement_index(name).map_or(0, |index| index % self.domains.len())
+    }
+
+    /// Trailing numeric component of `block_{id}_{i}.bin` / `meta_{epoch}_{k}.bin`.
+    fn placement_index(name: &str) -> Option<usize> {
+        let stem = name.strip_suffix(".bin")?;
+        if !stem.starts_with("block_") && !stem.starts_with("meta_") {
+            return None;
+        }
+        stem.rsplit('_').next()?.parse().ok()
+    }
+
+    /// Domains in lookup order: the placement domain first, then the rest.
+    fn lookup_order(&self, name: &str) -> impl Iterator<Item = &Arc<dyn ShardBackend>> {
+        let primary = self.domain_for(name);
+        std::iter::once(&self.domains[primary]).chain(
+            self.domains
+                .iter()
+                .enumerate()
+                .filter(move |(i, _)| *i != primary)
+                .map(|(_, domain)| domain),
+        )
+    }
+}
+
+impl ShardBackend for StripedBackend {
+    fn put(&self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.domains[self.domain_for(name)].put(name, bytes)
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let mut last_err = None;
+        for domain in self.lookup_order(name) {
+            match domain.get(name) {
+                Ok(Some(bytes)) => return Ok(Some(bytes)),
+                Ok(None) => {}
+                Err(err) => last_err = Some(err),
+            }
+        }
+        match last_err {
+            Some(err) => Err(err),
+            None => Ok(None),
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        let mut first_err = None;
+        for domain in &self.domains {
+            if let Err(err) = domain.delete(name) {
+                first_err.get_or_insert(err);
+            }
+        }
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut names = std::collections::BTreeSet::new();
+        let mut reachable = 0usize;
+        for domain in &self.domains {
+            if let Ok(domain_names) = domain.list() {
+                reachable += 1;
+                names.extend(domain_names);
+            }
+        }
+        if reachable == 0 {
+            return Err(anyhow!(
+                "No storage domain of {} is reachable",
+                self.location()
+            ));
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self
+            .lookup_order(name)
+            .any(|domain| domain.exists(name).unwrap_or(false)))
+    }
+
+    fn location(&self) -> String {
+        let locations: Vec<String> = self.domains.iter().map(|d| d.location()).collect();
+        format!("striped[{}]", locations.join(", "))
+    }
+
+    fn failure_domains(&self) -> Option<usize> {
+        Some(self.domains.len())
+    }
+
+    /// Locks every reachable domain in order, so writers sharing the same
+    /// roots always contend on the first one they have in common. Domains
+    /// whose lock file cannot be opened are skipped like unreachable reads.
+    fn lock(&self, mode: LockMode, timeout: Duration) -> Result<DatasetLock> {
+        let deadline = Instant::now() + timeout;
+        let mut parts = Vec::with_capacity(self.domains.len());
+        let mut last_err = None;
+        for domain in &self.domains {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match domain.lock(mode, remaining) {
+                Ok(part) => parts.push(part),
+                Err(err) if err.is::<LockTimeout>() => return Err(err),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        if parts.is_empty() {
+            return Err(last_err.unwrap_or_else(|| {
+                anyhow!("No storage domain of {} could be locked", self.location())
+            }));
+        }
+        Ok(DatasetLock::combine(mode, parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn exercise(backend: &dyn ShardBackend) {
+        assert_eq!(backend.get("a.bin").unwrap(), None);
+        backend.put("a.bin", b"one").unwrap();
+        backend.put("a.bin", b"two").unwrap();
+        backend.put("b.bin", b"three").unwrap();
+        assert_eq!(backend.get("a.bin").unwrap(), Some(b"two".to_vec()));
+        assert!(backend.exists("b.bin").unwrap());
+
+        let mut names = backend.list().unwrap();
+        names.sort();
+        assert_eq!(names, vec!["a.bin", "b.bin"]);
+
+        backend.delete("a.bin").unwrap();
+        backend.delete("a.bin").unwrap();
+        assert!(!backend.exists("a.bin").unwrap());
+        assert_eq!(backend.list().unwrap(), vec!["b.bin"]);
+    }
+
+    #[test]
+    fn test_local_dir_backend_round_trip() {
+        let dir = tempdir().expect("tempdir");
+        exercise(&LocalDirBackend::new(dir.path().to_path_buf(), true));
+    }
+
+    #[test]
+    fn test_memory_backend_round_trip() {
+        exercise(&MemoryBackend::new());
+    }
+
+    #[test]
+    fn test_local_dir_backend_rejects_path_names() {
+        let dir = tempdir().expect("tempdir");
+        let backend = LocalDirBackend::new(dir.path().to_path_buf(), false);
+        assert!(backend.put("../escape.bin", b"x").is_err());
+        assert!(backend.get("nested/a.bin").is_err());
+    }
+
+    #[test]
+    fn test_local_dir_backend_lists_missing_dir_as_empty() {
+        let dir = tempdir().expect("tempdir");
+        let backend = LocalDirBackend::new(dir.path().join("absent"), false);
+        assert!(backend.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_striped_backend_places_shards_by_index() {
+        let domains: Vec<MemoryBackend> = (0..3).map(|_| MemoryBackend::new()).collect();
+        let striped = StripedBackend::new(
+            domains
+                .iter()
+                .map(|d| Arc::new(d.clone()) as Arc<dyn ShardBackend>)
+                .collect(),
+        )
+        .unwrap();
+
+        for i in 0..6 {
+            striped
+                .put(&format!("block_7_{}.bin", i), b"shard")
+                .unwrap();
+        }
+        striped.put("meta_4_2.bin", b"meta").unwrap();
+        striped.put(".marker", b"x").unwrap();
+
+        assert_eq!(
+            domains[1].list().unwrap(),
+            vec!["block_7_1.bin", "block_7_4.bin"]
+        );
+        assert!(domains[2].exists("meta_4_2.bin").unwrap());
+        assert!(domains[0].exists(".marker").unwrap());
+        assert_eq!(striped.list().unwrap().len(), 8);
+
+        domains[1].delete("block_7_1.bin").unwrap();
+        domains[0].put("block_7_1.bin", b"moved").unwrap();
+        assert_eq!(
+            striped.get("block_7_1.bin").unwrap(),
+            Some(b"moved".to_vec())
+        );
+
+        striped.delete("block_7_1.bin").unwrap();
+        assert!(!striped.exists("block_7_1.bin").unwrap());
+    }
+}
@@ -1,29 +1,182 @@
 use crate::aont;
+use crate::archive::{self, ExportReport};
+use crate::audit::{AuditSecret, Challenge};
+use crate::backend::{LocalDirBackend, ShardBackend};
 use crate::chunk_format::{self, ChunkEnvelope, ChunkKind};
+use crate::chunker::{ChunkReader, Chunking};
+use crate::compaction::{AutoCompaction, CompactReport, CompactionPolicy};
+use crate::compression::{self, CompressionPolicy};
+use crate::dataset_reader::DatasetReader;
 use crate::erasure;
-use crate::integrity::{BlockMetadata, Manifest};
+use crate::integrity::{BlockMetadata, DatasetSettings, FileEntry, Manifest, Redundancy, Snapshot};
 use crate::io_guard::{self, IoOptions};
 use crate::key_material::{DerivedKeys, RootKey};
+use crate::lock::{DatasetLock, LockMode};
 use crate::manifest_recovery;
+use crate::merkle::RangeProof;
+use crate::parallel;
+use crate::scrub::{
+    BlockHealth, BlockRepairReport, BlockScrubReport, RepairOutcome, RepairReport, ScrubReport,
+    ShardStatus, UnrecoverableBlock,
+};
+use crate::transaction::Transaction;
 use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 const METADATA_COPY_TARGET: usize = 3;
+/// Small hint object naming the last committed epoch, so writers can check
+/// for concurrent commits without scanning every shard.
+const HEAD_OBJECT: &str = "head.txt";
+/// Blocks a restripe re-encodes per commit.
+const RESTRIPE_STEP_BLOCKS: usize = 64;
 const TEST_MANIFEST_FAIL_MARKER: &str = ".ironclad_fail_manifest_commit";
 
 #[derive(Debug, Clone)]
-struct PendingBlock {
-    metadata: BlockMetadata,
+pub(crate) struct PendingBlock {
+    pub(crate) metadata: BlockMetadata,
     shards: Vec<Vec<u8>>,
 }
 
+/// Hands out block ids for one operation and, when the dataset deduplicates,
+/// indexes every block seen so far by content so repeated chunks are reused.
+#[derive(Debug)]
+pub(crate) struct BlockBuilder {
+    next_id: usize,
+    dedup_index: Option<HashMap<DedupKey, BlockMetadata>>,
+}
+
+/// Content key plus shard policy; a chunk only reuses a block with both equal.
+type DedupKey = (String, usize, usize);
+
+/// Plaintext for one block together with the shard policy to encode it with.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChunkJob<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) data_shards: usize,
+    pub(crate) parity_shards: usize,
+}
+
+/// How `build_blocks` resolved one chunk before any encoding runs.
+enum BlockPlan {
+    Reuse(BlockMetadata),
+    Encode {
+        id: usize,
+        content_key: Option<String>,
+    },
+    /// Same content as an earlier chunk of the batch that is being encoded.
+    SameAs(usize),
+}
+
+/// Shard payloads of one block together with how each one fared on load.
+#[derive(Debug)]
+struct LoadedShards {
+    statuses: Vec<ShardStatus>,
+    payloads: Vec<Option<Vec<u8>>>,
+}
+
+/// Outcome of a restripe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestripeReport {
+    /// Distinct blocks re-encoded to the new layout.
+    pub blocks_restriped: usize,
+    /// Selected blocks that already had the new layout.
+    pub blocks_skipped: usize,
+    /// Plaintext bytes covered by the re-encoded blocks.
+    pub bytes: u64,
+    /// Epochs committed, one per step.
+    pub steps: usize,
+}
+
+/// Work done through one store handle since it was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct IoStats {
+    /// Blocks reconstructed and decrypted.
+    pub blocks_read: u64,
+    /// Blocks newly encoded and persisted.
+    pub blocks_written: u64,
+    /// Shard objects written, including read-repair and repair rewrites.
+    pub shards_written: u64,
+    /// Blocks that had to be decoded around missing or corrupt shards.
+    pub degraded_blocks_read: u64,
+    pub read_repaired_shards: u64,
+}
+
+impl IoStats {
+    /// Blocks decoded plus blocks encoded.
+    pub fn blocks_touched(&self) -> u64 {
+        self.blocks_read + self.blocks_written
+    }
+}
+
+#[derive(Debug, Default)]
+struct IoCounters {
+    blocks_read: AtomicU64,
+    blocks_written: AtomicU64,
+    shards_written: AtomicU64,
+    degraded_blocks_read: AtomicU64,
+    read_repaired_shards: AtomicU64,
+}
+
+impl IoCounters {
+    fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> IoStats {
+        IoStats {
+            blocks_read: self.blocks_read.load(Ordering::Relaxed),
+            blocks_written: self.blocks_written.load(Ordering::Relaxed),
+            shards_written: self.shards_written.load(Ordering::Relaxed),
+            degraded_blocks_read: self.degraded_blocks_read.load(Ordering::Relaxed),
+            read_repaired_shards: self.read_repaired_shards.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The dataset lock as held by one store, shared by nested operations.
+#[derive(Debug, Default)]
+struct LockState {
+    held: Option<DatasetLock>,
+    depth: usize,
+}
+
+/// Keeps the store's dataset lock held; the last guard dropped releases it.
+#[derive(Debug)]
+pub(crate) struct StoreLock {
+    state: Arc<Mutex<LockState>>,
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.depth = state.depth.saturating_sub(1);
+            if state.depth == 0 {
+                state.held = None;
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BlockStore {
-    root_path: PathBuf,
+    backend: Arc<dyn ShardBackend>,
     pub manifest: Manifest,
+    active_file: Option<String>,
     io_options: IoOptions,
     derived_keys: DerivedKeys,
+    counters: IoCounters,
+    lock_state: Arc<Mutex<LockState>>,
+    /// Exclusive lock held between `lock_exclusive` and `unlock`.
+    session_lock: Option<StoreLock>,
+    stale_lock: Option<String>,
 }
 
 impl BlockStore {
@@ -39,15 +192,92 @@ impl BlockStore {
         file_name: &str,
         root_key: [u8; 32],
         io_options: IoOptions,
+    ) -> Result<Self> {
+        let mut store = Self::create_empty_with_options(root_path, root_key, io_options)?;
+        store.manifest = Manifest::new(file_name);
+        store.active_file = Some(file_name.to_string());
+        Ok(store)
+    }
+
+    /// Creates a fresh dataset store whose namespace holds no files yet.
+    /// Existing managed files for this dataset are removed.
+    pub fn create_empty_with_options(
+        root_path: PathBuf,
+        root_key: [u8; 32],
+        io_options: IoOptions,
     ) -> Result<Self> {
         fs::create_dir_all(&root_path)?;
-        Self::cleanup_managed_files(&root_path)?;
-        Ok(BlockStore {
-            root_path,
-            manifest: Manifest::new(file_name),
+        Self::create_empty_with_backend(
+            Self::local_backend(root_path, io_options),
+            root_key,
+            io_options,
+        )
+    }
+
+    /// Creates a fresh single-file dataset on an arbitrary backend.
+    /// Existing managed objects in the backend are removed.
+    pub fn create_with_backend(
+        backend: Arc<dyn ShardBackend>,
+        file_name: &str,
+        root_key: [u8; 32],
+        io_options: IoOptions,
+    ) -> Result<Self> {
+        let mut store = Self::create_empty_with_backend(backend, root_key, io_options)?;
+        store.manifest = Manifest::new(file_name);
+        store.active_file = Some(file_name.to_string());
+        Ok(store)
+    }
+
+    /// Creates a fresh dataset with no files on an arbitrary backend.
+    pub fn create_empty_with_backend(
+        backend: Arc<dyn ShardBackend>,
+        root_key: [u8; 32],
+        io_options: IoOptions,
+    ) -> Result<Self> {
+        let store = BlockStore {
+            backend,
+            manifest: Manifest::empty(),
+            active_file: None,
             io_options,
             derived_keys: RootKey(root_key).derive(),
-        })
+            counters: IoCounters::default(),
+            lock_state: Arc::default(),
+            session_lock: None,
+            stale_lock: None,
+        };
+        // Creation replaces whatever was there, so there is no epoch to check.
+        let lock = store.acquire_lock(LockMode::Exclusive, false)?;
+        Self::cleanup_managed_files(store.backend.as_ref())?;
+        drop(lock);
+        Ok(store)
+    }
+
+    /// Opens the dataset, or creates an empty one if the path holds no managed
+    /// files yet. A dataset whose files cannot be authenticated is never wiped.
+    pub fn open_or_create_with_options(
+        root_path: PathBuf,
+        root_key: [u8; 32],
+        io_options: IoOptions,
+    ) -> Result<Self> {
+        fs::create_dir_all(&root_path)?;
+        Self::open_or_create_with_backend(
+            Self::local_backend(root_path, io_options),
+            root_key,
+            io_options,
+        )
+    }
+
+    /// Backend counterpart of `open_or_create_with_options`.
+    pub fn open_or_create_with_backend(
+        backend: Arc<dyn ShardBackend>,
+        root_key: [u8; 32],
+        io_options: IoOptions,
+    ) -> Result<Self> {
+        if Self::has_managed_files(backend.as_ref())? {
+            Self::open_with_backend(backend, root_key, io_options)
+        } else {
+            Self::create_empty_with_backend(backend, root_key, io_options)
+        }
     }
 
     /// Opens an existing dataset store.
@@ -67,56 +297,288 @@ impl BlockStore {
                 root_path.display()
             ));
         }
+        Self::open_with_backend(
+            Self::local_backend(root_path, io_options),
+            root_key,
+            io_options,
+        )
+    }
 
+    /// Opens an existing dataset stored on an arbitrary backend.
+    pub fn open_with_backend(
+        backend: Arc<dyn ShardBackend>,
+        root_key: [u8; 32],
+        io_options: IoOptions,
+    ) -> Result<Self> {
         let derived_keys = RootKey(root_key).derive();
-        let manifest =
-            manifest_recovery::load_manifest_from_chunks(&root_path, &derived_keys.meta_mac_key)
-                .map_err(|err| {
-                    anyhow!(
-                        "Dataset is not initialized: {} ({})",
-                        root_path.display(),
-                        err
-                    )
-                })?;
-
+        let lock = backend.lock(LockMode::Shared, io_options.lock_timeout)?;
+        let manifest = manifest_recovery::load_manifest_from_chunks(
+            backend.as_ref(),
+            &derived_keys.meta_mac_key,
+        )
+        .map_err(|err| {
+            anyhow!(
+                "Dataset is not initialized: {} ({})",
+                backend.location(),
+                err
+            )
+        })?;
+        drop(lock);
+
+        let active_file = manifest.files.first().map(|file| file.name.clone());
         Ok(BlockStore {
-            root_path,
+            backend,
             manifest,
+            active_file,
             io_options,
             derived_keys,
+            counters: IoCounters::default(),
+            lock_state: Arc::default(),
+            session_lock: None,
+            stale_lock: None,
         })
     }
 
-    pub fn save_manifest(&self) -> Result<()> {
-        self.persist_manifest_artifacts(&self.manifest, &[])
+    fn local_backend(root_path: PathBuf, io_options: IoOptions) -> Arc<dyn ShardBackend> {
+        Arc::new(LocalDirBackend::new(root_path, io_options.durability_sync))
     }
 
-    fn cleanup_managed_files(root_path: &Path) -> Result<()> {
-        if !root_path.exists() {
+    /// The backend holding this dataset's shards and metadata copies.
+    pub fn backend(&self) -> &Arc<dyn ShardBackend> {
+        &self.backend
+    }
+
+    /// Name of the file that offset-based operations act on.
+    pub fn active_file_name(&self) -> Option<&str> {
+        self.active_file.as_deref()
+    }
+
+    /// Selects the file that offset-based operations act on.
+    pub fn select_file(&mut self, name: &str) -> Result<()> {
+        if self.manifest.file(name).is_none() {
+            return Err(anyhow!("File '{}' not found in dataset", name));
+        }
+        self.active_file = Some(name.to_string());
+        Ok(())
+    }
+
+    /// The selected file, if any.
+    pub fn file(&self) -> Option<&FileEntry> {
+        self.active_file
+            .as_deref()
+            .and_then(|name| self.manifest.file(name))
+    }
+
+    /// Size of the selected file; zero when no file is selected.
+    pub fn total_size(&self) -> u64 {
+        self.file().map_or(0, |file| file.total_size)
+    }
+
+    /// Blocks of the selected file; empty when no file is selected.
+    pub fn blocks(&self) -> &[BlockMetadata] {
+        self.file().map_or(&[], |file| file.blocks.as_slice())
+    }
+
+    /// Worker threads this store may use for block encoding and decoding.
+    pub(crate) fn parallelism(&self) -> usize {
+        self.io_options.parallelism
+    }
+
+    /// Number of shards rewritten by read-repair since this store was opened.
+    pub fn read_repaired_shards(&self) -> u64 {
+        self.counters.read_repaired_shards.load(Ordering::Relaxed)
+    }
+
+    /// Counters for the work this store has done since it was opened.
+    pub fn io_stats(&self) -> IoStats {
+        self.counters.snapshot()
+    }
+
+    /// Holds the exclusive dataset lock until `unlock` or drop, so a sequence
+    /// of operations sees no other writer. If another writer committed since
+    /// the manifest was loaded, it is reloaded first.
+    pub fn lock_exclusive(&mut self) -> Result<()> {
+        if self.session_lock.is_some() {
             return Ok(());
         }
+        let lock = self.acquire_lock(LockMode::Exclusive, false)?;
+        self.stale_lock = self.with_held_lock(|held| held.stale_holder().map(str::to_string))?;
+        match self.committed_epoch()? {
+            Some(epoch) if epoch == self.manifest.epoch => {}
+            None if self.manifest.epoch == 0 => {}
+            _ => self.reload_manifest()?,
+        }
+        self.session_lock = Some(lock);
+        Ok(())
+    }
 
-        for entry in fs::read_dir(root_path)? {
-            let entry = entry?;
-            let file_type = entry.file_type()?;
-            if !file_type.is_file() {
-                continue;
+    /// Holder record of a stale lock that `lock_exclusive` had to reclaim:
+    /// a writer died while holding it, possibly mid-commit.
+    pub fn reclaimed_stale_lock(&self) -> Option<&str> {
+        self.stale_lock.as_deref()
+    }
+
+    /// Releases the lock taken by `lock_exclusive`.
+    pub fn unlock(&mut self) {
+        self.session_lock = None;
+    }
+
+    pub(crate) fn read_lock(&self) -> Result<StoreLock> {
+        self.acquire_lock(LockMode::Shared, false)
+    }
+
+    /// Exclusive lock for a mutation. Fails if another writer committed since
+    /// this store loaded its manifest, because building on that manifest would
+    /// fork the dataset into two quorums at the same epoch.
+    pub(crate) fn write_lock(&self) -> Result<StoreLock> {
+        self.acquire_lock(LockMode::Exclusive, true)
+    }
+
+    fn acquire_lock(&self, mode: LockMode, check_epoch: bool) -> Result<StoreLock> {
+        let mut state = self
+            .lock_state
+            .lock()
+            .map_err(|_| anyhow!("Dataset lock state poisoned"))?;
+        if let Some(held) = &state.held {
+            if mode == LockMode::Exclusive && held.mode() == LockMode::Shared {
+                return Err(anyhow!(
+                    "Cannot modify the dataset while this store is reading it; drop open readers first"
+                ));
             }
+            state.depth += 1;
+        } else {
+            let held = self.backend.lock(mode, self.io_options.lock_timeout)?;
+            if mode == LockMode::Exclusive && check_epoch {
+                self.ensure_current_epoch()?;
+            }
+            state.held = Some(held);
+            state.depth = 1;
+        }
+        Ok(StoreLock {
+            state: Arc::clone(&self.lock_state),
+        })
+    }
+
+    fn with_held_lock<T>(&self, f: impl FnOnce(&DatasetLock) -> T) -> Result<T> {
+        let state = self
+            .lock_state
+            .lock()
+            .map_err(|_| anyhow!("Dataset lock state poisoned"))?;
+        let held = state
+            .held
+            .as_ref()
+            .ok_or_else(|| anyhow!("Dataset lock is not held"))?;
+        Ok(f(held))
+    }
+
+    fn ensure_current_epoch(&self) -> Result<()> {
+        match self.committed_epoch()? {
+            Some(epoch) if epoch == self.manifest.epoch => Ok(()),
+            None if self.manifest.epoch == 0 => Ok(()),
+            committed => Err(anyhow!(
+                "Dataset {} was modified by another writer (loaded at epoch {}, now at {}); reopen it and retry",
+                self.backend.location(),
+                self.manifest.epoch,
+                committed.map_or_else(|| "no committed epoch".to_string(), |e| e.to_string())
+            )),
+        }
+    }
+
+    /// Epoch of the latest committed manifest, or `None` if nothing has been
+    /// committed. The head is trusted only when it matches this store's
+    /// epoch, which is safe because every commit advances it before writing
+    /// any manifest copy; anything else is settled by a full discovery.
+    fn committed_epoch(&self) -> Result<Option<u64>> {
+        let head = self
+            .backend
+            .get(HEAD_OBJECT)
+            .ok()
+            .flatten()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .and_then(|text| text.trim().parse::<u64>().ok());
+        if head == Some(self.manifest.epoch) {
+            return Ok(head);
+        }
+        match manifest_recovery::load_manifest_from_chunks(
+            self.backend.as_ref(),
+            &self.derived_keys.meta_mac_key,
+        ) {
+            Ok(manifest) => Ok(Some(manifest.epoch)),
+            Err(_) if !Self::has_managed_files(self.backend.as_ref())? => Ok(None),
+            Err(err) => Err(anyhow!(
+                "Cannot confirm the committed state of {}: {}",
+                self.backend.location(),
+                err
+            )),
+        }
+    }
+
+    /// Replaces the manifest with the latest committed one, keeping the
+    /// selected file if it still exists.
+    fn reload_manifest(&mut self) -> Result<()> {
+        let manifest = manifest_recovery::load_manifest_from_chunks(
+            self.backend.as_ref(),
+            &self.derived_keys.meta_mac_key,
+        )?;
+        if self
+            .active_file
+            .as_deref()
+            .is_none_or(|name| manifest.file(name).is_none())
+        {
+            self.active_file = manifest.files.first().map(|file| file.name.clone());
+        }
+        self.manifest = manifest;
+        Ok(())
+    }
+
+    pub fn save_manifest(&self) -> Result<()> {
+        let _lock = self.write_lock()?;
+        let mut manifest = self.manifest.clone();
+        manifest.update_merkle_root();
+        self.persist_manifest_artifacts(&manifest, &[])
+    }
+
+    /// Whether the backend already holds a dataset's shards or metadata.
+    pub fn is_initialized(backend: &dyn ShardBackend) -> Result<bool> {
+        Self::has_managed_files(backend)
+    }
 
-            let name = entry.file_name();
-            let name = name.to_string_lossy();
+    /// Deletes every shard and metadata object of the dataset on `backend`,
+    /// leaving unrelated objects alone. Returns how many objects were removed.
+    /// Holds the exclusive dataset lock throughout, so no reader or writer
+    /// has shards deleted under it.
+    pub fn destroy_with_backend(
+        backend: &dyn ShardBackend,
+        lock_timeout: Duration,
+    ) -> Result<usize> {
+        let _lock = backend.lock(LockMode::Exclusive, lock_timeout)?;
+        Self::cleanup_managed_files(backend)
+    }
+
+    fn has_managed_files(backend: &dyn ShardBackend) -> Result<bool> {
+        Ok(backend
+            .list()?
+            .iter()
+            .any(|name| Self::is_managed_file(name)))
+    }
+
+    fn cleanup_managed_files(backend: &dyn ShardBackend) -> Result<usize> {
+        let mut removed = 0;
+        for name in backend.list()? {
             if Self::is_managed_file(&name) {
-                fs::remove_file(entry.path())?;
+                backend.delete(&name)?;
+                removed += 1;
             }
         }
-        Ok(())
+        Ok(removed)
     }
 
-    fn is_managed_file(name: &str) -> bool {
+    pub(crate) fn is_managed_file(name: &str) -> bool {
         (name.starts_with("manifest_") && name.ends_with(".json"))
             || (name.starts_with("block_") && name.ends_with(".bin"))
             || (name.starts_with("meta_") && name.ends_with(".bin"))
             || (name.starts_with("shard_") && name.ends_with(".dat"))
+            || name == HEAD_OBJECT
     }
 
     fn delete_block_files(&self, block: &BlockMetadata) -> Result<()> {
@@ -129,10 +591,7 @@ impl BlockStore {
 
     fn delete_block_files_by_id(&self, block_id: usize, total_shards: usize) -> Result<()> {
         for i in 0..total_shards {
-            let path = self.root_path.join(format!("block_{}_{}.bin", block_id, i));
-            if path.exists() {
-                fs::remove_file(path)?;
-            }
+            self.backend.delete(&Self::shard_name(block_id, i))?;
         }
         Ok(())
     }
@@ -142,32 +601,31 @@ impl BlockStore {
     }
 
     fn cleanup_old_meta_files_best_effort(&self, current_epoch: u64) {
-        let entries = match fs::read_dir(&self.root_path) {
-            Ok(entries) => entries,
-            Err(_) => return,
+        let Ok(names) = self.backend.list() else {
+            return;
         };
 
-        for entry in entries.flatten() {
-            let file_type = match entry.file_type() {
-                Ok(file_type) => file_type,
-                Err(_) => continue,
-            };
-            if !file_type.is_file() {
-                continue;
-            }
-
-            let name = entry.file_name();
-            let name = name.to_string_lossy();
+        for name in names {
             let Some(epoch) = Self::parse_meta_epoch(&name) else {
                 continue;
             };
             if epoch < current_epoch {
-                let _ = fs::remove_file(entry.path());
+                let _ = self.backend.delete(&name);
             }
         }
     }
 
-    fn parse_meta_epoch(name: &str) -> Option<u64> {
+    fn delete_objects_best_effort(&self, names: Vec<String>) {
+        for name in names {
+            let _ = self.backend.delete(&name);
+        }
+    }
+
+    pub(crate) fn shard_name(block_id: usize, index: usize) -> String {
+        format!("block_{}_{}.bin", block_id, index)
+    }
+
+    pub(crate) fn parse_meta_epoch(name: &str) -> Option<u64> {
         if !name.starts_with("meta_") || !name.ends_with(".bin") {
             return None;
         }
@@ -191,14 +649,18 @@ impl BlockStore {
         parity_shards: usize,
     ) -> Result<PendingBlock> {
         Self::validate_shard_config(data_shards, parity_shards)?;
+        self.check_failure_domains(data_shards, parity_shards)?;
 
-        // 1. AONT Encrypt
-        let package = aont::encrypt(data, &self.derived_keys.aont_mask_key)?;
+        // 1. Compress according to the dataset policy
+        let (stored, compression) = self.manifest.settings.compression.apply(data)?;
 
-        // 2. Erasure Encode
+        // 2. AONT Encrypt
+        let package = aont::encrypt(&stored, &self.derived_keys.aont_mask_key)?;
+
+        // 3. Erasure Encode
         let shards = erasure::encode(&package, data_shards, parity_shards)?;
 
-        // 3. Calculate Hashes for shard payloads
+        // 4. Calculate Hashes for shard payloads
         let shard_hashes = shards
             .iter()
             .map(|shard| blake3::hash(shard).to_hex().to_string())
@@ -211,13 +673,157 @@ impl BlockStore {
                 data_shards,
                 parity_shards,
                 shard_hashes,
+                content_key: None,
+                compression,
             },
             shards,
         })
     }
 
-    /// Reads and reconstructs a block.
-    fn read_block(&self, block: &BlockMetadata) -> Result<Vec<u8>> {
+    /// Starts allocating blocks for one operation on top of the committed manifest.
+    pub(crate) fn block_builder(&self) -> Result<BlockBuilder> {
+        let dedup_index = self.manifest.settings.dedup.then(|| {
+            self.manifest
+                .referenced_blocks()
+                .filter_map(|block| {
+                    let key = block.content_key.clone()?;
+                    Some(((key, block.data_shards, block.parity_shards), block.clone()))
+                })
+                .collect()
+        });
+        Ok(BlockBuilder {
+            next_id: self.next_available_id()?,
+            dedup_index,
+        })
+    }
+
+    /// Turns chunks into blocks, in order. Dedup lookups and id allocation run
+    /// sequentially so the result is deterministic; encoding of the chunks that
+    /// need new blocks is spread over `io_options.parallelism` workers. Reused
+    /// blocks come back without a pending block; new ones must be persisted by
+    /// the caller.
+    pub(crate) fn build_blocks(
+        &self,
+        builder: &mut BlockBuilder,
+        jobs: &[ChunkJob<'_>],
+    ) -> Result<Vec<(BlockMetadata, Option<PendingBlock>)>> {
+        let workers = self.io_options.parallelism;
+        let content_keys: Vec<Option<String>> = match builder.dedup_index {
+            Some(_) => parallel::map_ordered(jobs, workers, |job| {
+                Some(Self::content_key(&self.derived_keys.dedup_key, job.data))
+            }),
+            None => vec![None; jobs.len()],
+        };
+
+        let mut plans = Vec::with_capacity(jobs.len());
+        let mut batch: HashMap<DedupKey, usize> = HashMap::new();
+        for (i, (job, content_key)) in jobs.iter().zip(content_keys).enumerate() {
+            if let (Some(index), Some(content_key)) = (&builder.dedup_index, &content_key) {
+                let key = (content_key.clone(), job.data_shards, job.parity_shards);
+                if let Some(existing) = index.get(&key)
+                    && existing.original_size == job.data.len() as u64
+                {
+                    plans.push(BlockPlan::Reuse(existing.clone()));
+                    continue;
+                }
+                if let Some(&first) = batch.get(&key) {
+                    plans.push(BlockPlan::SameAs(first));
+                    continue;
+                }
+                batch.insert(key, i);
+            }
+            plans.push(BlockPlan::Encode {
+                id: Self::take_next_id(&mut builder.next_id)?,
+                content_key,
+            });
+        }
+
+        let to_encode: Vec<(usize, usize)> = plans
+            .iter()
+            .enumerate()
+            .filter_map(|(i, plan)| match plan {
+                BlockPlan::Encode { id, .. } => Some((i, *id)),
+                _ => None,
+            })
+            .collect();
+        let mut encoded: HashMap<usize, PendingBlock> = HashMap::new();
+        for ((i, _), pending) in
+            to_encode
+                .iter()
+                .zip(parallel::map_ordered(&to_encode, workers, |&(i, id)| {
+                    let job = jobs[i];
+                    self.create_block(job.data, id, job.data_shards, job.parity_shards)
+                }))
+        {
+            encoded.insert(*i, pending?);
+        }
+
+        let mut built: Vec<(BlockMetadata, Option<PendingBlock>)> = Vec::with_capacity(jobs.len());
+        for (i, plan) in plans.into_iter().enumerate() {
+            match plan {
+                BlockPlan::Reuse(existing) => built.push((existing, None)),
+                BlockPlan::SameAs(first) => built.push((built[first].0.clone(), None)),
+                BlockPlan::Encode { content_key, .. } => {
+                    let mut pending = encoded
+                        .remove(&i)
+                        .ok_or_else(|| anyhow!("Encoded block for chunk {} is missing", i))?;
+                    if let (Some(index), Some(content_key)) =
+                        (builder.dedup_index.as_mut(), content_key)
+                    {
+                        pending.metadata.content_key = Some(content_key.clone());
+                        index.insert(
+                            (
+                                content_key,
+                                pending.metadata.data_shards,
+                                pending.metadata.parity_shards,
+                            ),
+                            pending.metadata.clone(),
+                        );
+                    }
+                    built.push((pending.metadata.clone(), Some(pending)));
+                }
+            }
+        }
+        Ok(built)
+    }
+
+    fn content_key(dedup_key: &[u8; 32], data: &[u8]) -> String {
+        blake3::keyed_hash(dedup_key, data).to_hex().to_string()
+    }
+
+    /// Loads and verifies a single shard envelope for `block`.
+    fn load_shard(&self, block: &BlockMetadata, index: usize) -> (ShardStatus, Option<Vec<u8>>) {
+        let envelope_bytes = match self.backend.get(&Self::shard_name(block.id, index)) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return (ShardStatus::Missing, None),
+            Err(_) => return (ShardStatus::Corrupt, None),
+        };
+
+        let envelope =
+            match chunk_format::decode_envelope(&envelope_bytes, &self.derived_keys.meta_mac_key) {
+                Ok(envelope) => envelope,
+                Err(_) => return (ShardStatus::Corrupt, None),
+            };
+
+        if envelope.kind != ChunkKind::DataShard
+            || envelope.block_id != Some(block.id)
+            || envelope.shard_index != Some(index)
+            || envelope.data_shards != Some(block.data_shards)
+            || envelope.parity_shards != Some(block.parity_shards)
+        {
+            return (ShardStatus::Corrupt, None);
+        }
+
+        let payload_hash = blake3::hash(&envelope.payload).to_hex().to_string();
+        if payload_hash != block.shard_hashes[index] {
+            return (ShardStatus::Corrupt, None);
+        }
+
+        (ShardStatus::Healthy, Some(envelope.payload))
+    }
+
+    /// Loads every shard of `block`, returning per-shard status and payloads.
+    fn load_shards(&self, block: &BlockMetadata) -> Result<LoadedShards> {
         let total_shards = block
             .data_shards
             .checked_add(block.parity_shards)
@@ -232,59 +838,70 @@ impl BlockStore {
             ));
         }
 
-        let mut loaded_shards = Vec::with_capacity(total_shards);
-
+        let mut statuses = Vec::with_capacity(total_shards);
+        let mut payloads = Vec::with_capacity(total_shards);
         for i in 0..total_shards {
-            let path = self.root_path.join(format!("block_{}_{}.bin", block.id, i));
-            let envelope_bytes = match fs::read(path) {
-                Ok(bytes) => bytes,
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    loaded_shards.push(None);
-                    continue;
-                }
-                Err(_) => {
-                    loaded_shards.push(None);
-                    continue;
-                }
-            };
+            let (status, payload) = self.load_shard(block, i);
+            statuses.push(status);
+            payloads.push(payload);
+        }
 
-            let envelope = match chunk_format::decode_envelope(
-                &envelope_bytes,
-                &self.derived_keys.meta_mac_key,
-            ) {
-                Ok(envelope) => envelope,
-                Err(_) => {
-                    loaded_shards.push(None);
-                    continue;
-                }
-            };
+        Ok(LoadedShards { statuses, payloads })
+    }
 
-            if envelope.kind != ChunkKind::DataShard
-                || envelope.block_id != Some(block.id)
-                || envelope.shard_index != Some(i)
-                || envelope.data_shards != Some(block.data_shards)
-                || envelope.parity_shards != Some(block.parity_shards)
-            {
-                loaded_shards.push(None);
-                continue;
-            }
+    /// Reads and reconstructs a block.
+    pub(crate) fn read_block(&self, block: &BlockMetadata) -> Result<Vec<u8>> {
+        let loaded = self.load_shards(block)?;
+        self.decode_block(block, loaded.payloads)
+    }
 
-            let payload_hash = blake3::hash(&envelope.payload).to_hex().to_string();
-            if payload_hash != block.shard_hashes[i] {
-                loaded_shards.push(None);
-                continue;
-            }
+    /// Reads a block for `read_at`, rewriting rejected shards afterwards when
+    /// read-repair is enabled. Repair failures never fail the read itself.
+    pub(crate) fn read_block_with_repair(&self, block: &BlockMetadata) -> Result<Vec<u8>> {
+        let loaded = self.load_shards(block)?;
+        let degraded = loaded
+            .statuses
+            .iter()
+            .any(|status| *status != ShardStatus::Healthy);
+        if !degraded {
+            return self.decode_block(block, loaded.payloads);
+        }
+        if !self.io_options.read_repair {
+            IoCounters::add(&self.counters.degraded_blocks_read, 1);
+            return self.decode_block(block, loaded.payloads);
+        }
 
-            loaded_shards.push(Some(envelope.payload));
+        let data = self.decode_block(block, loaded.payloads.clone())?;
+        IoCounters::add(&self.counters.degraded_blocks_read, 1);
+        if let Ok(repaired) = self.rewrite_bad_shards(block, loaded) {
+            IoCounters::add(&self.counters.read_repaired_shards, repaired.len() as u64);
         }
+        Ok(data)
+    }
 
+    /// Reconstructs and decrypts a block from its loaded shard set.
+    fn decode_block(
+        &self,
+        block: &BlockMetadata,
+        loaded_shards: Vec<Option<Vec<u8>>>,
+    ) -> Result<Vec<u8>> {
         // Reconstruct
+        let intact_shards = loaded_shards.iter().flatten().count();
+        if intact_shards < block.data_shards {
+            return Err(UnrecoverableBlock {
+                block_id: block.id,
+                intact_shards,
+                data_shards: block.data_shards,
+            }
+            .into());
+        }
         let package = erasure::reconstruct(loaded_shards, block.data_shards, block.parity_shards)?;
 
-        // Decrypt
-        let data = aont::decrypt(&package, &self.derived_keys.aont_mask_key)?;
+        // Decrypt and undo compression
         let expected_size = usize::try_from(block.original_size)
             .map_err(|_| anyhow!("Block {} size too large for this platform", block.id))?;
+        let data = aont::decrypt(&package, &self.derived_keys.aont_mask_key)?;
+        let data = compression::restore(data, block.compression, expected_size)?;
         if data.len() != expected_size {
             return Err(anyhow!(
                 "Block {} size mismatch: expected {}, reconstructed {}",
@@ -294,69 +911,341 @@ impl BlockStore {
             ));
         }
 
+        IoCounters::add(&self.counters.blocks_read, 1);
         Ok(data)
     }
 
-    /// High-level Read
-    pub fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
-        let read_end = offset
-            .checked_add(length)
-            .ok_or_else(|| anyhow!("Read range overflow"))?;
-        if read_end > self.manifest.total_size {
-            return Err(anyhow!("Read out of bounds"));
-        }
-        if length == 0 {
-            return Ok(Vec::new());
+    /// Checks every shard of every block against the manifest.
+    /// Plaintext is only reconstructed and decrypted when `verify_plaintext` is set.
+    pub fn scrub(&self, verify_plaintext: bool) -> Result<ScrubReport> {
+        let _lock = self.read_lock()?;
+        let mut blocks = Vec::new();
+        for file in &self.manifest.files {
+            self.scrub_file(file, verify_plaintext, &mut blocks)?;
         }
 
-        let expected_length = usize::try_from(length)
-            .map_err(|_| anyhow!("Requested read size too large for this platform"))?;
+        Ok(ScrubReport {
+            epoch: self.manifest.epoch,
+            blocks,
+        })
+    }
 
-        let mut current_offset: u64 = 0;
-        let mut collected_data = Vec::new();
+    fn scrub_file(
+        &self,
+        file: &FileEntry,
+        verify_plaintext: bool,
+        blocks: &mut Vec<BlockScrubReport>,
+    ) -> Result<()> {
+        let checked = parallel::map_ordered(&file.blocks, self.io_options.parallelism, |block| {
+            let loaded = self.load_shards(block)?;
+            let mut health =
+                BlockHealth::classify(&loaded.statuses, block.data_shards, block.parity_shards);
+
+            let plaintext_verified = if verify_plaintext && health != BlockHealth::Unrecoverable {
+                let verified = self.decode_block(block, loaded.payloads).is_ok();
+                if !verified {
+                    health = BlockHealth::Unrecoverable;
+                }
+                Some(verified)
+            } else {
+                None
+            };
+            Ok::<_, anyhow::Error>((loaded.statuses, health, plaintext_verified))
+        });
 
-        for block in &self.manifest.blocks {
-            let block_end = current_offset
+        let mut current_offset: u64 = 0;
+        for (block, result) in file.blocks.iter().zip(checked) {
+            let (shard_status, health, plaintext_verified) = result?;
+            blocks.push(BlockScrubReport {
+                file_name: file.name.clone(),
+                block_id: block.id,
+                offset: current_offset,
+                original_size: block.original_size,
+                data_shards: block.data_shards,
+                parity_shards: block.parity_shards,
+                shard_status,
+                health,
+                plaintext_verified,
+            });
+
+            current_offset = current_offset
                 .checked_add(block.original_size)
                 .ok_or_else(|| anyhow!("Block range overflow"))?;
-
-            if current_offset < read_end && block_end > offset {
-                let start_in_block = offset.saturating_sub(current_offset);
-                let end_in_block = if read_end < block_end {
-                    read_end - current_offset
-                } else {
-                    block.original_size
-                };
-
-                let block_data = self.read_block(block)?;
-                let start_idx = usize::try_from(start_in_block)
-                    .map_err(|_| anyhow!("Block offset too large for this platform"))?;
-                let end_idx = usize::try_from(end_in_block)
-                    .map_err(|_| anyhow!("Block offset too large for this platform"))?;
-
-                if start_idx > end_idx || end_idx > block_data.len() {
-                    return Err(anyhow!("Block {} range is inconsistent", block.id));
-                }
-
-                let chunk = &block_data[start_idx..end_idx];
-                collected_data.extend_from_slice(chunk);
-            }
-
-            current_offset = block_end;
         }
+        Ok(())
+    }
 
-        if collected_data.len() != expected_length {
-            return Err(anyhow!(
-                "Read size mismatch: expected {}, got {}",
-                expected_length,
-                collected_data.len()
-            ));
+    /// Rebuilds missing or corrupt shards of every block in place, including
+    /// blocks only kept alive by snapshots.
+    /// Blocks that can no longer be decoded are reported and skipped.
+    pub fn repair(&self) -> Result<RepairReport> {
+        let _lock = self.write_lock()?;
+        let mut blocks = Vec::new();
+        let mut seen = HashSet::new();
+        for block in self.manifest.referenced_blocks() {
+            if seen.insert(block.id) {
+                blocks.push(self.repair_metadata_block(block)?);
+            }
         }
+        Ok(RepairReport {
+            epoch: self.manifest.epoch,
+            blocks,
+        })
+    }
 
-        Ok(collected_data)
+    /// Rebuilds missing or corrupt shards of one block without re-encrypting it.
+    /// Only the bad shard files are rewritten; the manifest epoch is unchanged.
+    pub fn repair_block(&self, block_id: usize) -> Result<BlockRepairReport> {
+        let _lock = self.write_lock()?;
+        let block = self
+            .manifest
+            .referenced_blocks()
+            .find(|block| block.id == block_id)
+            .ok_or_else(|| anyhow!("Block {} not found in manifest", block_id))?;
+        self.repair_metadata_block(block)
     }
 
-    /// Insert data at offset.
+    fn repair_metadata_block(&self, block: &BlockMetadata) -> Result<BlockRepairReport> {
+        let loaded = self.load_shards(block)?;
+        let outcome =
+            match BlockHealth::classify(&loaded.statuses, block.data_shards, block.parity_shards) {
+                BlockHealth::Healthy => RepairOutcome::AlreadyHealthy,
+                BlockHealth::Unrecoverable => RepairOutcome::Unrecoverable,
+                BlockHealth::Degraded { .. } => RepairOutcome::Repaired {
+                    shards: self.rewrite_bad_shards(block, loaded)?,
+                },
+            };
+        Ok(BlockRepairReport {
+            block_id: block.id,
+            outcome,
+        })
+    }
+
+    /// Recomputes the bad shards of `block` from the healthy ones, checks them
+    /// against the manifest hashes and atomically rewrites only those files.
+    fn rewrite_bad_shards(
+        &self,
+        block: &BlockMetadata,
+        loaded: LoadedShards,
+    ) -> Result<Vec<usize>> {
+        let bad_shards: Vec<usize> = loaded
+            .statuses
+            .iter()
+            .enumerate()
+            .filter_map(|(i, status)| (*status != ShardStatus::Healthy).then_some(i))
+            .collect();
+        if bad_shards.is_empty() {
+            return Ok(bad_shards);
+        }
+
+        let rebuilt =
+            erasure::reconstruct_shards(loaded.payloads, block.data_shards, block.parity_shards)?;
+        for &i in &bad_shards {
+            let hash = blake3::hash(&rebuilt[i]).to_hex().to_string();
+            if hash != block.shard_hashes[i] {
+                return Err(anyhow!(
+                    "Rebuilt shard {} of block {} does not match its manifest hash",
+                    i,
+                    block.id
+                ));
+            }
+        }
+
+        let (manifest_blob_zstd, manifest_hash) =
+            manifest_recovery::encode_manifest_snapshot(&self.manifest)?;
+        for &i in &bad_shards {
+            let envelope = ChunkEnvelope::data_shard(
+                block.id,
+                i,
+                block.data_shards,
+                block.parity_shards,
+                rebuilt[i].clone(),
+                self.manifest.epoch,
+                manifest_hash,
+                manifest_blob_zstd.clone(),
+            );
+            self.write_envelope_file(&Self::shard_name(block.id, i), &envelope)?;
+            IoCounters::add(&self.counters.shards_written, 1);
+        }
+
+        Ok(bad_shards)
+    }
+
+    /// Returns a lazily decoding `Read + Seek` view over the selected file.
+    pub fn reader(&self) -> DatasetReader<'_> {
+        DatasetReader::new(self, self.blocks())
+    }
+
+    /// Returns a lazily decoding `Read + Seek` view over the named file.
+    pub fn file_reader(&self, name: &str) -> Result<DatasetReader<'_>> {
+        let file = self
+            .manifest
+            .file(name)
+            .ok_or_else(|| anyhow!("File '{}' not found in dataset", name))?;
+        Ok(DatasetReader::new(self, &file.blocks))
+    }
+
+    /// Returns a reader over a file as it was at `epoch`, which must be the
+    /// current epoch or one pinned by a snapshot. Without a name the selected
+    /// file is used for the current epoch and the first file otherwise.
+    pub fn reader_at_epoch(&self, epoch: u64, name: Option<&str>) -> Result<DatasetReader<'_>> {
+        if epoch == self.manifest.epoch {
+            return match name {
+                Some(name) => self.file_reader(name),
+                None => Ok(self.reader()),
+            };
+        }
+
+        let snapshot = self.manifest.snapshot_at_epoch(epoch).ok_or_else(|| {
+            anyhow!(
+                "Epoch {} is not pinned by a snapshot (current epoch is {})",
+                epoch,
+                self.manifest.epoch
+            )
+        })?;
+        let file = match name {
+            Some(name) => snapshot.files.iter().find(|file| file.name == name),
+            None => snapshot.files.first(),
+        }
+        .ok_or_else(|| {
+            anyhow!(
+                "File '{}' not found at epoch {}",
+                name.unwrap_or_default(),
+                epoch
+            )
+        })?;
+        Ok(DatasetReader::new(self, &file.blocks))
+    }
+
+    /// Writes everything the current epoch needs into one archive: the shards
+    /// of every block the manifest references, snapshots included, and the
+    /// epoch's metadata copies. `archive::import` installs it elsewhere.
+    pub fn export_archive<W: Write>(&self, writer: W) -> Result<ExportReport> {
+        let _lock = self.read_lock()?;
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+        for block in self.manifest.referenced_blocks() {
+            if seen.insert(block.id) {
+                let total_shards = block.data_shards + block.parity_shards;
+                names.extend((0..total_shards).map(|index| Self::shard_name(block.id, index)));
+            }
+        }
+        let mut meta_names: Vec<String> = self
+            .backend
+            .list()?
+            .into_iter()
+            .filter(|name| Self::parse_meta_epoch(name) == Some(self.manifest.epoch))
+            .collect();
+        meta_names.sort();
+        names.extend(meta_names);
+        archive::write_archive(writer, self.manifest.epoch, names, |name| {
+            self.backend.get(name)
+        })
+    }
+
+    /// Inclusion proof for bytes `offset..offset + length` of the selected
+    /// file at the current epoch, checkable against the manifest's Merkle
+    /// root without the key or the rest of the dataset.
+    pub fn prove_range(&self, offset: u64, length: u64) -> Result<RangeProof> {
+        let file = self.active_file()?;
+        RangeProof::build(&self.manifest, &file.name, offset, length)
+    }
+
+    /// Audit challenge over up to `samples` random shards. The length of each
+    /// sampled shard is read from a shard of its block that verifies against
+    /// the manifest, since all shards of a block have the same size.
+    pub fn audit_challenge(&self, secret: &AuditSecret, samples: usize) -> Result<Challenge> {
+        Challenge::new(secret, &self.manifest, samples, |item| {
+            let block = self
+                .manifest
+                .referenced_blocks()
+                .find(|block| block.id == item.block_id)
+                .ok_or_else(|| anyhow!("Block {} is not referenced", item.block_id))?;
+            let total_shards = block.shard_hashes.len();
+            (item.shard_index..total_shards)
+                .chain(0..item.shard_index)
+                .find_map(|index| self.load_shard(block, index).1)
+                .map(|payload| payload.len() as u64)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No shard of block {} is readable to learn its length",
+                        block.id
+                    )
+                })
+        })
+    }
+
+    /// High-level Read. Overlapping blocks are decoded in parallel.
+    pub fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let _lock = self.read_lock()?;
+        let read_end = offset
+            .checked_add(length)
+            .ok_or_else(|| anyhow!("Read range overflow"))?;
+        if read_end > self.total_size() {
+            return Err(anyhow!("Read out of bounds"));
+        }
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+
+        let expected_length = usize::try_from(length)
+            .map_err(|_| anyhow!("Requested read size too large for this platform"))?;
+
+        let (range, first_start) = Self::overlapping_blocks(self.blocks(), offset, read_end)?;
+        let touched = &self.blocks()[range];
+        let decoded = parallel::map_ordered(touched, self.io_options.parallelism, |block| {
+            self.read_block_with_repair(block)
+        });
+
+        let mut current_offset = first_start;
+        let mut collected_data = Vec::with_capacity(expected_length);
+        for (block, block_data) in touched.iter().zip(decoded) {
+            let block_data = block_data?;
+            let block_end = current_offset
+                .checked_add(block.original_size)
+                .ok_or_else(|| anyhow!("Block range overflow"))?;
+
+            let start_in_block = offset.saturating_sub(current_offset);
+            let end_in_block = u64::min(read_end, block_end) - current_offset;
+            let start_idx = usize::try_from(start_in_block)
+                .map_err(|_| anyhow!("Block offset too large for this platform"))?;
+            let end_idx = usize::try_from(end_in_block)
+                .map_err(|_| anyhow!("Block offset too large for this platform"))?;
+
+            if start_idx > end_idx || end_idx > block_data.len() {
+                return Err(anyhow!("Block {} range is inconsistent", block.id));
+            }
+
+            collected_data.extend_from_slice(&block_data[start_idx..end_idx]);
+            current_offset = block_end;
+        }
+
+        if collected_data.len() != expected_length {
+            return Err(anyhow!(
+                "Read size mismatch: expected {}, got {}",
+                expected_length,
+                collected_data.len()
+            ));
+        }
+
+        Ok(collected_data)
+    }
+
+    /// Starts a batch of inserts, overwrites and deletes on the selected file
+    /// that commits as a single epoch; see `Transaction`.
+    pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+        Transaction::new(self)
+    }
+
+    /// Inserts data at offset using the dataset's default layout.
+    pub fn insert(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let mut transaction = self.transaction()?;
+        transaction.insert(offset, data)?;
+        transaction.commit()
+    }
+
+    /// Insert data at offset with an explicit layout, overriding the
+    /// dataset's default.
     /// This splits the block at `offset` into [Left, Inserted, Right].
     pub fn insert_at(
         &mut self,
@@ -365,232 +1254,927 @@ impl BlockStore {
         data_shards: usize,
         parity_shards: usize,
     ) -> Result<()> {
-        Self::validate_shard_config(data_shards, parity_shards)?;
+        let mut transaction = self.transaction()?;
+        transaction.insert_at(offset, data, data_shards, parity_shards)?;
+        transaction.commit()
+    }
+
+    /// Appends everything read from `reader` to the end of the selected file,
+    /// split into blocks of at most `block_size` bytes.
+    ///
+    /// Each block's shards are written as soon as it is encoded, embedding the
+    /// currently committed manifest, so memory use stays bounded by one block.
+    /// The new block list only becomes visible when the final manifest is
+    /// committed after the stream is exhausted. Returns the number of bytes read.
+    pub fn write_stream<R: Read>(
+        &mut self,
+        reader: R,
+        block_size: usize,
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<u64> {
+        self.write_stream_chunked(
+            reader,
+            Chunking::Fixed { block_size },
+            data_shards,
+            parity_shards,
+        )
+    }
 
-        if offset > self.manifest.total_size {
-            return Err(anyhow!("Insert out of bounds (can append at exact end)"));
+    /// `write_stream` with an explicit chunking strategy, e.g. content-defined
+    /// chunking so that unchanged regions of a re-written stream deduplicate.
+    pub fn write_stream_chunked<R: Read>(
+        &mut self,
+        reader: R,
+        chunking: Chunking,
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<u64> {
+        let _lock = self.write_lock()?;
+        let mut file_blocks = self.active_file()?.blocks.clone();
+        let mut staged_names = Vec::new();
+
+        let result = self
+            .stage_stream(
+                reader,
+                chunking,
+                data_shards,
+                parity_shards,
+                &mut staged_names,
+            )
+            .and_then(|(blocks, bytes_read)| {
+                file_blocks.extend(blocks);
+                let next_manifest = self.manifest_with_active_blocks(file_blocks)?;
+                self.commit_manifest(next_manifest, Vec::new(), Vec::new())?;
+                Ok(bytes_read)
+            });
+
+        if result.is_err() {
+            self.delete_objects_best_effort(staged_names);
         }
+        result
+    }
 
-        let mut next_id = self.next_available_id()?;
+    /// Streams `reader` into a named file, replacing it if it already exists,
+    /// and selects it. Memory use is bounded by one block as in `write_stream`.
+    pub fn put_file<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        block_size: usize,
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<u64> {
+        self.put_file_chunked(
+            name,
+            reader,
+            Chunking::Fixed { block_size },
+            data_shards,
+            parity_shards,
+        )
+    }
+
+    /// `put_file` with an explicit chunking strategy.
+    pub fn put_file_chunked<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        chunking: Chunking,
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<u64> {
+        let _lock = self.write_lock()?;
+        Self::validate_file_name(name)?;
+        let mut staged_names = Vec::new();
+
+        let result = self
+            .stage_stream(
+                reader,
+                chunking,
+                data_shards,
+                parity_shards,
+                &mut staged_names,
+            )
+            .and_then(|(blocks, bytes_read)| {
+                let mut entry = FileEntry::new(name);
+                for block in blocks {
+                    entry.add_block(block);
+                }
+
+                let mut next_manifest = self.manifest.clone();
+                let mut obsolete_blocks = Vec::new();
+                match next_manifest.file_mut(name) {
+                    Some(existing) => {
+                        obsolete_blocks = std::mem::replace(existing, entry).blocks;
+                    }
+                    None => next_manifest.files.push(entry),
+                }
+                self.commit_manifest(next_manifest, obsolete_blocks, Vec::new())?;
+                Ok(bytes_read)
+            });
+
+        match result {
+            Ok(bytes_read) => {
+                self.active_file = Some(name.to_string());
+                Ok(bytes_read)
+            }
+            Err(err) => {
+                self.delete_objects_best_effort(staged_names);
+                Err(err)
+            }
+        }
+    }
+
+    /// Removes a file from the namespace and garbage-collects its blocks.
+    pub fn remove_file(&mut self, name: &str) -> Result<()> {
+        let _lock = self.write_lock()?;
         let mut next_manifest = self.manifest.clone();
-        let mut obsolete_blocks = Vec::new();
-        let mut pending_blocks = Vec::new();
+        let position = next_manifest
+            .files
+            .iter()
+            .position(|file| file.name == name)
+            .ok_or_else(|| anyhow!("File '{}' not found in dataset", name))?;
+        let removed = next_manifest.files.remove(position);
 
-        if offset == self.manifest.total_size {
-            let new_id = Self::take_next_id(&mut next_id)?;
-            let new_block = self.create_block(data, new_id, data_shards, parity_shards)?;
-            next_manifest.add_block(new_block.metadata.clone());
-            pending_blocks.push(new_block);
-            return self.commit_manifest(next_manifest, obsolete_blocks, pending_blocks);
+        self.commit_manifest(next_manifest, removed.blocks, Vec::new())?;
+        if self.active_file.as_deref() == Some(name) {
+            self.active_file = self.manifest.files.first().map(|file| file.name.clone());
         }
+        Ok(())
+    }
 
-        let mut current_offset: u64 = 0;
-        let mut split_index = None;
-        let mut split_pos_in_block = 0;
+    /// Renames a file without touching its blocks.
+    pub fn rename_file(&mut self, from: &str, to: &str) -> Result<()> {
+        let _lock = self.write_lock()?;
+        Self::validate_file_name(to)?;
+        if self.manifest.file(to).is_some() {
+            return Err(anyhow!("File '{}' already exists in dataset", to));
+        }
 
-        for (i, block) in self.manifest.blocks.iter().enumerate() {
-            let block_end = current_offset
-                .checked_add(block.original_size)
-                .ok_or_else(|| anyhow!("Block range overflow"))?;
-            if offset >= current_offset && offset < block_end {
-                split_index = Some(i);
-                split_pos_in_block = offset - current_offset;
-                break;
-            }
-            current_offset = block_end;
+        let mut next_manifest = self.manifest.clone();
+        let entry = next_manifest
+            .file_mut(from)
+            .ok_or_else(|| anyhow!("File '{}' not found in dataset", from))?;
+        entry.name = to.to_string();
+
+        self.commit_manifest(next_manifest, Vec::new(), Vec::new())?;
+        if self.active_file.as_deref() == Some(from) {
+            self.active_file = Some(to.to_string());
         }
+        Ok(())
+    }
 
-        let idx =
-            split_index.ok_or_else(|| anyhow!("Insert offset not found in manifest blocks"))?;
-        let block_to_split = self.manifest.blocks[idx].clone();
-        let full_data = self.read_block(&block_to_split)?;
-        let split_idx = usize::try_from(split_pos_in_block)
-            .map_err(|_| anyhow!("Split offset too large for this platform"))?;
-        if split_idx > full_data.len() {
-            return Err(anyhow!("Split offset is inconsistent with block data"));
+    /// Pins the current epoch under `name` so its blocks survive later commits.
+    pub fn create_snapshot(&mut self, name: &str) -> Result<()> {
+        let _lock = self.write_lock()?;
+        if name.is_empty() {
+            return Err(anyhow!("Snapshot name cannot be empty"));
+        }
+        if self.manifest.snapshot(name).is_some() {
+            return Err(anyhow!("Snapshot '{}' already exists", name));
         }
-        let (left_data, right_data) = full_data.split_at(split_idx);
 
-        let mut new_blocks = Vec::new();
+        let mut next_manifest = self.manifest.clone();
+        next_manifest.snapshots.push(Snapshot {
+            name: name.to_string(),
+            epoch: self.manifest.epoch,
+            files: self.manifest.files.clone(),
+        });
+        self.commit_manifest(next_manifest, Vec::new(), Vec::new())
+    }
 
-        if !left_data.is_empty() {
-            let id = Self::take_next_id(&mut next_id)?;
-            let pending = self.create_block(
-                left_data,
-                id,
-                block_to_split.data_shards,
-                block_to_split.parity_shards,
-            )?;
-            new_blocks.push(pending.metadata.clone());
-            pending_blocks.push(pending);
-        }
-
-        let id = Self::take_next_id(&mut next_id)?;
-        let inserted = self.create_block(data, id, data_shards, parity_shards)?;
-        new_blocks.push(inserted.metadata.clone());
-        pending_blocks.push(inserted);
-
-        if !right_data.is_empty() {
-            let id = Self::take_next_id(&mut next_id)?;
-            let pending = self.create_block(
-                right_data,
-                id,
-                block_to_split.data_shards,
-                block_to_split.parity_shards,
-            )?;
-            new_blocks.push(pending.metadata.clone());
-            pending_blocks.push(pending);
+    /// Unpins a snapshot and garbage-collects blocks nothing else references.
+    pub fn delete_snapshot(&mut self, name: &str) -> Result<()> {
+        let _lock = self.write_lock()?;
+        let mut next_manifest = self.manifest.clone();
+        let position = next_manifest
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.name == name)
+            .ok_or_else(|| anyhow!("Snapshot '{}' not found", name))?;
+        let removed = next_manifest.snapshots.remove(position);
+
+        let obsolete_blocks = removed
+            .files
+            .into_iter()
+            .flat_map(|file| file.blocks)
+            .collect();
+        self.commit_manifest(next_manifest, obsolete_blocks, Vec::new())
+    }
+
+    /// Turns block deduplication on or off for later writes in a new commit.
+    /// Blocks written while it is off carry no content key and are never reused.
+    pub fn set_dedup(&mut self, enabled: bool) -> Result<()> {
+        let mut settings = self.manifest.settings.clone();
+        settings.dedup = enabled;
+        self.commit_settings(settings)
+    }
+
+    /// Sets how blocks written from now on are compressed. Existing blocks
+    /// keep the compression recorded in their metadata.
+    pub fn set_compression(&mut self, policy: CompressionPolicy) -> Result<()> {
+        policy.validate()?;
+        let mut settings = self.manifest.settings.clone();
+        settings.compression = policy;
+        self.commit_settings(settings)
+    }
+
+    /// Sets the compaction that runs after edits once fragmentation reaches
+    /// its ratio, or turns it off with `None`.
+    pub fn set_auto_compaction(&mut self, auto_compact: Option<AutoCompaction>) -> Result<()> {
+        if let Some(auto_compact) = &auto_compact {
+            auto_compact.validate()?;
         }
+        let mut settings = self.manifest.settings.clone();
+        settings.auto_compact = auto_compact;
+        self.commit_settings(settings)
+    }
 
-        next_manifest.blocks.splice(idx..idx + 1, new_blocks);
-        Self::recalc_total_size(&mut next_manifest)?;
+    /// Layout given to blocks that edits add without naming one.
+    pub fn redundancy(&self) -> Redundancy {
+        self.manifest.settings.redundancy
+    }
 
-        obsolete_blocks.push(block_to_split);
-        self.commit_manifest(next_manifest, obsolete_blocks, pending_blocks)
+    /// Sets the dataset's default layout for new blocks in a new commit.
+    /// Existing blocks keep theirs; `restripe` changes them.
+    pub fn set_redundancy(&mut self, redundancy: Redundancy) -> Result<()> {
+        Self::validate_shard_config(redundancy.data_shards, redundancy.parity_shards)?;
+        self.check_failure_domains(redundancy.data_shards, redundancy.parity_shards)?;
+        let mut settings = self.manifest.settings.clone();
+        settings.redundancy = redundancy;
+        self.commit_settings(settings)
     }
 
-    /// Deletes data in range [offset, offset + length).
-    pub fn delete_range(&mut self, offset: u64, length: u64) -> Result<()> {
-        if length == 0 {
+    /// Commits new dataset settings; a no-op when nothing changes.
+    fn commit_settings(&mut self, settings: DatasetSettings) -> Result<()> {
+        let _lock = self.write_lock()?;
+        if self.manifest.settings == settings {
             return Ok(());
         }
-        let delete_end = offset
-            .checked_add(length)
-            .ok_or_else(|| anyhow!("Delete range overflow"))?;
-        if delete_end > self.manifest.total_size {
-            return Err(anyhow!("Delete out of bounds"));
+        let mut next_manifest = self.manifest.clone();
+        next_manifest.settings = settings;
+        self.commit_manifest(next_manifest, Vec::new(), Vec::new())
+    }
+
+    /// Makes the namespace pinned at `epoch` current again in a new commit.
+    /// The snapshot itself is kept, so the rollback can be repeated.
+    pub fn rollback(&mut self, epoch: u64) -> Result<()> {
+        let _lock = self.write_lock()?;
+        let snapshot = self
+            .manifest
+            .snapshot_at_epoch(epoch)
+            .ok_or_else(|| anyhow!("Epoch {} is not pinned by a snapshot", epoch))?;
+
+        let mut next_manifest = self.manifest.clone();
+        next_manifest.files = snapshot.files.clone();
+        let obsolete_blocks = self.manifest.blocks().cloned().collect();
+        self.commit_manifest(next_manifest, obsolete_blocks, Vec::new())?;
+
+        let still_present = self
+            .active_file
+            .as_deref()
+            .is_some_and(|name| self.manifest.file(name).is_some());
+        if !still_present {
+            self.active_file = self.manifest.files.first().map(|file| file.name.clone());
+        }
+        Ok(())
+    }
+
+    /// Moves the dataset to a new root key in a single epoch commit.
+    ///
+    /// Every referenced block, including those pinned by snapshots, is
+    /// reconstructed, its AONT package re-masked and re-encoded under a fresh
+    /// block id with envelopes MACed under the new key. Old blocks are only
+    /// collected after the commit, so an interrupted rotation leaves the dataset
+    /// readable under the old key alone. Staged shards embed the current
+    /// manifest MACed under the new key, so after a crash mid-rotation the new
+    /// key may still open that manifest, but not read the blocks it names.
+    pub fn rotate_key(&mut self, new_root_key: [u8; 32]) -> Result<()> {
+        let _lock = self.write_lock()?;
+        let new_keys = RootKey(new_root_key).derive();
+        if new_keys == self.derived_keys {
+            return Err(anyhow!("New root key is identical to the current one"));
         }
 
+        let (anchor_blob_zstd, anchor_hash) =
+            manifest_recovery::encode_manifest_snapshot(&self.manifest)?;
         let mut next_id = self.next_available_id()?;
-        let mut current_offset: u64 = 0;
-        let mut new_blocks = Vec::new();
+        let mut remapped: HashMap<usize, BlockMetadata> = HashMap::new();
         let mut obsolete_blocks = Vec::new();
-        let mut pending_blocks = Vec::new();
+        let mut staged_names = Vec::new();
 
-        for block in &self.manifest.blocks {
-            let block_start = current_offset;
-            let block_end = current_offset
-                .checked_add(block.original_size)
-                .ok_or_else(|| anyhow!("Block range overflow"))?;
+        let staged = (|| -> Result<()> {
+            for block in self.manifest.referenced_blocks() {
+                if remapped.contains_key(&block.id) {
+                    continue;
+                }
+                let pending =
+                    self.rekey_block(block, &new_keys, Self::take_next_id(&mut next_id)?)?;
+                self.write_block_shards(
+                    &pending,
+                    &new_keys.meta_mac_key,
+                    self.manifest.epoch,
+                    anchor_hash,
+                    &anchor_blob_zstd,
+                    &mut staged_names,
+                )?;
+                remapped.insert(block.id, pending.metadata);
+                obsolete_blocks.push(block.clone());
+            }
+            Ok(())
+        })();
+        if let Err(err) = staged {
+            self.delete_objects_best_effort(staged_names);
+            return Err(err);
+        }
 
-            let overlap_start = u64::max(offset, block_start);
-            let overlap_end = u64::min(delete_end, block_end);
-
-            if overlap_start < overlap_end {
-                let data = self.read_block(block)?;
-                let start_in_block = usize::try_from(overlap_start - block_start)
-                    .map_err(|_| anyhow!("Delete offset too large for this platform"))?;
-                let end_in_block = usize::try_from(overlap_end - block_start)
-                    .map_err(|_| anyhow!("Delete offset too large for this platform"))?;
-                if start_in_block > end_in_block || end_in_block > data.len() {
-                    return Err(anyhow!("Delete range is inconsistent with block data"));
+        let mut next_manifest = self.manifest.clone();
+        let remap_files = |files: &mut Vec<FileEntry>| {
+            for block in files.iter_mut().flat_map(|file| file.blocks.iter_mut()) {
+                if let Some(rekeyed) = remapped.get(&block.id) {
+                    *block = rekeyed.clone();
                 }
+            }
+        };
+        remap_files(&mut next_manifest.files);
+        for snapshot in &mut next_manifest.snapshots {
+            remap_files(&mut snapshot.files);
+        }
+
+        let old_keys = std::mem::replace(&mut self.derived_keys, new_keys);
+        if let Err(err) = self.commit_manifest(next_manifest, obsolete_blocks, Vec::new()) {
+            self.derived_keys = old_keys;
+            self.delete_objects_best_effort(staged_names);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Reconstructs `block` under the current key and returns it re-masked for
+    /// `new_keys`, re-encoded with the same shard policy under `new_id`.
+    fn rekey_block(
+        &self,
+        block: &BlockMetadata,
+        new_keys: &DerivedKeys,
+        new_id: usize,
+    ) -> Result<PendingBlock> {
+        let loaded = self.load_shards(block)?;
+        let package =
+            erasure::reconstruct(loaded.payloads, block.data_shards, block.parity_shards)?;
+        let content_key = match block.content_key {
+            Some(_) => {
+                let expected_size = usize::try_from(block.original_size)
+                    .map_err(|_| anyhow!("Block {} size too large for this platform", block.id))?;
+                let data = aont::decrypt(&package, &self.derived_keys.aont_mask_key)?;
+                let data = compression::restore(data, block.compression, expected_size)?;
+                Some(Self::content_key(&new_keys.dedup_key, &data))
+            }
+            None => None,
+        };
+        let package = aont::rekey(
+            &package,
+            &self.derived_keys.aont_mask_key,
+            &new_keys.aont_mask_key,
+        )?;
 
-                if start_in_block > 0 {
-                    let left_data = &data[0..start_in_block];
-                    let id = Self::take_next_id(&mut next_id)?;
-                    let pending =
-                        self.create_block(left_data, id, block.data_shards, block.parity_shards)?;
-                    new_blocks.push(pending.metadata.clone());
-                    pending_blocks.push(pending);
+        let shards = erasure::encode(&package, block.data_shards, block.parity_shards)?;
+        let shard_hashes = shards
+            .iter()
+            .map(|shard| blake3::hash(shard).to_hex().to_string())
+            .collect();
+        Ok(PendingBlock {
+            metadata: BlockMetadata {
+                id: new_id,
+                original_size: block.original_size,
+                data_shards: block.data_shards,
+                parity_shards: block.parity_shards,
+                shard_hashes,
+                content_key,
+                compression: block.compression,
+            },
+            shards,
+        })
+    }
+
+    /// Re-encodes every block of every file to `data_shards + parity_shards`
+    /// and makes that the dataset's default layout. See `restripe_range`.
+    pub fn restripe(&mut self, data_shards: usize, parity_shards: usize) -> Result<RestripeReport> {
+        let _lock = self.write_lock()?;
+        let positions = self
+            .manifest
+            .files
+            .iter()
+            .enumerate()
+            .flat_map(|(file, entry)| (0..entry.blocks.len()).map(move |block| (file, block)))
+            .collect();
+        let report = self.restripe_positions(positions, data_shards, parity_shards)?;
+        self.set_redundancy(Redundancy {
+            data_shards,
+            parity_shards,
+        })?;
+        Ok(report)
+    }
+
+    /// Re-encodes the blocks of the selected file overlapping
+    /// `[offset, offset + length)` to `data_shards + parity_shards`; blocks
+    /// are always re-encoded whole.
+    ///
+    /// Only the erasure coding changes: each block's AONT package is
+    /// reconstructed from its shards and split again under a fresh block id,
+    /// without decrypting it. The work is committed in steps of a bounded
+    /// number of blocks, each one its own epoch, so a crash loses
+    /// at most the step in flight and leaves the dataset readable. Blocks
+    /// already at the target layout are skipped, so running it again resumes
+    /// where it stopped. Snapshots keep the layout they pinned.
+    pub fn restripe_range(
+        &mut self,
+        offset: u64,
+        length: u64,
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<RestripeReport> {
+        let _lock = self.write_lock()?;
+        let end = offset
+            .checked_add(length)
+            .ok_or_else(|| anyhow!("Restripe range overflow"))?;
+        let file = self.active_file()?;
+        if end > file.total_size {
+            return Err(anyhow!("Restripe range out of bounds"));
+        }
+        let positions = if length == 0 {
+            Vec::new()
+        } else {
+            let file_index = self
+                .manifest
+                .files
+                .iter()
+                .position(|entry| entry.name == file.name)
+                .ok_or_else(|| anyhow!("File '{}' not found in dataset", file.name))?;
+            let (range, _) = Self::overlapping_blocks(&file.blocks, offset, end)?;
+            range.map(|block| (file_index, block)).collect()
+        };
+        self.restripe_positions(positions, data_shards, parity_shards)
+    }
+
+    /// Restripes the blocks at the given (file, block) positions of the
+    /// current namespace, committing every `RESTRIPE_STEP_BLOCKS` of them.
+    fn restripe_positions(
+        &mut self,
+        positions: Vec<(usize, usize)>,
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<RestripeReport> {
+        Self::validate_shard_config(data_shards, parity_shards)?;
+        self.check_failure_domains(data_shards, parity_shards)?;
+        let mut report = RestripeReport::default();
+        let positions: Vec<(usize, usize)> = positions
+            .into_iter()
+            .filter(|&(file, block)| {
+                let block = &self.manifest.files[file].blocks[block];
+                let current =
+                    block.data_shards == data_shards && block.parity_shards == parity_shards;
+                if current {
+                    report.blocks_skipped += 1;
                 }
+                !current
+            })
+            .collect();
 
-                if end_in_block < data.len() {
-                    let right_data = &data[end_in_block..];
-                    let id = Self::take_next_id(&mut next_id)?;
-                    let pending =
-                        self.create_block(right_data, id, block.data_shards, block.parity_shards)?;
-                    new_blocks.push(pending.metadata.clone());
-                    pending_blocks.push(pending);
+        // Shared blocks are re-encoded once and every position moves to the
+        // same copy, even across steps.
+        let mut remapped: HashMap<usize, BlockMetadata> = HashMap::new();
+        for step in positions.chunks(RESTRIPE_STEP_BLOCKS) {
+            let (anchor_blob_zstd, anchor_hash) =
+                manifest_recovery::encode_manifest_snapshot(&self.manifest)?;
+            let mut next_id = self.next_available_id()?;
+            let mut to_encode: Vec<(BlockMetadata, usize)> = Vec::new();
+            for &(file, block) in step {
+                let block = &self.manifest.files[file].blocks[block];
+                if !remapped.contains_key(&block.id)
+                    && !to_encode.iter().any(|(queued, _)| queued.id == block.id)
+                {
+                    to_encode.push((block.clone(), Self::take_next_id(&mut next_id)?));
                 }
+            }
 
-                obsolete_blocks.push(block.clone());
-            } else {
-                new_blocks.push(block.clone());
+            let mut staged_names = Vec::new();
+            let staged = (|| -> Result<()> {
+                for batch in to_encode.chunks(self.io_options.parallelism.max(1)) {
+                    let encoded =
+                        parallel::map_ordered(batch, self.io_options.parallelism, |(block, id)| {
+                            self.restripe_block(block, *id, data_shards, parity_shards)
+                        });
+                    for ((block, _), pending) in batch.iter().zip(encoded) {
+                        let pending = pending?;
+                        self.write_block_shards(
+                            &pending,
+                            &self.derived_keys.meta_mac_key,
+                            self.manifest.epoch,
+                            anchor_hash,
+                            &anchor_blob_zstd,
+                            &mut staged_names,
+                        )?;
+                        report.bytes = report.bytes.saturating_add(block.original_size);
+                        remapped.insert(block.id, pending.metadata);
+                    }
+                }
+                Ok(())
+            })();
+            if let Err(err) = staged {
+                self.delete_objects_best_effort(staged_names);
+                return Err(err);
             }
 
-            current_offset = block_end;
+            // Every block a slot moves away from is offered to garbage
+            // collection, including shared blocks re-encoded in an earlier
+            // step, which are only unreferenced once their last slot moves.
+            let mut next_manifest = self.manifest.clone();
+            let mut obsolete_blocks: Vec<BlockMetadata> = Vec::new();
+            for &(file, block) in step {
+                let slot = &mut next_manifest.files[file].blocks[block];
+                if let Some(restriped) = remapped.get(&slot.id) {
+                    if !obsolete_blocks.iter().any(|old| old.id == slot.id) {
+                        obsolete_blocks.push(slot.clone());
+                    }
+                    *slot = restriped.clone();
+                }
+            }
+            if let Err(err) = self.commit_manifest(next_manifest, obsolete_blocks, Vec::new()) {
+                self.delete_objects_best_effort(staged_names);
+                return Err(err);
+            }
+            report.blocks_restriped += to_encode.len();
+            report.steps += 1;
         }
+        Ok(report)
+    }
+
+    /// Rebuilds the AONT package of `block` from its shards and erasure-codes
+    /// it again as `data_shards + parity_shards` under `new_id`.
+    fn restripe_block(
+        &self,
+        block: &BlockMetadata,
+        new_id: usize,
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<PendingBlock> {
+        let loaded = self.load_shards(block)?;
+        let package =
+            erasure::reconstruct(loaded.payloads, block.data_shards, block.parity_shards)?;
+        let shards = erasure::encode(&package, data_shards, parity_shards)?;
+        let shard_hashes = shards
+            .iter()
+            .map(|shard| blake3::hash(shard).to_hex().to_string())
+            .collect();
+        Ok(PendingBlock {
+            metadata: BlockMetadata {
+                id: new_id,
+                original_size: block.original_size,
+                data_shards,
+                parity_shards,
+                shard_hashes,
+                content_key: block.content_key.clone(),
+                compression: block.compression,
+            },
+            shards,
+        })
+    }
 
+    /// Merges runs of adjacent small blocks in every file into blocks of at
+    /// most the policy's target size, in a single epoch commit.
+    ///
+    /// Each run is decoded, re-encoded with its shard layout and staged
+    /// before the next one is read, so memory use is bounded by the target
+    /// size. Staged shards embed the committed manifest and are removed again
+    /// if anything fails. Blocks a snapshot still pins are kept by garbage
+    /// collection; the rest of the merged blocks are collected.
+    pub fn compact(&mut self, policy: &CompactionPolicy) -> Result<CompactReport> {
+        let _lock = self.write_lock()?;
+        policy.validate()?;
+
+        let (anchor_blob_zstd, anchor_hash) =
+            manifest_recovery::encode_manifest_snapshot(&self.manifest)?;
+        let mut builder = self.block_builder()?;
         let mut next_manifest = self.manifest.clone();
-        next_manifest.blocks = new_blocks;
-        Self::recalc_total_size(&mut next_manifest)?;
+        let mut obsolete_blocks = Vec::new();
+        let mut staged_names = Vec::new();
+        let mut report = CompactReport::default();
+
+        let staged = (|| -> Result<()> {
+            for file in &mut next_manifest.files {
+                report.blocks_before += file.blocks.len();
+                // Later runs first, so the ranges of earlier ones stay valid.
+                for run in policy.plan(&file.blocks).into_iter().rev() {
+                    let merged_from = &file.blocks[run.clone()];
+                    let mut data = Vec::new();
+                    for block_data in
+                        parallel::map_ordered(merged_from, self.io_options.parallelism, |block| {
+                            self.read_block(block)
+                        })
+                    {
+                        data.extend_from_slice(&block_data?);
+                    }
+                    let job = ChunkJob {
+                        data: &data,
+                        data_shards: merged_from[0].data_shards,
+                        parity_shards: merged_from[0].parity_shards,
+                    };
+                    let mut merged = Vec::with_capacity(1);
+                    for (metadata, pending) in self.build_blocks(&mut builder, &[job])? {
+                        if let Some(pending) = pending {
+                            self.write_block_shards(
+                                &pending,
+                                &self.derived_keys.meta_mac_key,
+                                self.manifest.epoch,
+                                anchor_hash,
+                                &anchor_blob_zstd,
+                                &mut staged_names,
+                            )?;
+                        }
+                        merged.push(metadata);
+                    }
+                    report.blocks_merged += run.len();
+                    obsolete_blocks.extend(file.blocks.splice(run, merged));
+                }
+                report.blocks_after += file.blocks.len();
+            }
+            Ok(())
+        })();
+        if let Err(err) = staged {
+            self.delete_objects_best_effort(staged_names);
+            return Err(err);
+        }
+        if report.blocks_merged == 0 {
+            return Ok(report);
+        }
+
+        if let Err(err) = self.commit_manifest(next_manifest, obsolete_blocks, Vec::new()) {
+            self.delete_objects_best_effort(staged_names);
+            return Err(err);
+        }
+        Ok(report)
+    }
 
-        self.commit_manifest(next_manifest, obsolete_blocks, pending_blocks)
+    /// Runs the dataset's automatic compaction if it is configured and
+    /// fragmentation has reached its ratio. Returns the report when it ran.
+    pub fn compact_if_fragmented(&mut self) -> Result<Option<CompactReport>> {
+        let Some(auto_compact) = self.manifest.settings.auto_compact else {
+            return Ok(None);
+        };
+        if auto_compact.policy.fragmentation(&self.manifest.files) < auto_compact.ratio {
+            return Ok(None);
+        }
+        self.compact(&auto_compact.policy).map(Some)
+    }
+
+    /// Encodes `reader` into blocks and writes their shards immediately,
+    /// embedding the currently committed manifest. Up to `parallelism` chunks
+    /// are encoded at once, which also bounds memory use. Chunks that
+    /// deduplicate against an existing block write nothing. Returns the new
+    /// block list and the number of bytes read; staged names are recorded for
+    /// rollback.
+    fn stage_stream<R: Read>(
+        &self,
+        reader: R,
+        chunking: Chunking,
+        data_shards: usize,
+        parity_shards: usize,
+        staged_names: &mut Vec<String>,
+    ) -> Result<(Vec<BlockMetadata>, u64)> {
+        Self::validate_shard_config(data_shards, parity_shards)?;
+        let mut chunks = ChunkReader::new(reader, chunking)?;
+
+        let (anchor_blob_zstd, anchor_hash) =
+            manifest_recovery::encode_manifest_snapshot(&self.manifest)?;
+        let mut builder = self.block_builder()?;
+        let mut blocks = Vec::new();
+        let mut bytes_read: u64 = 0;
+        let batch_size = self.io_options.parallelism.max(1);
+
+        let mut batch = Vec::with_capacity(batch_size);
+        loop {
+            batch.clear();
+            while batch.len() < batch_size {
+                match chunks.next_chunk()? {
+                    Some(chunk) => batch.push(chunk),
+                    None => break,
+                }
+            }
+            if batch.is_empty() {
+                return Ok((blocks, bytes_read));
+            }
+
+            let jobs: Vec<ChunkJob<'_>> = batch
+                .iter()
+                .map(|chunk| ChunkJob {
+                    data: chunk,
+                    data_shards,
+                    parity_shards,
+                })
+                .collect();
+            for (metadata, pending) in self.build_blocks(&mut builder, &jobs)? {
+                if let Some(pending) = pending {
+                    self.write_block_shards(
+                        &pending,
+                        &self.derived_keys.meta_mac_key,
+                        self.manifest.epoch,
+                        anchor_hash,
+                        &anchor_blob_zstd,
+                        staged_names,
+                    )?;
+                }
+                blocks.push(metadata);
+            }
+            for chunk in &batch {
+                bytes_read = bytes_read
+                    .checked_add(chunk.len() as u64)
+                    .ok_or_else(|| anyhow!("Stream size overflow"))?;
+            }
+        }
+    }
+
+    /// Overwrites bytes starting at `offset` in a single epoch commit.
+    /// Only blocks overlapping the written range are re-encoded, each keeping
+    /// its own shard configuration. Bytes past the current end are appended as
+    /// a new block using the dataset's default layout.
+    pub fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let mut transaction = self.transaction()?;
+        transaction.write_at(offset, data)?;
+        transaction.commit()
+    }
+
+    /// Deletes data in range [offset, offset + length).
+    /// Only the blocks at either edge of the range are decoded, to keep the
+    /// bytes outside it; blocks fully inside the range are simply dropped.
+    pub fn delete_range(&mut self, offset: u64, length: u64) -> Result<()> {
+        let mut transaction = self.transaction()?;
+        transaction.delete_range(offset, length)?;
+        transaction.commit()
     }
 
-    fn write_envelope_file(&self, path: &Path, envelope: &ChunkEnvelope) -> Result<()> {
-        let bytes = chunk_format::encode_envelope(envelope, &self.derived_keys.meta_mac_key)?;
+    /// The contiguous run of `blocks` overlapping `[start, end)` and the file
+    /// offset of its first block. `end` must not exceed the file size; an
+    /// empty range at the very end yields an empty run.
+    pub(crate) fn overlapping_blocks(
+        blocks: &[BlockMetadata],
+        start: u64,
+        end: u64,
+    ) -> Result<(Range<usize>, u64)> {
+        let mut first = None;
+        let mut first_start = 0;
+        let mut stop = blocks.len();
+        let mut current_offset: u64 = 0;
+        for (i, block) in blocks.iter().enumerate() {
+            if current_offset >= end && first.is_some() {
+                stop = i;
+                break;
+            }
+            let block_end = current_offset
+                .checked_add(block.original_size)
+                .ok_or_else(|| anyhow!("Block range overflow"))?;
+            if first.is_none() && block_end > start {
+                first = Some(i);
+                first_start = current_offset;
+            }
+            current_offset = block_end;
+        }
+        match first {
+            Some(first) => Ok((first..stop, first_start)),
+            None => Ok((blocks.len()..blocks.len(), current_offset)),
+        }
+    }
+
+    fn write_envelope_file(&self, name: &str, envelope: &ChunkEnvelope) -> Result<()> {
+        self.write_envelope_file_with_key(name, envelope, &self.derived_keys.meta_mac_key)
+    }
+
+    fn write_envelope_file_with_key(
+        &self,
+        name: &str,
+        envelope: &ChunkEnvelope,
+        meta_mac_key: &[u8; 32],
+    ) -> Result<()> {
+        let bytes = chunk_format::encode_envelope(envelope, meta_mac_key)?;
         let expected_hash = blake3::hash(&bytes).to_hex().to_string();
-        io_guard::write_atomic_verified(path, &bytes, &expected_hash, self.io_options)?;
+        io_guard::write_atomic_verified(
+            self.backend.as_ref(),
+            name,
+            &bytes,
+            &expected_hash,
+            self.io_options,
+        )?;
         Ok(())
     }
 
+    /// Writes every shard envelope of `pending`, embedding the given manifest
+    /// snapshot and MACed under `meta_mac_key`. Paths are recorded as they land
+    /// so callers can roll back.
+    fn write_block_shards(
+        &self,
+        pending: &PendingBlock,
+        meta_mac_key: &[u8; 32],
+        epoch: u64,
+        manifest_hash: [u8; 32],
+        manifest_blob_zstd: &[u8],
+        written_names: &mut Vec<String>,
+    ) -> Result<()> {
+        let total_shards = pending
+            .metadata
+            .data_shards
+            .checked_add(pending.metadata.parity_shards)
+            .ok_or_else(|| anyhow!("Block {} shard count overflow", pending.metadata.id))?;
+        if pending.shards.len() != total_shards {
+            return Err(anyhow!(
+                "Pending block {} shard count mismatch ({} != {})",
+                pending.metadata.id,
+                pending.shards.len(),
+                total_shards
+            ));
+        }
+
+        let indices: Vec<usize> = (0..total_shards).collect();
+        let results = parallel::map_ordered(&indices, self.io_options.parallelism, |&i| {
+            let envelope = ChunkEnvelope::data_shard(
+                pending.metadata.id,
+                i,
+                pending.metadata.data_shards,
+                pending.metadata.parity_shards,
+                pending.shards[i].clone(),
+                epoch,
+                manifest_hash,
+                manifest_blob_zstd.to_vec(),
+            );
+            let name = Self::shard_name(pending.metadata.id, i);
+            self.write_envelope_file_with_key(&name, &envelope, meta_mac_key)?;
+            IoCounters::add(&self.counters.shards_written, 1);
+            Ok(name)
+        });
+
+        // Record every shard that landed before reporting a failure, so the
+        // caller's rollback also removes shards written by other workers.
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(name) => written_names.push(name),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => {
+                IoCounters::add(&self.counters.blocks_written, 1);
+                Ok(())
+            }
+        }
+    }
+
     fn persist_manifest_artifacts(
         &self,
         manifest: &Manifest,
         pending_blocks: &[PendingBlock],
     ) -> Result<()> {
-        let fail_marker = self.root_path.join(TEST_MANIFEST_FAIL_MARKER);
-        if fail_marker.exists() {
+        if self.backend.exists(TEST_MANIFEST_FAIL_MARKER)? {
             return Err(anyhow!(
                 "Manifest commit aborted due to failure marker: {}",
-                fail_marker.display()
+                TEST_MANIFEST_FAIL_MARKER
             ));
         }
 
         let (manifest_blob_zstd, manifest_hash) =
             manifest_recovery::encode_manifest_snapshot(manifest)?;
-        let mut written_paths = Vec::new();
+        // The head moves before any copy of the new manifest exists, so a
+        // writer whose epoch still matches it never missed a commit. If the
+        // commit fails after this, the head is ahead and the next writer
+        // settles the epoch by discovery instead.
+        self.backend
+            .put(HEAD_OBJECT, format!("{}\n", manifest.epoch).as_bytes())?;
+        let mut written_names = Vec::new();
 
         let write_result = (|| -> Result<()> {
             let mut metadata_copies = 0usize;
 
             for pending in pending_blocks {
-                let total_shards = pending
-                    .metadata
-                    .data_shards
-                    .checked_add(pending.metadata.parity_shards)
-                    .ok_or_else(|| anyhow!("Block {} shard count overflow", pending.metadata.id))?;
-                if pending.shards.len() != total_shards {
-                    return Err(anyhow!(
-                        "Pending block {} shard count mismatch ({} != {})",
-                        pending.metadata.id,
-                        pending.shards.len(),
-                        total_shards
-                    ));
-                }
-
-                for (i, shard_payload) in pending.shards.iter().enumerate() {
-                    let envelope = ChunkEnvelope::data_shard(
-                        pending.metadata.id,
-                        i,
-                        pending.metadata.data_shards,
-                        pending.metadata.parity_shards,
-                        shard_payload.clone(),
-                        manifest.epoch,
-                        manifest_hash,
-                        manifest_blob_zstd.clone(),
-                    );
-                    let path = self
-                        .root_path
-                        .join(format!("block_{}_{}.bin", pending.metadata.id, i));
-                    self.write_envelope_file(&path, &envelope)?;
-                    written_paths.push(path);
-                    metadata_copies += 1;
-                }
+                self.write_block_shards(
+                    pending,
+                    &self.derived_keys.meta_mac_key,
+                    manifest.epoch,
+                    manifest_hash,
+                    &manifest_blob_zstd,
+                    &mut written_names,
+                )?;
+                metadata_copies += pending.shards.len();
             }
 
             let mut fallback_idx = 0usize;
             while metadata_copies < METADATA_COPY_TARGET {
-                let path = self
-                    .root_path
-                    .join(format!("meta_{}_{}.bin", manifest.epoch, fallback_idx));
+                let name = format!("meta_{}_{}.bin", manifest.epoch, fallback_idx);
                 fallback_idx = fallback_idx
                     .checked_add(1)
                     .ok_or_else(|| anyhow!("Meta fallback index overflow"))?;
 
-                if path.exists() {
+                if self.backend.exists(&name)? {
                     continue;
                 }
 
@@ -599,8 +2183,8 @@ impl BlockStore {
                     manifest_hash,
                     manifest_blob_zstd.clone(),
                 );
-                self.write_envelope_file(&path, &envelope)?;
-                written_paths.push(path);
+                self.write_envelope_file(&name, &envelope)?;
+                written_names.push(name);
                 metadata_copies += 1;
             }
 
@@ -608,16 +2192,13 @@ impl BlockStore {
         })();
 
         if let Err(err) = write_result {
-            for path in written_paths {
-                let _ = fs::remove_file(path);
-            }
+            self.delete_objects_best_effort(written_names);
             return Err(err);
         }
-
         Ok(())
     }
 
-    fn commit_manifest(
+    pub(crate) fn commit_manifest(
         &mut self,
         mut next_manifest: Manifest,
         obsolete_blocks: Vec<BlockMetadata>,
@@ -628,20 +2209,26 @@ impl BlockStore {
             .epoch
             .checked_add(1)
             .ok_or_else(|| anyhow!("Manifest epoch overflow"))?;
+        next_manifest.update_merkle_root();
         next_manifest.validate()?;
         self.persist_manifest_artifacts(&next_manifest, &pending_blocks)?;
 
         self.manifest = next_manifest;
 
+        // Only blocks whose reference count dropped to zero are collected;
+        // blocks pinned by a snapshot or shared through dedup stay.
+        let reference_counts = self.manifest.reference_counts();
         for block in &obsolete_blocks {
-            self.delete_block_files_best_effort(block);
+            if reference_counts.get(&block.id).copied().unwrap_or(0) == 0 {
+                self.delete_block_files_best_effort(block);
+            }
         }
         self.cleanup_old_meta_files_best_effort(self.manifest.epoch);
 
         Ok(())
     }
 
-    fn validate_shard_config(data_shards: usize, parity_shards: usize) -> Result<()> {
+    pub(crate) fn validate_shard_config(data_shards: usize, parity_shards: usize) -> Result<()> {
         if data_shards == 0 {
             return Err(anyhow!("data_shards must be greater than zero"));
         }
@@ -659,10 +2246,30 @@ impl BlockStore {
         Ok(())
     }
 
+    /// Refuses a layout with more shards per block than the backend has
+    /// failure domains, since two shards of a block would share a domain and
+    /// losing `parity_shards` domains could then lose the block.
+    fn check_failure_domains(&self, data_shards: usize, parity_shards: usize) -> Result<()> {
+        let Some(domains) = self.backend.failure_domains() else {
+            return Ok(());
+        };
+        let total_shards = data_shards + parity_shards;
+        if domains < total_shards {
+            return Err(anyhow!(
+                "A {}+{} layout needs {} failure domains, but {} has {}",
+                data_shards,
+                parity_shards,
+                total_shards,
+                self.backend.location(),
+                domains
+            ));
+        }
+        Ok(())
+    }
+
     fn next_available_id(&self) -> Result<usize> {
         self.manifest
-            .blocks
-            .iter()
+            .referenced_blocks()
             .map(|b| b.id)
             .max()
             .unwrap_or(0)
@@ -678,12 +2285,48 @@ impl BlockStore {
         Ok(id)
     }
 
-    fn recalc_total_size(manifest: &mut Manifest) -> Result<()> {
-        manifest.total_size = manifest
+    /// The selected file, or an error when the namespace has none selected.
+    pub(crate) fn active_file(&self) -> Result<&FileEntry> {
+        let name = self
+            .active_file
+            .as_deref()
+            .ok_or_else(|| anyhow!("No file selected in dataset"))?;
+        self.manifest
+            .file(name)
+            .ok_or_else(|| anyhow!("File '{}' not found in dataset", name))
+    }
+
+    /// Clones the manifest with the selected file's block list replaced.
+    pub(crate) fn manifest_with_active_blocks(
+        &self,
+        blocks: Vec<BlockMetadata>,
+    ) -> Result<Manifest> {
+        let name = self.active_file()?.name.clone();
+        let mut next_manifest = self.manifest.clone();
+        let file = next_manifest
+            .file_mut(&name)
+            .ok_or_else(|| anyhow!("File '{}' not found in dataset", name))?;
+        file.blocks = blocks;
+        Self::recalc_total_size(file)?;
+        Ok(next_manifest)
+    }
+
+    fn validate_file_name(name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow!("File name cannot be empty"));
+        }
+        if name.chars().any(|c| c.is_control()) {
+            return Err(anyhow!("File name cannot contain control characters"));
+        }
+        Ok(())
+    }
+
+    fn recalc_total_size(file: &mut FileEntry) -> Result<()> {
+        file.total_size = file
             .blocks
             .iter()
             .try_fold(0u64, |acc, block| acc.checked_add(block.original_size))
-            .ok_or_else(|| anyhow!("Manifest total_size overflow"))?;
+            .ok_or_else(|| anyhow!("File total_size overflow"))?;
         Ok(())
     }
 }
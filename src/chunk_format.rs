@@ -24,6 +24,7 @@ pub struct ChunkEnvelope {
 }
 
 impl ChunkEnvelope {
+    #[allow(clippy::too_many_arguments)]
     pub fn data_shard(
         block_id: usize,
         shard_index: usize,
@@ -110,20 +111,35 @@ pub fn encode_envelope(envelope: &ChunkEnvelope, meta_mac_key: &[u8; 32]) -> Res
 }
 
 pub fn decode_envelope(bytes: &[u8], meta_mac_key: &[u8; 32]) -> Result<ChunkEnvelope> {
+    let packet = decode_packet(bytes)?;
+    let expected_mac = *blake3::keyed_hash(meta_mac_key, &packet.body).as_bytes();
+    if packet.mac != expected_mac {
+        return Err(anyhow!("Envelope MAC verification failed"));
+    }
+    decode_body(&packet.body)
+}
+
+/// Decodes an envelope without checking its MAC, for holders of the shard
+/// files who do not have the key. Nothing in the result is authenticated;
+/// callers must check the payload some other way, such as against a shard
+/// hash from a verified manifest.
+pub fn decode_envelope_unauthenticated(bytes: &[u8]) -> Result<ChunkEnvelope> {
+    decode_body(&decode_packet(bytes)?.body)
+}
+
+fn decode_packet(bytes: &[u8]) -> Result<ChunkPacket> {
     let config = bincode::config::standard();
     let (packet, used) = bincode::serde::decode_from_slice::<ChunkPacket, _>(bytes, config)?;
     if used != bytes.len() {
         return Err(anyhow!("Unexpected trailing bytes in envelope"));
     }
+    Ok(packet)
+}
 
-    let expected_mac = *blake3::keyed_hash(meta_mac_key, &packet.body).as_bytes();
-    if packet.mac != expected_mac {
-        return Err(anyhow!("Envelope MAC verification failed"));
-    }
-
-    let (body, body_used) =
-        bincode::serde::decode_from_slice::<ChunkBody, _>(&packet.body, config)?;
-    if body_used != packet.body.len() {
+fn decode_body(bytes: &[u8]) -> Result<ChunkEnvelope> {
+    let config = bincode::config::standard();
+    let (body, body_used) = bincode::serde::decode_from_slice::<ChunkBody, _>(bytes, config)?;
+    if body_used != bytes.len() {
         return Err(anyhow!("Unexpected trailing bytes in chunk body"));
     }
     validate_body(&body)?;
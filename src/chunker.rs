@@ -0,0 +1,245 @@
+use anyhow::{Result, anyhow};
+use std::io::{self, Read};
+
+/// Smallest average chunk size content-defined chunking accepts.
+const MIN_CDC_AVG_SIZE: usize = 256;
+
+/// Gear table for the rolling hash, generated with splitmix64 from a fixed
+/// seed so chunk boundaries are stable across builds and platforms.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x1c0d_c1ad_5eed_0001;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// How a byte stream is split into blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunking {
+    /// Cut every `block_size` bytes.
+    Fixed { block_size: usize },
+    /// Cut where the content says so (FastCDC), so an insertion only moves
+    /// the boundaries next to it and unchanged regions produce identical chunks.
+    ContentDefined(CdcParams),
+}
+
+impl Chunking {
+    /// Largest chunk this strategy can produce.
+    pub fn max_chunk_size(&self) -> usize {
+        match self {
+            Chunking::Fixed { block_size } => *block_size,
+            Chunking::ContentDefined(params) => params.max_size,
+        }
+    }
+}
+
+/// Size bounds for content-defined chunking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CdcParams {
+    pub min_size: usize,
+    pub avg_size: usize,
+    pub max_size: usize,
+}
+
+impl CdcParams {
+    /// Derives FastCDC bounds from a hard maximum: the average is a quarter of
+    /// it (rounded down to a power of two) and the minimum a quarter of that.
+    pub fn with_max_size(max_size: usize) -> Result<Self> {
+        let avg_size = prev_power_of_two(max_size / 4);
+        if avg_size < MIN_CDC_AVG_SIZE {
+            return Err(anyhow!(
+                "Content-defined chunking needs a maximum chunk size of at least {} bytes",
+                MIN_CDC_AVG_SIZE * 4
+            ));
+        }
+        Ok(Self {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size,
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(self.min_size < self.avg_size && self.avg_size < self.max_size) {
+            return Err(anyhow!("CDC sizes must satisfy min < avg < max"));
+        }
+        if self.avg_size < MIN_CDC_AVG_SIZE {
+            return Err(anyhow!(
+                "CDC average size must be at least {}",
+                MIN_CDC_AVG_SIZE
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn prev_power_of_two(value: usize) -> usize {
+    if value == 0 {
+        0
+    } else {
+        1 << (usize::BITS - 1 - value.leading_zeros())
+    }
+}
+
+/// Length of the first chunk of `data` under FastCDC normalized chunking.
+///
+/// A stricter mask is used before the average size and a looser one after it,
+/// which pulls chunk sizes towards the average. Returns `data.len()` when no
+/// cut point is found and the data is shorter than `max_size`.
+pub fn cut_point(data: &[u8], params: &CdcParams) -> usize {
+    if data.len() <= params.min_size {
+        return data.len();
+    }
+    let end = data.len().min(params.max_size);
+    let normal = params.avg_size.min(end);
+
+    // The gear hash shifts left, so its high bits cover the most recent bytes.
+    let bits = params.avg_size.trailing_zeros();
+    let mask_strict = !0u64 << (64 - (bits + 1));
+    let mask_loose = !0u64 << (64 - (bits - 1));
+
+    let mut hash: u64 = 0;
+    let mut i = params.min_size;
+    while i < normal {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & mask_strict == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < end {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & mask_loose == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    end
+}
+
+/// Pulls chunks out of a reader, holding at most one maximum-size chunk.
+pub struct ChunkReader<R> {
+    reader: R,
+    chunking: Chunking,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(reader: R, chunking: Chunking) -> Result<Self> {
+        match &chunking {
+            Chunking::Fixed { block_size } if *block_size == 0 => {
+                return Err(anyhow!("block_size must be greater than zero"));
+            }
+            Chunking::ContentDefined(params) => params.validate()?,
+            Chunking::Fixed { .. } => {}
+        }
+        Ok(Self {
+            reader,
+            buffer: Vec::with_capacity(chunking.max_chunk_size()),
+            chunking,
+            eof: false,
+        })
+    }
+
+    /// Returns the next chunk, or `None` once the reader is exhausted.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let max = self.chunking.max_chunk_size();
+        while !self.eof && self.buffer.len() < max {
+            let want = (max - self.buffer.len()) as u64;
+            let read = (&mut self.reader)
+                .take(want)
+                .read_to_end(&mut self.buffer)?;
+            if read == 0 {
+                self.eof = true;
+            }
+        }
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+
+        let cut = match &self.chunking {
+            Chunking::Fixed { block_size } => self.buffer.len().min(*block_size),
+            Chunking::ContentDefined(params) => cut_point(&self.buffer, params),
+        };
+        let rest = self.buffer.split_off(cut);
+        Ok(Some(std::mem::replace(&mut self.buffer, rest)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    fn chunk_all(data: &[u8], chunking: Chunking) -> Vec<Vec<u8>> {
+        let mut reader = ChunkReader::new(data, chunking).unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_fixed_chunking_splits_evenly() {
+        let data = pseudo_random(2_500, 1);
+        let chunks = chunk_all(&data, Chunking::Fixed { block_size: 1_000 });
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![1_000, 1_000, 500]);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn test_cdc_respects_bounds_and_reassembles() {
+        let params = CdcParams::with_max_size(16 * 1024).unwrap();
+        let data = pseudo_random(200_000, 2);
+        let chunks = chunk_all(&data, Chunking::ContentDefined(params));
+
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() > params.min_size && chunk.len() <= params.max_size);
+        }
+    }
+
+    #[test]
+    fn test_cdc_boundaries_resync_after_insertion() {
+        let params = CdcParams::with_max_size(16 * 1024).unwrap();
+        let data = pseudo_random(200_000, 3);
+        let mut shifted = b"a few inserted bytes".to_vec();
+        shifted.extend_from_slice(&data);
+
+        let original = chunk_all(&data, Chunking::ContentDefined(params));
+        let edited = chunk_all(&shifted, Chunking::ContentDefined(params));
+        let shared = edited.iter().filter(|c| original.contains(c)).count();
+        assert!(
+            shared + 2 >= original.len(),
+            "only {} chunks shared",
+            shared
+        );
+    }
+
+    #[test]
+    fn test_cdc_rejects_tiny_maximum() {
+        assert!(CdcParams::with_max_size(512).is_err());
+    }
+}
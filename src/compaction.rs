@@ -0,0 +1,201 @@
+use crate::integrity::{BlockMetadata, FileEntry};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Blocks below this size count as fragments unless a policy says otherwise.
+pub const DEFAULT_SMALL_BLOCK_SIZE: u64 = 1024 * 1024;
+/// Merged blocks are cut at this size unless a policy says otherwise.
+pub const DEFAULT_TARGET_BLOCK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Which blocks compaction merges and how large the merged blocks get.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPolicy {
+    /// Blocks smaller than this are merged with adjacent small blocks.
+    pub small_block_size: u64,
+    /// Upper bound on the size of a merged block.
+    pub target_block_size: u64,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        CompactionPolicy {
+            small_block_size: DEFAULT_SMALL_BLOCK_SIZE,
+            target_block_size: DEFAULT_TARGET_BLOCK_SIZE,
+        }
+    }
+}
+
+impl CompactionPolicy {
+    pub fn validate(&self) -> Result<()> {
+        if self.small_block_size == 0 {
+            return Err(anyhow!("Small block size must be greater than zero"));
+        }
+        if self.target_block_size < self.small_block_size {
+            return Err(anyhow!(
+                "Target block size {} is below the small block size {}",
+                self.target_block_size,
+                self.small_block_size
+            ));
+        }
+        usize::try_from(self.target_block_size)
+            .map_err(|_| anyhow!("Target block size too large for this platform"))?;
+        Ok(())
+    }
+
+    /// Runs of adjacent small blocks to merge, in order. A run only joins
+    /// blocks with the same shard layout and stops before its total would pass
+    /// the target size, so every merged block stays within it. Runs of a
+    /// single block are left alone.
+    pub fn plan(&self, blocks: &[BlockMetadata]) -> Vec<Range<usize>> {
+        let is_small = |block: &BlockMetadata| block.original_size < self.small_block_size;
+        let mut runs = Vec::new();
+        let mut start = 0;
+        while start < blocks.len() {
+            let first = &blocks[start];
+            if !is_small(first) {
+                start += 1;
+                continue;
+            }
+            let mut size = first.original_size;
+            let mut end = start + 1;
+            while let Some(next) = blocks.get(end) {
+                let fits = size
+                    .checked_add(next.original_size)
+                    .is_some_and(|merged| merged <= self.target_block_size);
+                if !is_small(next)
+                    || !fits
+                    || next.data_shards != first.data_shards
+                    || next.parity_shards != first.parity_shards
+                {
+                    break;
+                }
+                size += next.original_size;
+                end += 1;
+            }
+            if end - start > 1 {
+                runs.push(start..end);
+            }
+            start = end;
+        }
+        runs
+    }
+
+    /// Share of all blocks in `files` that `plan` would merge; 0 when there
+    /// are no blocks.
+    pub fn fragmentation(&self, files: &[FileEntry]) -> f64 {
+        let total: usize = files.iter().map(|file| file.blocks.len()).sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let mergeable: usize = files
+            .iter()
+            .flat_map(|file| self.plan(&file.blocks))
+            .map(|run| run.len())
+            .sum();
+        mergeable as f64 / total as f64
+    }
+}
+
+/// Dataset setting that compacts after an edit once the share of mergeable
+/// blocks reaches `ratio`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AutoCompaction {
+    pub policy: CompactionPolicy,
+    /// Fraction of blocks, in `(0, 1]`, that may be fragments before compacting.
+    pub ratio: f64,
+}
+
+impl AutoCompaction {
+    pub fn validate(&self) -> Result<()> {
+        self.policy.validate()?;
+        if !(self.ratio > 0.0 && self.ratio <= 1.0) {
+            return Err(anyhow!(
+                "Fragmentation ratio {} must be greater than 0 and at most 1",
+                self.ratio
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of one compaction run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactReport {
+    pub blocks_before: usize,
+    pub blocks_after: usize,
+    /// Small blocks that were folded into merged blocks.
+    pub blocks_merged: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+
+    fn block(id: usize, size: u64, data_shards: usize) -> BlockMetadata {
+        BlockMetadata {
+            id,
+            original_size: size,
+            data_shards,
+            parity_shards: 2,
+            shard_hashes: Vec::new(),
+            content_key: None,
+            compression: Compression::None,
+        }
+    }
+
+    const POLICY: CompactionPolicy = CompactionPolicy {
+        small_block_size: 10,
+        target_block_size: 20,
+    };
+
+    #[test]
+    fn test_plan_merges_small_runs_up_to_target() {
+        let blocks = vec![
+            block(1, 4, 4),
+            block(2, 4, 4),
+            block(3, 50, 4),
+            block(4, 9, 4),
+            block(5, 9, 4),
+            block(6, 9, 4),
+            block(7, 1, 4),
+        ];
+        assert_eq!(POLICY.plan(&blocks), vec![0..2, 3..5, 5..7]);
+    }
+
+    #[test]
+    fn test_plan_keeps_shard_layouts_apart_and_skips_lone_fragments() {
+        let blocks = vec![block(1, 4, 4), block(2, 4, 8), block(3, 40, 8)];
+        assert!(POLICY.plan(&blocks).is_empty());
+
+        let mut file = FileEntry::new("f");
+        for b in [
+            block(1, 4, 4),
+            block(2, 4, 4),
+            block(3, 40, 4),
+            block(4, 4, 4),
+        ] {
+            file.add_block(b);
+        }
+        assert_eq!(POLICY.fragmentation(&[file]), 0.5);
+        assert_eq!(POLICY.fragmentation(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_policies_reject_inconsistent_values() {
+        let inverted = CompactionPolicy {
+            small_block_size: 30,
+            target_block_size: 20,
+        };
+        assert!(inverted.validate().is_err());
+        assert!(CompactionPolicy::default().validate().is_ok());
+        let auto = |ratio| AutoCompaction {
+            policy: POLICY,
+            ratio,
+        };
+        assert!(auto(0.0).validate().is_err());
+        assert!(auto(f64::NAN).validate().is_err());
+        assert!(auto(0.25).validate().is_ok());
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+/// zstd level used when a policy does not name one.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Compressed output has to be at least this many bytes per 64 smaller than
+/// the input for `Auto` to keep it; anything less is treated as incompressible.
+const AUTO_MIN_SAVINGS_PER_64: usize = 4;
+
+/// Transform applied to a block's plaintext before AONT encryption.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+/// Dataset-wide rule for compressing new blocks.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionPolicy {
+    #[default]
+    None,
+    /// Always store the zstd frame, even if it is not smaller.
+    Zstd { level: i32 },
+    /// Compress with zstd, but keep the raw bytes when they barely shrink.
+    Auto { level: i32 },
+}
+
+impl CompressionPolicy {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            CompressionPolicy::None => Ok(()),
+            CompressionPolicy::Zstd { level } | CompressionPolicy::Auto { level } => {
+                let range = zstd::compression_level_range();
+                if range.contains(level) {
+                    Ok(())
+                } else {
+                    Err(anyhow!(
+                        "zstd level {} is outside the supported range {}..={}",
+                        level,
+                        range.start(),
+                        range.end()
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Applies the policy to one block's plaintext, returning the bytes to
+    /// encrypt and the transform a reader must undo.
+    pub fn apply(&self, data: &[u8]) -> Result<(Vec<u8>, Compression)> {
+        match *self {
+            CompressionPolicy::None => Ok((data.to_vec(), Compression::None)),
+            CompressionPolicy::Zstd { level } => {
+                Ok((zstd::bulk::compress(data, level)?, Compression::Zstd))
+            }
+            CompressionPolicy::Auto { level } => {
+                let compressed = zstd::bulk::compress(data, level)?;
+                let savings = data.len().saturating_sub(compressed.len());
+                if savings > 0 && savings * 64 >= data.len() * AUTO_MIN_SAVINGS_PER_64 {
+                    Ok((compressed, Compression::Zstd))
+                } else {
+                    Ok((data.to_vec(), Compression::None))
+                }
+            }
+        }
+    }
+}
+
+/// Reverses `compression` on decrypted block bytes. Output is capped at
+/// `original_size` so a forged frame cannot expand without bound.
+pub fn restore(data: Vec<u8>, compression: Compression, original_size: usize) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data),
+        Compression::Zstd => zstd::bulk::decompress(&data, original_size)
+            .map_err(|err| anyhow!("Block decompression failed: {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zstd_round_trip_shrinks_repetitive_data() {
+        let data = b"log line: all systems nominal\n".repeat(200);
+        let policy = CompressionPolicy::Zstd {
+            level: DEFAULT_ZSTD_LEVEL,
+        };
+        let (stored, compression) = policy.apply(&data).unwrap();
+        assert_eq!(compression, Compression::Zstd);
+        assert!(stored.len() < data.len() / 4);
+        assert_eq!(restore(stored, compression, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_auto_keeps_incompressible_data_raw() {
+        let mut state = 0x1234_5678_u32;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let policy = CompressionPolicy::Auto {
+            level: DEFAULT_ZSTD_LEVEL,
+        };
+        let (stored, compression) = policy.apply(&noise).unwrap();
+        assert_eq!(compression, Compression::None);
+        assert_eq!(stored, noise);
+
+        let (_, compression) = policy.apply(&[b'a'; 4096]).unwrap();
+        assert_eq!(compression, Compression::Zstd);
+    }
+
+    #[test]
+    fn test_restore_rejects_output_larger_than_declared() {
+        let data = vec![0u8; 1000];
+        let (stored, compression) = CompressionPolicy::Zstd { level: 1 }.apply(&data).unwrap();
+        assert!(restore(stored, compression, 10).is_err());
+    }
+
+    #[test]
+    fn test_policy_rejects_out_of_range_level() {
+        assert!(CompressionPolicy::Zstd { level: 1000 }.validate().is_err());
+        assert!(CompressionPolicy::Auto { level: 3 }.validate().is_ok());
+    }
+}
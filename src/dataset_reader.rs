@@ -0,0 +1,168 @@
+use crate::block_store::{BlockStore, StoreLock};
+use crate::integrity::BlockMetadata;
+use crate::lock::LockTimeout;
+use crate::parallel;
+use crate::scrub::UnrecoverableBlock;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Streaming `Read + Seek` view over one file of a dataset.
+///
+/// Blocks are decoded lazily as the position moves into them. Sequential
+/// reads decode a window of up to `parallelism` blocks at once, so memory use
+/// is bounded by that many blocks rather than the dataset size. The first
+/// decode takes a shared dataset lock that is held until the reader is
+/// dropped, so no writer can collect blocks out from under it.
+pub struct DatasetReader<'a> {
+    store: &'a BlockStore,
+    blocks: &'a [BlockMetadata],
+    block_starts: Vec<u64>,
+    total_size: u64,
+    position: u64,
+    cached: Vec<CachedBlock>,
+    lock: Option<StoreLock>,
+}
+
+struct CachedBlock {
+    index: usize,
+    data: Vec<u8>,
+}
+
+impl<'a> DatasetReader<'a> {
+    pub(crate) fn new(store: &'a BlockStore, blocks: &'a [BlockMetadata]) -> Self {
+        let mut block_starts = Vec::with_capacity(blocks.len());
+        let mut offset: u64 = 0;
+        for block in blocks {
+            block_starts.push(offset);
+            offset = offset.saturating_add(block.original_size);
+        }
+
+        Self {
+            store,
+            blocks,
+            block_starts,
+            total_size: offset,
+            position: 0,
+            cached: Vec::new(),
+            lock: None,
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Index of the block containing `offset`; requires `offset < total_size`.
+    fn block_index_for(&self, offset: u64) -> usize {
+        self.block_starts
+            .partition_point(|start| *start <= offset)
+            .saturating_sub(1)
+    }
+
+    fn load_block(&mut self, index: usize) -> io::Result<&[u8]> {
+        if !self.cached.iter().any(|cached| cached.index == index) {
+            if self.lock.is_none() {
+                self.lock = Some(self.store.read_lock().map_err(store_error)?);
+            }
+            // Read ahead only when moving forward block by block; a seek
+            // elsewhere decodes just the block it lands in.
+            let sequential = index == 0
+                || self
+                    .cached
+                    .last()
+                    .is_some_and(|last| last.index + 1 == index);
+            let window = if sequential {
+                self.store.parallelism()
+            } else {
+                1
+            };
+            let end = index.saturating_add(window).min(self.blocks.len());
+            let store = self.store;
+            let decoded = parallel::map_ordered(&self.blocks[index..end], window, |block| {
+                store.read_block_with_repair(block)
+            });
+
+            self.cached.clear();
+            for (offset, data) in decoded.into_iter().enumerate() {
+                match data {
+                    Ok(data) => self.cached.push(CachedBlock {
+                        index: index + offset,
+                        data,
+                    }),
+                    // Errors past the requested block surface once it is reached.
+                    Err(err) if offset == 0 => return Err(store_error(err)),
+                    Err(_) => break,
+                }
+            }
+        }
+
+        self.cached
+            .iter()
+            .find(|cached| cached.index == index)
+            .map(|cached| cached.data.as_slice())
+            .ok_or_else(|| io::Error::other("Block cache unexpectedly empty"))
+    }
+}
+
+impl Read for DatasetReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.total_size {
+            return Ok(0);
+        }
+
+        let position = self.position;
+        let index = self.block_index_for(position);
+        let block_start = self.block_starts[index];
+        let block_id = self.blocks[index].id;
+        let data = self.load_block(index)?;
+
+        let start = usize::try_from(position - block_start)
+            .map_err(|_| io::Error::other("Block offset too large for this platform"))?;
+        if start >= data.len() {
+            return Err(io::Error::other(format!(
+                "Block {} range is inconsistent",
+                block_id
+            )));
+        }
+
+        let count = usize::min(buf.len(), data.len() - start);
+        buf[..count].copy_from_slice(&data[start..start + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for DatasetReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.total_size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+
+        let target = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.position = target;
+        Ok(target)
+    }
+}
+
+/// Carries a store error through `io::Error`. Lock timeouts and lost blocks
+/// are unwrapped first, so callers can still tell them apart by downcasting.
+fn store_error(err: anyhow::Error) -> io::Error {
+    let err = match err.downcast::<LockTimeout>() {
+        Ok(timeout) => return io::Error::other(timeout),
+        Err(err) => err,
+    };
+    match err.downcast::<UnrecoverableBlock>() {
+        Ok(lost) => io::Error::other(lost),
+        Err(err) => io::Error::other(err),
+    }
+}
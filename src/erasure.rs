@@ -54,6 +54,38 @@ pub fn reconstruct(
     data_shards: usize,
     parity_shards: usize,
 ) -> Result<Vec<u8>> {
+    let recon_shards = reconstruct_shards(shards, data_shards, parity_shards)?;
+
+    // Extract data
+    let mut result = Vec::new();
+    for shard in recon_shards.iter().take(data_shards) {
+        result.extend_from_slice(shard);
+    }
+
+    // Strip padding and length prefix
+    if result.len() < 8 {
+        return Err(anyhow!("Reconstructed data too short"));
+    }
+
+    let len_bytes: [u8; 8] = result[0..8].try_into()?;
+    let original_len = u64::from_le_bytes(len_bytes) as usize;
+
+    if result.len() < 8 + original_len {
+        return Err(anyhow!("Reconstructed data length mismatch"));
+    }
+
+    Ok(result[8..8 + original_len].to_vec())
+}
+
+/// Rebuilds every missing shard, data and parity alike.
+///
+/// `shards` must be a vector of `data_shards + parity_shards` options.
+/// Returns the complete shard set in index order.
+pub fn reconstruct_shards(
+    shards: Vec<Option<Vec<u8>>>,
+    data_shards: usize,
+    parity_shards: usize,
+) -> Result<Vec<Vec<u8>>> {
     if data_shards == 0 {
         return Err(anyhow!("data_shards must be greater than zero"));
     }
@@ -90,29 +122,11 @@ pub fn reconstruct(
     r.reconstruct(&mut recon_shards)
         .map_err(|e| anyhow!("Reconstruction failed: {}", e))?;
 
-    // Extract data
-    let mut result = Vec::new();
-    for (i, shard) in recon_shards.iter().enumerate().take(data_shards) {
-        if let Some(shard) = shard {
-            result.extend_from_slice(shard);
-        } else {
-            return Err(anyhow!("Failed to reconstruct data shard {}", i));
-        }
-    }
-
-    // Strip padding and length prefix
-    if result.len() < 8 {
-        return Err(anyhow!("Reconstructed data too short"));
-    }
-
-    let len_bytes: [u8; 8] = result[0..8].try_into()?;
-    let original_len = u64::from_le_bytes(len_bytes) as usize;
-
-    if result.len() < 8 + original_len {
-        return Err(anyhow!("Reconstructed data length mismatch"));
-    }
-
-    Ok(result[8..8 + original_len].to_vec())
+    recon_shards
+        .into_iter()
+        .enumerate()
+        .map(|(i, shard)| shard.ok_or_else(|| anyhow!("Failed to reconstruct shard {}", i)))
+        .collect()
 }
 
 #[cfg(test)]
@@ -152,6 +166,19 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn test_reconstruct_shards_rebuilds_parity() {
+        let data = b"Parity shards must come back too";
+        let shards = encode(data, 3, 2).unwrap();
+
+        let mut partial: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+        partial[1] = None;
+        partial[4] = None;
+
+        let rebuilt = reconstruct_shards(partial, 3, 2).unwrap();
+        assert_eq!(rebuilt, shards);
+    }
+
     #[test]
     fn test_custom_config() {
         let data = b"Custom Config Data";
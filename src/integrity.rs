@@ -1,6 +1,10 @@
+use crate::compaction::AutoCompaction;
+use crate::compression::{Compression, CompressionPolicy};
+use crate::merkle;
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct BlockMetadata {
@@ -9,21 +13,26 @@ pub struct BlockMetadata {
     pub data_shards: usize,
     pub parity_shards: usize,
     pub shard_hashes: Vec<String>,
+    /// Keyed BLAKE3 of the plaintext, set when the block was written with
+    /// deduplication enabled so later writes of the same bytes can reuse it.
+    pub content_key: Option<String>,
+    /// Transform applied to the plaintext before encryption;
+    /// `original_size` is always the size of the uncompressed bytes.
+    pub compression: Compression,
 }
 
+/// One named file inside a dataset and the ordered blocks holding its bytes.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub struct Manifest {
-    pub epoch: u64,
-    pub file_name: String,
+pub struct FileEntry {
+    pub name: String,
     pub total_size: u64,
     pub blocks: Vec<BlockMetadata>,
 }
 
-impl Manifest {
-    pub fn new(file_name: &str) -> Self {
-        Manifest {
-            epoch: 0,
-            file_name: file_name.to_string(),
+impl FileEntry {
+    pub fn new(name: &str) -> Self {
+        FileEntry {
+            name: name.to_string(),
             total_size: 0,
             blocks: Vec::new(),
         }
@@ -34,11 +43,218 @@ impl Manifest {
         self.blocks.push(block);
     }
 
-    pub fn validate(&self) -> Result<()> {
-        let mut seen_ids = HashSet::new();
+    fn validate(&self) -> Result<()> {
         let mut recomputed_total: u64 = 0;
-
         for block in &self.blocks {
+            recomputed_total = recomputed_total
+                .checked_add(block.original_size)
+                .ok_or_else(|| anyhow!("File '{}' total_size overflow", self.name))?;
+        }
+
+        if recomputed_total != self.total_size {
+            return Err(anyhow!(
+                "File '{}' total_size mismatch: declared {}, actual {}",
+                self.name,
+                self.total_size,
+                recomputed_total
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A named, pinned copy of the namespace as it was at `epoch`.
+///
+/// Pinned file lists travel inside every later manifest, so their blocks are
+/// kept by garbage collection until the snapshot is deleted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    pub epoch: u64,
+    pub files: Vec<FileEntry>,
+}
+
+/// Data shards in the layout new blocks get unless a caller picks another.
+pub const DEFAULT_DATA_SHARDS: usize = 4;
+/// Parity shards in the layout new blocks get unless a caller picks another.
+pub const DEFAULT_PARITY_SHARDS: usize = 4;
+
+/// Reed-Solomon layout of a block: `data_shards` data plus `parity_shards`
+/// parity shards, of which any `parity_shards` may be lost.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redundancy {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+}
+
+impl Default for Redundancy {
+    fn default() -> Self {
+        Redundancy {
+            data_shards: DEFAULT_DATA_SHARDS,
+            parity_shards: DEFAULT_PARITY_SHARDS,
+        }
+    }
+}
+
+impl fmt::Display for Redundancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.data_shards, self.parity_shards)
+    }
+}
+
+/// Dataset-wide behaviour that is committed with the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DatasetSettings {
+    /// Reuse an existing block when a write produces a chunk with the same
+    /// plaintext and shard layout instead of encoding another copy.
+    pub dedup: bool,
+    /// How newly written blocks are compressed before encryption.
+    pub compression: CompressionPolicy,
+    /// Compaction to run after edits once the file layout is fragmented.
+    pub auto_compact: Option<AutoCompaction>,
+    /// Layout for blocks added by edits that do not name one.
+    pub redundancy: Redundancy,
+}
+
+/// Namespace of files committed together at one epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub epoch: u64,
+    pub files: Vec<FileEntry>,
+    pub snapshots: Vec<Snapshot>,
+    pub settings: DatasetSettings,
+    /// Root of the Merkle tree over `files` at `epoch`; see `merkle`.
+    pub merkle_root: [u8; 32],
+}
+
+impl Manifest {
+    /// Creates an uncommitted manifest holding a single empty file.
+    pub fn new(file_name: &str) -> Self {
+        let files = vec![FileEntry::new(file_name)];
+        Manifest {
+            epoch: 0,
+            merkle_root: merkle::root(0, &files),
+            files,
+            snapshots: Vec::new(),
+            settings: DatasetSettings::default(),
+        }
+    }
+
+    /// Creates an uncommitted manifest with no files.
+    pub fn empty() -> Self {
+        Manifest {
+            epoch: 0,
+            files: Vec::new(),
+            snapshots: Vec::new(),
+            settings: DatasetSettings::default(),
+            merkle_root: merkle::root(0, &[]),
+        }
+    }
+
+    pub fn file(&self, name: &str) -> Option<&FileEntry> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn file_mut(&mut self, name: &str) -> Option<&mut FileEntry> {
+        self.files.iter_mut().find(|file| file.name == name)
+    }
+
+    /// Every block referenced by any file, in namespace order.
+    pub fn blocks(&self) -> impl Iterator<Item = &BlockMetadata> {
+        self.files.iter().flat_map(|file| file.blocks.iter())
+    }
+
+    pub fn snapshot(&self, name: &str) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.name == name)
+    }
+
+    /// The most recently created snapshot pinning `epoch`, if any.
+    pub fn snapshot_at_epoch(&self, epoch: u64) -> Option<&Snapshot> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.epoch == epoch)
+    }
+
+    /// Every block referenced by the current files or by any snapshot.
+    /// Blocks shared between them are yielded more than once.
+    pub fn referenced_blocks(&self) -> impl Iterator<Item = &BlockMetadata> {
+        self.blocks().chain(
+            self.snapshots
+                .iter()
+                .flat_map(|snapshot| snapshot.files.iter())
+                .flat_map(|file| file.blocks.iter()),
+        )
+    }
+
+    pub fn references_block(&self, block_id: usize) -> bool {
+        self.referenced_blocks().any(|block| block.id == block_id)
+    }
+
+    /// Number of references to each block id across files and snapshots.
+    /// Deduplicated blocks count once per place they appear.
+    pub fn reference_counts(&self) -> HashMap<usize, usize> {
+        let mut counts = HashMap::new();
+        for block in self.referenced_blocks() {
+            *counts.entry(block.id).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.settings.compression.validate()?;
+        Self::validate_files(&self.files)?;
+
+        let mut seen_snapshots = HashSet::new();
+        for snapshot in &self.snapshots {
+            if snapshot.name.is_empty() {
+                return Err(anyhow!("Snapshot name cannot be empty"));
+            }
+            if !seen_snapshots.insert(snapshot.name.as_str()) {
+                return Err(anyhow!("Duplicate snapshot name '{}'", snapshot.name));
+            }
+            if snapshot.epoch > self.epoch {
+                return Err(anyhow!(
+                    "Snapshot '{}' pins future epoch {}",
+                    snapshot.name,
+                    snapshot.epoch
+                ));
+            }
+            Self::validate_files(&snapshot.files)?;
+        }
+
+        // A block id shared between the current state and a snapshot must
+        // describe the same stored shards.
+        let mut by_id: HashMap<usize, &BlockMetadata> = HashMap::new();
+        for block in self.referenced_blocks() {
+            if let Some(existing) = by_id.insert(block.id, block)
+                && existing != block
+            {
+                return Err(anyhow!(
+                    "Block {} is referenced with conflicting metadata",
+                    block.id
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_files(files: &[FileEntry]) -> Result<()> {
+        let mut seen_ids: HashMap<usize, &BlockMetadata> = HashMap::new();
+        let mut seen_names = HashSet::new();
+
+        for file in files {
+            if file.name.is_empty() {
+                return Err(anyhow!("File name cannot be empty"));
+            }
+            if !seen_names.insert(file.name.as_str()) {
+                return Err(anyhow!("Duplicate file name '{}'", file.name));
+            }
+            file.validate()?;
+        }
+
+        for block in files.iter().flat_map(|file| file.blocks.iter()) {
             let total_shards = block
                 .data_shards
                 .checked_add(block.parity_shards)
@@ -58,21 +274,16 @@ impl Manifest {
                     total_shards
                 ));
             }
-            if !seen_ids.insert(block.id) {
-                return Err(anyhow!("Duplicate block id {}", block.id));
+            // Deduplicated chunks legitimately repeat an id, but every
+            // occurrence must describe the same stored shards.
+            if let Some(existing) = seen_ids.insert(block.id, block)
+                && existing != block
+            {
+                return Err(anyhow!(
+                    "Duplicate block id {} with different metadata",
+                    block.id
+                ));
             }
-
-            recomputed_total = recomputed_total
-                .checked_add(block.original_size)
-                .ok_or_else(|| anyhow!("Manifest total_size overflow"))?;
-        }
-
-        if recomputed_total != self.total_size {
-            return Err(anyhow!(
-                "Manifest total_size mismatch: declared {}, actual {}",
-                self.total_size,
-                recomputed_total
-            ));
         }
 
         Ok(())
@@ -81,8 +292,25 @@ impl Manifest {
     pub fn with_epoch(&self, epoch: u64) -> Self {
         let mut copy = self.clone();
         copy.epoch = epoch;
+        copy.update_merkle_root();
         copy
     }
+
+    /// Recomputes `merkle_root` after the files or the epoch changed.
+    pub fn update_merkle_root(&mut self) {
+        self.merkle_root = merkle::root(self.epoch, &self.files);
+    }
+
+    /// Fails if `merkle_root` does not match the files and epoch.
+    pub fn verify_merkle_root(&self) -> Result<()> {
+        if merkle::root(self.epoch, &self.files) != self.merkle_root {
+            return Err(anyhow!(
+                "Merkle root does not match the block list of epoch {}",
+                self.epoch
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -103,20 +331,91 @@ mod tests {
             data_shards: 1,
             parity_shards: 1,
             shard_hashes,
+            content_key: None,
+            compression: Compression::None,
         };
 
         let mut manifest = Manifest::new("test.txt");
-        manifest.add_block(block);
+        manifest.files[0].add_block(block);
+        assert!(manifest.validate().is_ok());
+    }
+
+    #[test]
+    fn test_manifest_validation_rejects_duplicate_names_and_ids() {
+        let block = BlockMetadata {
+            id: 3,
+            original_size: 4,
+            data_shards: 1,
+            parity_shards: 1,
+            shard_hashes: vec!["a".to_string(), "b".to_string()],
+            content_key: None,
+            compression: Compression::None,
+        };
+
+        let mut manifest = Manifest::empty();
+        manifest.files.push(FileEntry::new("a.txt"));
+        manifest.files.push(FileEntry::new("a.txt"));
+        let err = manifest.validate().expect_err("duplicate names");
+        assert!(err.to_string().contains("Duplicate file name"));
+
+        manifest.files[1].name = "b.txt".to_string();
+        manifest.files[0].add_block(block.clone());
+        manifest.files[1].add_block(block.clone());
+        manifest
+            .validate()
+            .expect("identical shared block is allowed");
+        assert_eq!(manifest.reference_counts()[&3], 2);
+
+        let mut conflicting = block;
+        conflicting.original_size = 5;
+        manifest.files[1].blocks.clear();
+        manifest.files[1].total_size = 0;
+        manifest.files[1].add_block(conflicting);
+        let err = manifest.validate().expect_err("duplicate ids");
+        assert!(err.to_string().contains("Duplicate block id"));
+    }
+
+    #[test]
+    fn test_manifest_validation_checks_snapshots() {
+        let block = BlockMetadata {
+            id: 5,
+            original_size: 4,
+            data_shards: 1,
+            parity_shards: 1,
+            shard_hashes: vec!["a".to_string(), "b".to_string()],
+            content_key: None,
+            compression: Compression::None,
+        };
+
+        let mut manifest = Manifest::new("a.txt");
+        manifest.epoch = 3;
+        manifest.files[0].add_block(block.clone());
+        manifest.snapshots.push(Snapshot {
+            name: "before".to_string(),
+            epoch: 2,
+            files: manifest.files.clone(),
+        });
         assert!(manifest.validate().is_ok());
+        assert!(manifest.references_block(5));
+        assert_eq!(manifest.snapshot_at_epoch(2).unwrap().name, "before");
+
+        manifest.snapshots[0].files[0].blocks[0].shard_hashes[0] = "c".to_string();
+        let err = manifest.validate().expect_err("conflicting metadata");
+        assert!(err.to_string().contains("conflicting metadata"));
+
+        manifest.snapshots[0].files = manifest.files.clone();
+        manifest.snapshots[0].epoch = 4;
+        let err = manifest.validate().expect_err("future epoch");
+        assert!(err.to_string().contains("future epoch"));
     }
 
     #[test]
     fn test_manifest_with_epoch_preserves_content() {
         let mut manifest = Manifest::new("demo.txt");
-        manifest.total_size = 42;
+        manifest.files[0].total_size = 42;
         let bumped = manifest.with_epoch(7);
         assert_eq!(bumped.epoch, 7);
-        assert_eq!(bumped.file_name, "demo.txt");
-        assert_eq!(bumped.total_size, 42);
+        assert_eq!(bumped.files[0].name, "demo.txt");
+        assert_eq!(bumped.files[0].total_size, 42);
     }
 }
@@ -1,12 +1,11 @@
+use crate::backend::ShardBackend;
+use crate::parallel;
 use anyhow::{Result, anyhow};
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
-static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// How long a store waits for a contended dataset lock by default.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IoMode {
@@ -20,6 +19,12 @@ pub struct IoOptions {
     pub read_retries: usize,
     pub write_retries: usize,
     pub durability_sync: bool,
+    /// Rewrite shards that a successful read had to reconstruct around.
+    pub read_repair: bool,
+    /// Upper bound on worker threads used to encode, decode and write blocks.
+    pub parallelism: usize,
+    /// How long to wait for the dataset lock before giving up.
+    pub lock_timeout: Duration,
 }
 
 impl IoOptions {
@@ -29,6 +34,9 @@ impl IoOptions {
             read_retries: 3,
             write_retries: 3,
             durability_sync: true,
+            read_repair: false,
+            parallelism: parallel::default_parallelism(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
         }
     }
 
@@ -38,9 +46,28 @@ impl IoOptions {
             read_retries: 1,
             write_retries: 1,
             durability_sync: false,
+            read_repair: false,
+            parallelism: parallel::default_parallelism(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
         }
     }
 
+    pub fn with_read_repair(mut self, enabled: bool) -> Self {
+        self.read_repair = enabled;
+        self
+    }
+
+    /// Caps worker threads; zero is treated as one.
+    pub fn with_parallelism(mut self, workers: usize) -> Self {
+        self.parallelism = workers.max(1);
+        self
+    }
+
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
     fn read_attempts(&self) -> usize {
         self.read_retries.max(1)
     }
@@ -56,25 +83,25 @@ impl Default for IoOptions {
     }
 }
 
+/// Reads `name` from `backend`, retrying until its BLAKE3 hash matches.
+/// Returns `None` when the object is missing or never verifies.
 pub fn read_verified(
-    path: &Path,
+    backend: &dyn ShardBackend,
+    name: &str,
     expected_hash: &str,
     options: IoOptions,
 ) -> Result<Option<Vec<u8>>> {
     let attempts = options.read_attempts();
     for attempt in 0..attempts {
-        match fs::read(path) {
-            Ok(data) => {
+        match backend.get(name) {
+            Ok(Some(data)) => {
                 let hash = blake3::hash(&data).to_hex().to_string();
                 if hash == expected_hash {
                     return Ok(Some(data));
                 }
             }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    return Ok(None);
-                }
-            }
+            Ok(None) => return Ok(None),
+            Err(_) => {}
         }
 
         if attempt + 1 < attempts {
@@ -85,8 +112,11 @@ pub fn read_verified(
     Ok(None)
 }
 
+/// Stores `bytes` under `name` and reads them back to check the hash,
+/// retrying the whole round trip. Atomicity comes from the backend's `put`.
 pub fn write_atomic_verified(
-    path: &Path,
+    backend: &dyn ShardBackend,
+    name: &str,
     bytes: &[u8],
     expected_hash: &str,
     options: IoOptions,
@@ -94,37 +124,21 @@ pub fn write_atomic_verified(
     let attempts = options.write_attempts();
     let mut last_err: Option<anyhow::Error> = None;
 
-    for attempt in 0..attempts {
-        let temp_path = make_temp_path(path, attempt)?;
-
+    for _ in 0..attempts {
         let result = (|| -> Result<()> {
-            let mut file = OpenOptions::new()
-                .create_new(true)
-                .write(true)
-                .open(&temp_path)?;
-            file.write_all(bytes)?;
-            if options.durability_sync {
-                file.sync_data()?;
-            }
-            drop(file);
+            backend.put(name, bytes)?;
 
-            fs::rename(&temp_path, path)?;
-
-            if options.durability_sync {
-                sync_parent_dir(path)?;
-            }
-
-            let persisted = fs::read(path)?;
+            let persisted = backend
+                .get(name)?
+                .ok_or_else(|| anyhow!("Object {} vanished after write", name))?;
             let persisted_hash = blake3::hash(&persisted).to_hex().to_string();
             if persisted_hash != expected_hash {
-                return Err(anyhow!("Verification hash mismatch at {}", path.display()));
+                return Err(anyhow!("Verification hash mismatch at {}", name));
             }
 
             Ok(())
         })();
 
-        let _ = fs::remove_file(&temp_path);
-
         match result {
             Ok(_) => return Ok(()),
             Err(err) => {
@@ -134,8 +148,9 @@ pub fn write_atomic_verified(
     }
 
     Err(anyhow!(
-        "Failed to atomically write {} after {} attempts: {}",
-        path.display(),
+        "Failed to atomically write {} in {} after {} attempts: {}",
+        name,
+        backend.location(),
         attempts,
         last_err
             .map(|e| e.to_string())
@@ -143,78 +158,52 @@ pub fn write_atomic_verified(
     ))
 }
 
-fn sync_parent_dir(path: &Path) -> Result<()> {
-    let parent = path
-        .parent()
-        .ok_or_else(|| anyhow!("Path has no parent: {}", path.display()))?;
-    let dir = OpenOptions::new().read(true).open(parent)?;
-    dir.sync_all()?;
-    Ok(())
-}
-
-fn make_temp_path(path: &Path, attempt: usize) -> Result<PathBuf> {
-    let file_name = path
-        .file_name()
-        .ok_or_else(|| anyhow!("Path has no file name: {}", path.display()))?
-        .to_string_lossy();
-
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| anyhow!("System time error: {}", e))?;
-    let nonce = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
-
-    let temp_name = format!(
-        ".{}.tmp.{}.{}.{}.{}",
-        file_name,
-        std::process::id(),
-        now.as_secs(),
-        now.subsec_nanos(),
-        nonce + attempt as u64
-    );
-
-    Ok(path.with_file_name(temp_name))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::{LocalDirBackend, MemoryBackend};
+    use std::fs;
     use tempfile::tempdir;
 
     #[test]
     fn test_write_atomic_verified_rejects_bad_expected_hash() {
-        let dir = tempdir().expect("tempdir");
-        let path = dir.path().join("file.bin");
+        let backend = MemoryBackend::new();
 
-        let err = write_atomic_verified(&path, b"payload", "bad-hash", IoOptions::strict())
-            .expect_err("write should fail verification");
+        let err = write_atomic_verified(
+            &backend,
+            "file.bin",
+            b"payload",
+            "bad-hash",
+            IoOptions::strict(),
+        )
+        .expect_err("write should fail verification");
 
         assert!(err.to_string().contains("Failed to atomically write"));
     }
 
     #[test]
     fn test_read_verified_missing_returns_none() {
-        let dir = tempdir().expect("tempdir");
-        let path = dir.path().join("missing.bin");
+        let backend = MemoryBackend::new();
 
-        let result =
-            read_verified(&path, "irrelevant", IoOptions::strict()).expect("read should not error");
+        let result = read_verified(&backend, "missing.bin", "irrelevant", IoOptions::strict())
+            .expect("read should not error");
         assert!(result.is_none());
     }
 
     #[test]
     fn test_read_verified_hash_mismatch_returns_none() {
-        let dir = tempdir().expect("tempdir");
-        let path = dir.path().join("mismatch.bin");
-        fs::write(&path, b"data").expect("write");
+        let backend = MemoryBackend::new();
+        backend.put("mismatch.bin", b"data").expect("write");
 
-        let result =
-            read_verified(&path, "bad-hash", IoOptions::strict()).expect("read should not error");
+        let result = read_verified(&backend, "mismatch.bin", "bad-hash", IoOptions::strict())
+            .expect("read should not error");
         assert!(result.is_none());
     }
 
     #[test]
     fn test_read_verified_recovers_after_transient_mismatch() {
         let dir = tempdir().expect("tempdir");
+        let backend = LocalDirBackend::new(dir.path().to_path_buf(), false);
         let path = dir.path().join("flaky.bin");
         fs::write(&path, b"bad").expect("write bad");
 
@@ -231,8 +220,11 @@ mod tests {
             read_retries: 5,
             write_retries: 1,
             durability_sync: true,
+            read_repair: false,
+            parallelism: 1,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
         };
-        let data = read_verified(&path, &expected, options).expect("read");
+        let data = read_verified(&backend, "flaky.bin", &expected, options).expect("read");
         writer.join().expect("join");
 
         assert_eq!(data, Some(b"good".to_vec()));
@@ -4,6 +4,7 @@ const ROOT_KEY_BYTES: usize = 32;
 const ROOT_KEY_HEX_LEN: usize = ROOT_KEY_BYTES * 2;
 const AONT_MASK_CONTEXT: &str = "ironclad/v2/aont-mask";
 const META_MAC_CONTEXT: &str = "ironclad/v2/meta-mac";
+const DEDUP_CONTEXT: &str = "ironclad/v2/dedup";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RootKey(pub [u8; ROOT_KEY_BYTES]);
@@ -12,6 +13,8 @@ pub struct RootKey(pub [u8; ROOT_KEY_BYTES]);
 pub struct DerivedKeys {
     pub aont_mask_key: [u8; ROOT_KEY_BYTES],
     pub meta_mac_key: [u8; ROOT_KEY_BYTES],
+    /// Keys the plaintext fingerprints used to find duplicate chunks.
+    pub dedup_key: [u8; ROOT_KEY_BYTES],
 }
 
 impl RootKey {
@@ -37,6 +40,7 @@ impl RootKey {
         DerivedKeys {
             aont_mask_key: blake3::derive_key(AONT_MASK_CONTEXT, &self.0),
             meta_mac_key: blake3::derive_key(META_MAC_CONTEXT, &self.0),
+            dedup_key: blake3::derive_key(DEDUP_CONTEXT, &self.0),
         }
     }
 }
@@ -81,5 +85,7 @@ mod tests {
     fn test_derive_separates_subkeys() {
         let key = RootKey([7u8; 32]).derive();
         assert_ne!(key.aont_mask_key, key.meta_mac_key);
+        assert_ne!(key.dedup_key, key.aont_mask_key);
+        assert_ne!(key.dedup_key, key.meta_mac_key);
     }
 }
@@ -1,8 +1,21 @@
 pub mod aont;
+pub mod archive;
+pub mod audit;
+pub mod backend;
 pub mod block_store;
 pub mod chunk_format;
+pub mod chunker;
+pub mod compaction;
+pub mod compression;
+pub mod dataset_reader;
 pub mod erasure;
 pub mod integrity;
 pub mod io_guard;
 pub mod key_material;
+pub mod lock;
+pub mod manifest_legacy;
 pub mod manifest_recovery;
+pub mod merkle;
+pub mod parallel;
+pub mod scrub;
+pub mod transaction;
@@ -0,0 +1,343 @@
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Lock file a `LocalDirBackend` keeps next to its objects.
+pub const LOCK_FILE_NAME: &str = ".ironclad.lock";
+
+/// Longest pause between two attempts to take a contended lock.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Any number of readers at once.
+    Shared,
+    /// One writer and nobody else.
+    Exclusive,
+}
+
+impl fmt::Display for LockMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockMode::Shared => f.write_str("shared"),
+            LockMode::Exclusive => f.write_str("exclusive"),
+        }
+    }
+}
+
+/// Returned when a lock stays contended for the whole wait timeout.
+#[derive(Debug)]
+pub struct LockTimeout {
+    pub mode: LockMode,
+    pub location: String,
+    pub waited: Duration,
+    /// Record left by the last exclusive holder, if it wrote one.
+    pub holder: Option<String>,
+}
+
+impl fmt::Display for LockTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {:?} waiting for the {} lock on {}",
+            self.waited, self.mode, self.location
+        )?;
+        if let Some(holder) = &self.holder {
+            write!(f, " (last exclusive holder: {})", holder)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LockTimeout {}
+
+/// A held dataset lock; dropping it releases the lock.
+pub struct DatasetLock {
+    mode: LockMode,
+    stale_holder: Option<String>,
+    _held: Vec<Box<dyn Send + Sync>>,
+}
+
+impl DatasetLock {
+    /// A handle that excludes nobody, for backends without a lock primitive.
+    pub fn unlocked(mode: LockMode) -> Self {
+        Self {
+            mode,
+            stale_holder: None,
+            _held: Vec::new(),
+        }
+    }
+
+    /// Combines the locks of several domains into one handle.
+    pub fn combine(mode: LockMode, parts: Vec<DatasetLock>) -> Self {
+        let stale_holder = parts.iter().find_map(|part| part.stale_holder.clone());
+        Self {
+            mode,
+            stale_holder,
+            _held: parts
+                .into_iter()
+                .map(|part| Box::new(part) as Box<dyn Send + Sync>)
+                .collect(),
+        }
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Holder record found when the exclusive lock was taken. A clean release
+    /// clears the record, so one that is still there was left by a holder that
+    /// died while holding the lock; the operating system has already released
+    /// it, and the record is overwritten.
+    pub fn stale_holder(&self) -> Option<&str> {
+        self.stale_holder.as_deref()
+    }
+}
+
+impl fmt::Debug for DatasetLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatasetLock")
+            .field("mode", &self.mode)
+            .field("stale_holder", &self.stale_holder)
+            .finish()
+    }
+}
+
+/// Retries `attempt` with growing pauses until it succeeds or `timeout` passes.
+/// `attempt` returns `Ok(None)` while the lock is contended.
+fn wait_for<T>(
+    timeout: Duration,
+    mut attempt: impl FnMut() -> Result<Option<T>>,
+) -> Result<Option<T>> {
+    let deadline = Instant::now() + timeout;
+    let mut pause = Duration::from_millis(1);
+    loop {
+        if let Some(done) = attempt()? {
+            return Ok(Some(done));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(pause.min(deadline - now));
+        pause = (pause * 2).min(MAX_RETRY_INTERVAL);
+    }
+}
+
+/// Takes an advisory lock on `path`, creating the file if needed.
+///
+/// The lock belongs to the open file, so the operating system releases it when
+/// the holding process exits, even if it crashes. Exclusive holders write a
+/// short record naming themselves that is shown to anyone left waiting.
+pub fn lock_file(path: &Path, mode: LockMode, timeout: Duration) -> Result<DatasetLock> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| anyhow!("Cannot open lock file {}: {}", path.display(), e))?;
+
+    let acquired = wait_for(timeout, || {
+        let attempt = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match attempt {
+            Ok(()) => Ok(Some(())),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(anyhow!("Cannot lock {}: {}", path.display(), e)),
+        }
+    })?;
+    if acquired.is_none() {
+        return Err(LockTimeout {
+            mode,
+            location: path.display().to_string(),
+            waited: timeout,
+            holder: read_holder(&mut file),
+        }
+        .into());
+    }
+
+    let mut stale_holder = None;
+    if mode == LockMode::Exclusive {
+        stale_holder = read_holder(&mut file);
+        write_holder(&mut file)?;
+    }
+    Ok(DatasetLock {
+        mode,
+        stale_holder,
+        _held: vec![Box::new(FileLock { file, mode })],
+    })
+}
+
+fn read_holder(file: &mut File) -> Option<String> {
+    let mut record = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut record).ok()?;
+    let record = record.trim();
+    (!record.is_empty()).then(|| record.to_string())
+}
+
+fn write_holder(file: &mut File) -> Result<()> {
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    writeln!(file, "pid {} since unix time {}", std::process::id(), since)?;
+    file.flush()?;
+    Ok(())
+}
+
+struct FileLock {
+    file: File,
+    mode: LockMode,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+/// Reader-writer lock for stores that share one in-process backend.
+#[derive(Debug, Default)]
+pub struct MemoryLock {
+    state: Mutex<MemoryLockState>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct MemoryLockState {
+    readers: usize,
+    writer: bool,
+}
+
+impl MemoryLock {
+    pub fn acquire(self: &Arc<Self>, mode: LockMode, timeout: Duration) -> Result<DatasetLock> {
+        let poisoned = || anyhow!("Memory backend lock poisoned");
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().map_err(|_| poisoned())?;
+        loop {
+            let free = match mode {
+                LockMode::Shared => !state.writer,
+                LockMode::Exclusive => !state.writer && state.readers == 0,
+            };
+            if free {
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(LockTimeout {
+                    mode,
+                    location: "<memory>".to_string(),
+                    waited: timeout,
+                    holder: None,
+                }
+                .into());
+            }
+            state = self
+                .released
+                .wait_timeout(state, deadline - now)
+                .map_err(|_| poisoned())?
+                .0;
+        }
+        match mode {
+            LockMode::Shared => state.readers += 1,
+            LockMode::Exclusive => state.writer = true,
+        }
+        Ok(DatasetLock {
+            mode,
+            stale_holder: None,
+            _held: vec![Box::new(MemoryLockGuard {
+                lock: Arc::clone(self),
+                mode,
+            })],
+        })
+    }
+}
+
+struct MemoryLockGuard {
+    lock: Arc<MemoryLock>,
+    mode: LockMode,
+}
+
+impl Drop for MemoryLockGuard {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.lock.state.lock() {
+            match self.mode {
+                LockMode::Shared => state.readers = state.readers.saturating_sub(1),
+                LockMode::Exclusive => state.writer = false,
+            }
+        }
+        self.lock.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const SHORT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_file_lock_excludes_writers_but_shares_readers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE_NAME);
+
+        let first = lock_file(&path, LockMode::Shared, SHORT).unwrap();
+        let second = lock_file(&path, LockMode::Shared, SHORT).unwrap();
+        let err = lock_file(&path, LockMode::Exclusive, SHORT).unwrap_err();
+        assert!(err.is::<LockTimeout>());
+        drop((first, second));
+
+        let writer = lock_file(&path, LockMode::Exclusive, SHORT).unwrap();
+        let err = lock_file(&path, LockMode::Shared, SHORT).unwrap_err();
+        let timeout = err.downcast_ref::<LockTimeout>().unwrap();
+        assert!(timeout.holder.as_deref().unwrap().contains("pid"));
+        drop(writer);
+        assert!(lock_file(&path, LockMode::Shared, SHORT).is_ok());
+    }
+
+    #[test]
+    fn test_leftover_holder_record_is_reported_as_stale() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(LOCK_FILE_NAME);
+        std::fs::write(&path, "pid 999999 since unix time 1\n").unwrap();
+
+        let lock = lock_file(&path, LockMode::Exclusive, SHORT).unwrap();
+        assert_eq!(lock.stale_holder(), Some("pid 999999 since unix time 1"));
+        drop(lock);
+
+        let lock = lock_file(&path, LockMode::Exclusive, SHORT).unwrap();
+        assert_eq!(lock.stale_holder(), None);
+    }
+
+    #[test]
+    fn test_memory_lock_waits_for_release() {
+        let lock = Arc::new(MemoryLock::default());
+        let writer = lock.acquire(LockMode::Exclusive, SHORT).unwrap();
+        assert!(lock.acquire(LockMode::Shared, SHORT).is_err());
+
+        let waiter = {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || {
+                lock.acquire(LockMode::Shared, Duration::from_secs(5))
+                    .is_ok()
+            })
+        };
+        thread::sleep(SHORT);
+        drop(writer);
+        assert!(waiter.join().unwrap());
+    }
+}
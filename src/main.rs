@@ -1,32 +1,118 @@
 use anyhow::{Result, anyhow};
 use clap::{Parser, Subcommand, ValueEnum};
-use ironclad::block_store::BlockStore;
+use ironclad::archive;
+use ironclad::audit::{AuditResponse, AuditSecret, Challenge};
+use ironclad::backend::{LocalDirBackend, ShardBackend, StripedBackend};
+use ironclad::block_store::{BlockStore, IoStats};
+use ironclad::chunker::{CdcParams, Chunking};
+use ironclad::compaction::{
+    AutoCompaction, CompactionPolicy, DEFAULT_SMALL_BLOCK_SIZE, DEFAULT_TARGET_BLOCK_SIZE,
+};
+use ironclad::compression::{CompressionPolicy, DEFAULT_ZSTD_LEVEL};
+use ironclad::dataset_reader::DatasetReader;
+use ironclad::integrity::{DEFAULT_DATA_SHARDS, DEFAULT_PARITY_SHARDS, Redundancy};
 use ironclad::io_guard::IoOptions;
 use ironclad::key_material::RootKey;
-use std::fs;
-use std::path::PathBuf;
+use ironclad::lock::{LockMode, LockTimeout};
+use ironclad::manifest_recovery::{ManifestCandidate, ManifestDiscovery, discover_manifest};
+use ironclad::merkle::RangeProof;
+use ironclad::scrub::{
+    BlockHealth, BlockScrubReport, RepairOutcome, RepairReport, UnrecoverableBlock,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
 const STORAGE_DIR: &str = "storage";
-const DEFAULT_DATA_SHARDS: usize = 4;
-const DEFAULT_PARITY_SHARDS: usize = 4;
+const DEFAULT_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+const LAYOUT_FILE: &str = "roots.json";
+const DEFAULT_AUDIT_SAMPLES: usize = 64;
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+/// Shard roots of a dataset striped across failure domains, kept in
+/// `storage/<dataset>/roots.json`.
+#[derive(Serialize, Deserialize, Debug)]
+struct DatasetLayout {
+    roots: Vec<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, Default, ValueEnum)]
 enum IoModeArg {
+    #[default]
     Strict,
     Fast,
 }
 
-impl Default for IoModeArg {
-    fn default() -> Self {
-        Self::Strict
+impl IoModeArg {
+    fn to_io_options(self, tuning: Tuning) -> IoOptions {
+        let mut options = match self {
+            IoModeArg::Strict => IoOptions::strict(),
+            IoModeArg::Fast => IoOptions::fast(),
+        };
+        if let Some(jobs) = tuning.jobs {
+            options = options.with_parallelism(jobs);
+        }
+        if let Some(timeout) = tuning.lock_timeout {
+            options = options.with_lock_timeout(timeout);
+        }
+        options
     }
 }
 
-impl IoModeArg {
-    fn to_io_options(self) -> IoOptions {
+/// Global flags that adjust the I/O options of every command.
+#[derive(Copy, Clone, Debug)]
+struct Tuning {
+    jobs: Option<usize>,
+    lock_timeout: Option<Duration>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per command, including failures
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, Default, ValueEnum)]
+enum ChunkingArg {
+    /// Cut every --block-size bytes
+    #[default]
+    Fixed,
+    /// Content-defined (FastCDC) cut points, at most --block-size bytes apart
+    Cdc,
+}
+
+impl ChunkingArg {
+    fn to_chunking(self, block_size: usize) -> Result<Chunking> {
         match self {
-            IoModeArg::Strict => IoOptions::strict(),
-            IoModeArg::Fast => IoOptions::fast(),
+            ChunkingArg::Fixed => Ok(Chunking::Fixed { block_size }),
+            ChunkingArg::Cdc => Ok(Chunking::ContentDefined(CdcParams::with_max_size(
+                block_size,
+            )?)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum CompressionArg {
+    None,
+    /// Always zstd-compress blocks
+    Zstd,
+    /// zstd-compress blocks unless they turn out incompressible
+    Auto,
+}
+
+impl CompressionArg {
+    fn to_policy(self, level: i32) -> CompressionPolicy {
+        match self {
+            CompressionArg::None => CompressionPolicy::None,
+            CompressionArg::Zstd => CompressionPolicy::Zstd { level },
+            CompressionArg::Auto => CompressionPolicy::Auto { level },
         }
     }
 }
@@ -36,6 +122,15 @@ impl IoModeArg {
 struct Cli {
     #[arg(long = "root-key-hex", global = true)]
     root_key_hex: Option<String>,
+    /// Worker threads for block encoding, decoding and shard writes (defaults to the CPU count)
+    #[arg(long, global = true)]
+    jobs: Option<usize>,
+    /// Seconds to wait for another process holding the dataset lock
+    #[arg(long = "lock-timeout", global = true)]
+    lock_timeout: Option<u64>,
+    /// Output format for status and errors
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
     #[command(subcommand)]
     command: Commands,
 }
@@ -57,16 +152,45 @@ enum Commands {
             default_value_t = DEFAULT_PARITY_SHARDS
         )]
         parity: usize,
+        /// Maximum plaintext bytes per block
+        #[arg(long = "block-size", default_value_t = DEFAULT_BLOCK_SIZE)]
+        block_size: usize,
+        /// How the input is split into blocks
+        #[arg(long, value_enum, default_value_t = ChunkingArg::Fixed)]
+        chunking: ChunkingArg,
+        /// Store identical chunks once and share the block between references
+        #[arg(long)]
+        dedup: bool,
+        /// Compress blocks before encryption (recorded as the dataset setting)
+        #[arg(long, value_enum)]
+        compression: Option<CompressionArg>,
+        #[arg(long = "compression-level", default_value_t = DEFAULT_ZSTD_LEVEL)]
+        compression_level: i32,
+        /// Comma-separated directories to stripe shards across (shard i goes to root i % n)
+        #[arg(long, value_delimiter = ',')]
+        roots: Vec<PathBuf>,
+        /// Overwrite the dataset if it already holds data
+        #[arg(long)]
+        replace: bool,
         #[arg(long, default_value = "default")]
         dataset: String,
         #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
         io_mode: IoModeArg,
     },
-    /// Recover and decrypt a dataset into an output file
+    /// Recover and decrypt a dataset into an output file ("-" for stdout)
     Read {
         output_file: PathBuf,
         #[arg(long, default_value = "default")]
         dataset: String,
+        /// File within the dataset (defaults to the first one)
+        #[arg(long)]
+        file: Option<String>,
+        /// Read the content pinned by a snapshot at this epoch
+        #[arg(long)]
+        epoch: Option<u64>,
+        /// Rewrite shards that had to be reconstructed around
+        #[arg(long = "read-repair")]
+        read_repair: bool,
         #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
         io_mode: IoModeArg,
     },
@@ -74,132 +198,2417 @@ enum Commands {
     Insert {
         offset: u64,
         text: String,
+        /// Data shards for new blocks (defaults to the dataset's layout)
+        #[arg(short = 'd', long = "data")]
+        data: Option<usize>,
+        /// Parity shards for new blocks (defaults to the dataset's layout)
+        #[arg(short = 'p', long = "parity")]
+        parity: Option<usize>,
+        /// Override the dataset's layout without asking
+        #[arg(long)]
+        force: bool,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// File within the dataset (defaults to the first one)
+        #[arg(long)]
+        file: Option<String>,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Overwrite bytes at an offset, extending the file if needed
+    Overwrite {
+        offset: u64,
+        text: String,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// File within the dataset (defaults to the first one)
+        #[arg(long)]
+        file: Option<String>,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Delete a byte range
+    Delete {
+        offset: u64,
+        length: u64,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// File within the dataset (defaults to the first one)
+        #[arg(long)]
+        file: Option<String>,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Apply a script of inserts, overwrites and deletes as a single commit
+    Apply {
+        /// Edit script: one `insert <offset> <text>`, `overwrite <offset> <text>`
+        /// or `delete <offset> <length>` per line
+        script: PathBuf,
+        /// Data shards for inserted blocks (defaults to the dataset's layout)
+        #[arg(short = 'd', long = "data")]
+        data: Option<usize>,
+        /// Parity shards for inserted blocks (defaults to the dataset's layout)
+        #[arg(short = 'p', long = "parity")]
+        parity: Option<usize>,
+        /// Override the dataset's layout without asking
+        #[arg(long)]
+        force: bool,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// File within the dataset (defaults to the first one)
+        #[arg(long)]
+        file: Option<String>,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// List the files stored in a dataset
+    Ls {
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Show the manifest, block layout and metadata copies of a dataset without reading file data
+    #[command(alias = "stat")]
+    Info {
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Add a file to a dataset, replacing any file with the same name
+    Put {
+        input_file: PathBuf,
+        /// Name inside the dataset (defaults to the input file name)
+        #[arg(long)]
+        name: Option<String>,
+        /// Data shards for new blocks (defaults to the dataset's layout)
+        #[arg(short = 'd', long = "data")]
+        data: Option<usize>,
+        /// Parity shards for new blocks (defaults to the dataset's layout)
+        #[arg(short = 'p', long = "parity")]
+        parity: Option<usize>,
+        /// Override an existing dataset's layout without asking
+        #[arg(long)]
+        force: bool,
+        /// Maximum plaintext bytes per block
+        #[arg(long = "block-size", default_value_t = DEFAULT_BLOCK_SIZE)]
+        block_size: usize,
+        /// How the input is split into blocks
+        #[arg(long, value_enum, default_value_t = ChunkingArg::Fixed)]
+        chunking: ChunkingArg,
+        /// Store identical chunks once and share the block between references
+        #[arg(long)]
+        dedup: bool,
+        /// Compress blocks before encryption (recorded as the dataset setting)
+        #[arg(long, value_enum)]
+        compression: Option<CompressionArg>,
+        #[arg(long = "compression-level", default_value_t = DEFAULT_ZSTD_LEVEL)]
+        compression_level: i32,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Recover one file of a dataset into an output file ("-" for stdout)
+    Get {
+        name: String,
+        output_file: PathBuf,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// Rewrite shards that had to be reconstructed around
+        #[arg(long = "read-repair")]
+        read_repair: bool,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Remove a file from a dataset
+    Rm {
+        name: String,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Rename a file within a dataset
+    Mv {
+        from: String,
+        to: String,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// List, create, destroy or rename datasets
+    Datasets {
+        #[command(subcommand)]
+        action: DatasetAction,
+    },
+    /// Pin, list or unpin epochs of a dataset
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    /// Make the content pinned at an epoch current again
+    Rollback {
+        epoch: u64,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Move a dataset to a new root key in one commit
+    RotateKey {
+        #[arg(long = "new-root-key-hex")]
+        new_root_key_hex: String,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Merge runs of small adjacent blocks into larger ones in one commit
+    Compact {
+        /// Blocks smaller than this many bytes are merged with their neighbours
+        #[arg(long = "small-block-size", default_value_t = DEFAULT_SMALL_BLOCK_SIZE)]
+        small_block_size: u64,
+        /// Largest merged block in bytes
+        #[arg(long = "target-block-size", default_value_t = DEFAULT_TARGET_BLOCK_SIZE)]
+        target_block_size: u64,
+        /// Also compact after every edit once this fraction of blocks can be merged
+        #[arg(long = "auto-ratio", conflicts_with = "no_auto")]
+        auto_ratio: Option<f64>,
+        /// Turn automatic compaction off
+        #[arg(long = "no-auto")]
+        no_auto: bool,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Re-encode blocks to a new data/parity layout in crash-safe steps
+    Restripe {
+        #[arg(short = 'd', long = "data")]
+        data: usize,
+        #[arg(short = 'p', long = "parity")]
+        parity: usize,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// Only restripe this file (defaults to every file)
+        #[arg(long)]
+        file: Option<String>,
+        /// Start of a byte range to restripe, rounded out to whole blocks
+        #[arg(long, requires = "length")]
+        offset: Option<u64>,
+        /// Length of the byte range to restripe
+        #[arg(long, requires = "offset")]
+        length: Option<u64>,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Write a Merkle inclusion proof for a byte range at the current epoch
+    Prove {
+        offset: u64,
+        length: u64,
+        /// Where to write the proof (JSON)
+        proof_file: PathBuf,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// File within the dataset (defaults to the first one)
+        #[arg(long)]
+        file: Option<String>,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Check a proof written by `prove`; needs neither the key nor the dataset
+    VerifyProof {
+        proof_file: PathBuf,
+        /// Hex Merkle root the proof must match
+        #[arg(long)]
+        root: Option<String>,
+        /// Epoch the proof must be for
+        #[arg(long)]
+        epoch: Option<u64>,
+    },
+    /// Challenge a storage host to prove it still holds random shards
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+    /// Bundle the current epoch of a dataset into one archive file
+    Export {
+        archive: PathBuf,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Validate an archive and install it as a new dataset
+    Import {
+        archive: PathBuf,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Rebuild missing or corrupt shards in place
+    Repair {
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// Only repair this block id
+        #[arg(long = "block")]
+        block: Option<usize>,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Verify every shard of every block and report remaining redundancy
+    Scrub {
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        /// Also reconstruct and decrypt each block
+        #[arg(long = "verify-data")]
+        verify_data: bool,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SnapshotAction {
+    /// Pin the current epoch under a name
+    Create {
+        name: String,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// List pinned epochs
+    List {
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Unpin a snapshot and release blocks only it was keeping
+    Delete {
+        name: String,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditAction {
+    /// Sample random shards and write a challenge for the storage host
+    Challenge {
+        /// Where to write the challenge (JSON)
+        challenge_file: PathBuf,
+        /// Hex audit secret kept by the verifier; created if missing
+        #[arg(long = "secret")]
+        secret_file: PathBuf,
+        /// Number of shards to sample
+        #[arg(long, default_value_t = DEFAULT_AUDIT_SAMPLES)]
+        samples: usize,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Answer a challenge from the shard files alone; needs no key
+    Respond {
+        challenge_file: PathBuf,
+        /// Where to write the response (JSON)
+        response_file: PathBuf,
+        #[arg(long, default_value = "default")]
+        dataset: String,
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Check a response against the challenge and the audit secret
+    Verify {
+        challenge_file: PathBuf,
+        response_file: PathBuf,
+        #[arg(long = "secret")]
+        secret_file: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DatasetAction {
+    /// List the datasets under the storage directory
+    List {
+        #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
+        io_mode: IoModeArg,
+    },
+    /// Initialize an empty dataset
+    Create {
+        dataset: String,
+        /// Comma-separated directories to stripe shards across (shard i goes to root i % n)
+        #[arg(long, value_delimiter = ',')]
+        roots: Vec<PathBuf>,
+        /// Default data shards for blocks added later
         #[arg(
             short = 'd',
             long = "data",
             default_value_t = DEFAULT_DATA_SHARDS
         )]
         data: usize,
+        /// Default parity shards for blocks added later
         #[arg(
             short = 'p',
             long = "parity",
             default_value_t = DEFAULT_PARITY_SHARDS
         )]
         parity: usize,
-        #[arg(long, default_value = "default")]
-        dataset: String,
         #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
         io_mode: IoModeArg,
     },
-    /// Delete a byte range
-    Delete {
-        offset: u64,
-        length: u64,
-        #[arg(long, default_value = "default")]
+    /// Delete a dataset with all of its shards and metadata
+    Destroy {
         dataset: String,
+        /// Skip the confirmation prompt
+        #[arg(long)]
+        force: bool,
         #[arg(long = "io-mode", value_enum, default_value_t = IoModeArg::Strict)]
         io_mode: IoModeArg,
     },
+    /// Give a dataset a new name
+    Rename { from: String, to: String },
 }
 
-fn main() -> Result<()> {
+impl Commands {
+    /// Name reported as `command` in JSON output.
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Write { .. } => "write",
+            Commands::Read { .. } => "read",
+            Commands::Insert { .. } => "insert",
+            Commands::Overwrite { .. } => "overwrite",
+            Commands::Delete { .. } => "delete",
+            Commands::Apply { .. } => "apply",
+            Commands::Ls { .. } => "ls",
+            Commands::Info { .. } => "info",
+            Commands::Put { .. } => "put",
+            Commands::Get { .. } => "get",
+            Commands::Rm { .. } => "rm",
+            Commands::Mv { .. } => "mv",
+            Commands::Datasets { action } => match action {
+                DatasetAction::List { .. } => "datasets list",
+                DatasetAction::Create { .. } => "datasets create",
+                DatasetAction::Destroy { .. } => "datasets destroy",
+                DatasetAction::Rename { .. } => "datasets rename",
+            },
+            Commands::Snapshot { action } => match action {
+                SnapshotAction::Create { .. } => "snapshot create",
+                SnapshotAction::List { .. } => "snapshot list",
+                SnapshotAction::Delete { .. } => "snapshot delete",
+            },
+            Commands::Rollback { .. } => "rollback",
+            Commands::RotateKey { .. } => "rotate-key",
+            Commands::Compact { .. } => "compact",
+            Commands::Restripe { .. } => "restripe",
+            Commands::Prove { .. } => "prove",
+            Commands::VerifyProof { .. } => "verify-proof",
+            Commands::Audit { action } => match action {
+                AuditAction::Challenge { .. } => "audit challenge",
+                AuditAction::Respond { .. } => "audit respond",
+                AuditAction::Verify { .. } => "audit verify",
+            },
+            Commands::Export { .. } => "export",
+            Commands::Import { .. } => "import",
+            Commands::Repair { .. } => "repair",
+            Commands::Scrub { .. } => "scrub",
+        }
+    }
+}
+
+/// Stable error codes reported by `--output json`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    /// A flag, name or key was rejected before touching any data.
+    InvalidArgument,
+    /// No dataset directory exists under the requested name.
+    DatasetNotFound,
+    /// The target dataset already exists and the command will not overwrite it.
+    DatasetExists,
+    /// The dataset exists but no authenticated manifest quorum could be loaded.
+    DatasetUnavailable,
+    /// Another process held the dataset lock for the whole wait timeout.
+    Locked,
+    /// Some blocks can no longer be reconstructed.
+    Unrecoverable,
+    /// A local file could not be read or written.
+    Io,
+    /// The operation itself was refused or failed.
+    OperationFailed,
+}
+
+/// An error together with the code scripts can match on.
+#[derive(Debug)]
+struct CliFailure {
+    code: ErrorCode,
+    error: anyhow::Error,
+}
+
+impl From<anyhow::Error> for CliFailure {
+    fn from(error: anyhow::Error) -> Self {
+        let code = if error.is::<LockTimeout>() {
+            ErrorCode::Locked
+        } else if error.is::<UnrecoverableBlock>() {
+            ErrorCode::Unrecoverable
+        } else if error.chain().any(|cause| cause.is::<io::Error>()) {
+            ErrorCode::Io
+        } else {
+            ErrorCode::OperationFailed
+        };
+        CliFailure { code, error }
+    }
+}
+
+impl From<io::Error> for CliFailure {
+    fn from(error: io::Error) -> Self {
+        // `DatasetReader` carries store errors inside `io::Error`.
+        let code = match error.get_ref() {
+            Some(inner) if caused_by::<LockTimeout>(inner) => ErrorCode::Locked,
+            Some(inner) if caused_by::<UnrecoverableBlock>(inner) => ErrorCode::Unrecoverable,
+            _ => ErrorCode::Io,
+        };
+        CliFailure {
+            code,
+            error: error.into(),
+        }
+    }
+}
+
+fn caused_by<E: std::error::Error + 'static>(error: &(dyn std::error::Error + 'static)) -> bool {
+    std::iter::successors(Some(error), |error| error.source()).any(|cause| cause.is::<E>())
+}
+
+/// Tags an error with `code`, for use with `map_err`.
+fn with_code<E: Into<anyhow::Error>>(code: ErrorCode) -> impl FnOnce(E) -> CliFailure {
+    move |error| CliFailure {
+        code,
+        error: error.into(),
+    }
+}
+
+/// Failure to open a dataset: `Locked` if the wait for its lock ran out,
+/// otherwise `DatasetUnavailable`.
+fn unavailable(error: anyhow::Error) -> CliFailure {
+    let code = if error.is::<LockTimeout>() {
+        ErrorCode::Locked
+    } else {
+        ErrorCode::DatasetUnavailable
+    };
+    CliFailure { code, error }
+}
+
+type CliResult<T> = std::result::Result<T, CliFailure>;
+
+/// Outcome of one command: lines for people, one JSON object for scripts.
+struct Report {
+    command: &'static str,
+    dataset: Option<String>,
+    epoch: Option<u64>,
+    total_size: Option<u64>,
+    stats: IoStats,
+    warnings: Vec<String>,
+    fields: serde_json::Map<String, serde_json::Value>,
+    lines: Vec<String>,
+    /// Set when file content is streamed to stdout, so status goes to stderr.
+    status_to_stderr: bool,
+    /// Set when the command ran to completion but must still fail.
+    failure: Option<CliFailure>,
+}
+
+impl Report {
+    fn new(command: &'static str, dataset: &str) -> Self {
+        Report {
+            command,
+            dataset: Some(dataset.to_string()),
+            epoch: None,
+            total_size: None,
+            stats: IoStats::default(),
+            warnings: Vec::new(),
+            fields: serde_json::Map::new(),
+            lines: Vec::new(),
+            status_to_stderr: false,
+            failure: None,
+        }
+    }
+
+    /// Starts a report with the epoch, selected file size and I/O counters
+    /// of `store`, warning about any block read from a degraded shard set.
+    fn for_store(command: &'static str, dataset: &str, store: &BlockStore) -> Self {
+        let mut report = Report::new(command, dataset);
+        report.epoch = Some(store.manifest.epoch);
+        report.total_size = Some(store.total_size());
+        report.stats = store.io_stats();
+        if let Some(holder) = store.reclaimed_stale_lock() {
+            report.warnings.push(format!(
+                "Reclaimed a stale dataset lock left by {}; check the dataset with scrub",
+                holder
+            ));
+        }
+        if report.stats.degraded_blocks_read > 0 {
+            report.warnings.push(format!(
+                "{} block(s) were decoded around missing or corrupt shards",
+                report.stats.degraded_blocks_read
+            ));
+        }
+        report
+    }
+
+    fn line(mut self, line: String) -> Self {
+        self.lines.push(line);
+        self
+    }
+
+    fn field(mut self, key: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+        self.fields.insert(key.to_string(), value);
+        self
+    }
+
+    fn fail(mut self, failure: CliFailure) -> Self {
+        self.failure = Some(failure);
+        self
+    }
+
+    fn emit(self, format: OutputFormat) -> ExitCode {
+        let exit = if self.failure.is_some() {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        };
+        match format {
+            OutputFormat::Text => {
+                for line in &self.lines {
+                    if self.status_to_stderr {
+                        eprintln!("{}", line);
+                    } else {
+                        println!("{}", line);
+                    }
+                }
+                for warning in &self.warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                if let Some(failure) = &self.failure {
+                    eprintln!("Error: {:?}", failure.error);
+                }
+            }
+            OutputFormat::Json => {
+                let mut object = serde_json::Map::new();
+                object.insert("ok".into(), self.failure.is_none().into());
+                object.insert("command".into(), self.command.into());
+                object.insert("dataset".into(), self.dataset.into());
+                object.insert("epoch".into(), self.epoch.into());
+                object.insert("total_size".into(), self.total_size.into());
+                object.insert("blocks_touched".into(), self.stats.blocks_touched().into());
+                object.insert("shards_written".into(), self.stats.shards_written.into());
+                object.insert(
+                    "io".into(),
+                    serde_json::to_value(self.stats).unwrap_or_default(),
+                );
+                object.insert("warnings".into(), self.warnings.into());
+                object.extend(self.fields);
+                if let Some(failure) = &self.failure {
+                    object.insert("error".into(), error_json(failure));
+                }
+                let text = serde_json::Value::Object(object).to_string();
+                if self.status_to_stderr {
+                    eprintln!("{}", text);
+                } else {
+                    println!("{}", text);
+                }
+            }
+        }
+        exit
+    }
+}
+
+fn error_json(failure: &CliFailure) -> serde_json::Value {
+    serde_json::json!({
+        "code": failure.code,
+        "message": format!("{:#}", failure.error),
+    })
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
-    let root_key = resolve_root_key(&cli)?;
+    let format = cli.output;
+    let command = cli.command.name();
+    match run(cli) {
+        Ok(report) => report.emit(format),
+        Err(failure) => {
+            match format {
+                OutputFormat::Text => eprintln!("Error: {:?}", failure.error),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::json!({
+                        "ok": false,
+                        "command": command,
+                        "error": error_json(&failure),
+                    })
+                ),
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> CliResult<Report> {
+    let tuning = Tuning {
+        jobs: cli.jobs,
+        lock_timeout: cli.lock_timeout.map(Duration::from_secs),
+    };
+    let format = cli.output;
+    let command = cli.command.name();
+
+    // These only handle files exchanged with other parties, so they run
+    // without the root key.
+    match &cli.command {
+        Commands::VerifyProof {
+            proof_file,
+            root,
+            epoch,
+        } => return verify_proof_report(command, proof_file, root.as_deref(), *epoch),
+        Commands::Audit {
+            action:
+                AuditAction::Respond {
+                    challenge_file,
+                    response_file,
+                    dataset,
+                    io_mode,
+                },
+        } => {
+            return audit_respond_report(
+                command,
+                dataset,
+                challenge_file,
+                response_file,
+                io_mode.to_io_options(tuning),
+            );
+        }
+        Commands::Audit {
+            action:
+                AuditAction::Verify {
+                    challenge_file,
+                    response_file,
+                    secret_file,
+                },
+        } => return audit_verify_report(command, challenge_file, response_file, secret_file),
+        _ => {}
+    }
+    let root_key = resolve_root_key(&cli).map_err(with_code(ErrorCode::InvalidArgument))?;
 
-    match cli.command {
+    let report = match cli.command {
         Commands::Write {
             input_file,
             data,
             parity,
+            block_size,
+            chunking,
+            dedup,
+            compression,
+            compression_level,
+            roots,
+            replace,
             dataset,
             io_mode,
         } => {
-            validate_shard_config(data, parity)?;
-            let dataset_path = dataset_path(&dataset)?;
-            fs::create_dir_all(&dataset_path)?;
-
-            let data_bytes = fs::read(&input_file)?;
-            let file_name = input_file
-                .file_name()
-                .ok_or_else(|| anyhow!("Input path has no file name: {}", input_file.display()))?
-                .to_string_lossy()
-                .into_owned();
+            validate_shard_config(data, parity).map_err(with_code(ErrorCode::InvalidArgument))?;
+            validate_roots(&roots, data, parity).map_err(with_code(ErrorCode::InvalidArgument))?;
+            let chunking = chunking_for(chunking, block_size)?;
+            let compression =
+                compression.map(|compression| compression.to_policy(compression_level));
+            if let Some(policy) = &compression {
+                policy
+                    .validate()
+                    .map_err(with_code(ErrorCode::InvalidArgument))?;
+            }
+            let dataset_path =
+                dataset_path(&dataset).map_err(with_code(ErrorCode::InvalidArgument))?;
+            let input = BufReader::new(File::open(&input_file)?);
+            let file_name = input_file_name(&input_file)?;
 
-            let mut store = BlockStore::create_with_options(
-                dataset_path,
-                &file_name,
-                root_key,
-                io_mode.to_io_options(),
-            )?;
-            store.insert_at(0, &data_bytes, data, parity)?;
-            println!(
-                "Write complete. Dataset: {}, total size: {}",
-                dataset, store.manifest.total_size
-            );
+            let io_options = io_mode.to_io_options(tuning);
+            // A replaced dataset stays intact until the new one has committed:
+            // the write goes to a staging directory that is swapped in after.
+            let mut staging = None;
+            if dataset_path.exists() {
+                let existing = dataset_backend(&dataset_path, io_options)?;
+                if BlockStore::is_initialized(existing.as_ref())? {
+                    if !replace {
+                        return Err(dataset_exists(&dataset, "pass --replace to overwrite it"));
+                    }
+                    if shares_roots(&dataset_path, &roots)? {
+                        // The new shards would land next to the old ones, so
+                        // the old dataset has to be cleared first.
+                        BlockStore::destroy_with_backend(
+                            existing.as_ref(),
+                            io_options.lock_timeout,
+                        )?;
+                    } else {
+                        staging = Some(Path::new(STORAGE_DIR).join(format!(
+                            ".{}.replace-{}",
+                            dataset,
+                            std::process::id()
+                        )));
+                    }
+                }
+            }
+            let write_path = staging.clone().unwrap_or_else(|| dataset_path.clone());
+            let write = || -> CliResult<BlockStore> {
+                fs::create_dir_all(&write_path)?;
+                if !roots.is_empty() {
+                    write_layout(&write_path, roots)?;
+                }
+                let mut store = BlockStore::create_with_backend(
+                    dataset_backend(&write_path, io_options)?,
+                    &file_name,
+                    root_key,
+                    io_options,
+                )?;
+                store.lock_exclusive()?;
+                store.set_redundancy(Redundancy {
+                    data_shards: data,
+                    parity_shards: parity,
+                })?;
+                store.set_dedup(dedup)?;
+                if let Some(policy) = compression {
+                    store
+                        .set_compression(policy)
+                        .map_err(with_code(ErrorCode::InvalidArgument))?;
+                }
+                store.write_stream_chunked(input, chunking, data, parity)?;
+                Ok(store)
+            };
+            let store = match write() {
+                Ok(store) => store,
+                Err(err) => {
+                    if let Some(staging) = &staging {
+                        discard_dataset_dir(staging, io_options);
+                    }
+                    return Err(err);
+                }
+            };
+            let report = Report::for_store(command, &dataset, &store)
+                .field("file", &file_name)
+                .field("blocks", store.blocks().len())
+                .line(format!(
+                    "Write complete. Dataset: {}, total size: {}, blocks: {}",
+                    dataset,
+                    store.total_size(),
+                    store.blocks().len()
+                ));
+            if let Some(staging) = staging {
+                drop(store);
+                match swap_in_staged(&staging, &dataset_path, io_options) {
+                    Ok(retired) => discard_dataset_dir(&retired, io_options),
+                    Err(err) => {
+                        discard_dataset_dir(&staging, io_options);
+                        return Err(err.into());
+                    }
+                }
+            }
+            report
         }
         Commands::Read {
             output_file,
             dataset,
+            file,
+            epoch,
+            read_repair,
             io_mode,
         } => {
-            let store = BlockStore::open_with_options(
-                dataset_path(&dataset)?,
+            let store = open_store(
+                &dataset,
+                if epoch.is_some() {
+                    None
+                } else {
+                    file.as_deref()
+                },
                 root_key,
-                io_mode.to_io_options(),
+                io_mode.to_io_options(tuning).with_read_repair(read_repair),
             )?;
-            if store.manifest.blocks.is_empty() {
-                return Err(anyhow!("Dataset '{}' has no blocks to read", dataset));
-            }
-            println!(
-                "Reading dataset '{}' (file '{}', size {})",
-                dataset, store.manifest.file_name, store.manifest.total_size
-            );
-            let data = store.read_at(0, store.manifest.total_size)?;
-            fs::write(output_file, &data)?;
-            println!("Read complete.");
+            let (reader, banner) = match epoch {
+                Some(epoch) => {
+                    let reader = store.reader_at_epoch(epoch, file.as_deref())?;
+                    let banner = format!(
+                        "Reading dataset '{}' at epoch {} (size {})",
+                        dataset,
+                        epoch,
+                        reader.total_size()
+                    );
+                    (reader, banner)
+                }
+                None => {
+                    if store.blocks().is_empty() {
+                        return Err(anyhow!("Dataset '{}' has no blocks to read", dataset).into());
+                    }
+                    let banner = format!(
+                        "Reading dataset '{}' (file '{}', size {})",
+                        dataset,
+                        store.active_file_name().unwrap_or_default(),
+                        store.total_size()
+                    );
+                    (store.reader(), banner)
+                }
+            };
+            let size = reader.total_size();
+            let report = stream_to_output(
+                Report::new(command, &dataset),
+                format,
+                &store,
+                reader,
+                &output_file,
+                read_repair,
+                banner,
+            )?;
+            report
+                .field("file", file.as_deref().or(store.active_file_name()))
+                .field("read_epoch", epoch.unwrap_or(store.manifest.epoch))
+                .field("bytes", size)
         }
         Commands::Insert {
             offset,
             text,
             data,
             parity,
+            force,
             dataset,
+            file,
             io_mode,
         } => {
-            validate_shard_config(data, parity)?;
-            let mut store = BlockStore::open_with_options(
-                dataset_path(&dataset)?,
+            let mut store = open_store_for_update(
+                &dataset,
+                file.as_deref(),
                 root_key,
-                io_mode.to_io_options(),
+                io_mode.to_io_options(tuning),
             )?;
-            store.insert_at(offset, text.as_bytes(), data, parity)?;
-            println!(
-                "Insert complete. Dataset: {}, new size: {}",
-                dataset, store.manifest.total_size
-            );
+            let redundancy = resolve_redundancy(&dataset, &store, data, parity, force)?;
+            store.insert_at(
+                offset,
+                text.as_bytes(),
+                redundancy.data_shards,
+                redundancy.parity_shards,
+            )?;
+            Report::for_store(command, &dataset, &store)
+                .field("file", store.active_file_name())
+                .field("offset", offset)
+                .field("bytes", text.len())
+                .line(format!(
+                    "Insert complete. Dataset: {}, new size: {}",
+                    dataset,
+                    store.total_size()
+                ))
+        }
+        Commands::Overwrite {
+            offset,
+            text,
+            dataset,
+            file,
+            io_mode,
+        } => {
+            let mut store = open_store_for_update(
+                &dataset,
+                file.as_deref(),
+                root_key,
+                io_mode.to_io_options(tuning),
+            )?;
+            store.write_at(offset, text.as_bytes())?;
+            Report::for_store(command, &dataset, &store)
+                .field("file", store.active_file_name())
+                .field("offset", offset)
+                .field("bytes", text.len())
+                .line(format!(
+                    "Overwrite complete. Dataset: {}, new size: {}",
+                    dataset,
+                    store.total_size()
+                ))
         }
         Commands::Delete {
             offset,
             length,
             dataset,
+            file,
             io_mode,
         } => {
-            let mut store = BlockStore::open_with_options(
-                dataset_path(&dataset)?,
+            let mut store = open_store_for_update(
+                &dataset,
+                file.as_deref(),
                 root_key,
-                io_mode.to_io_options(),
+                io_mode.to_io_options(tuning),
             )?;
             store.delete_range(offset, length)?;
-            println!(
-                "Delete complete. Dataset: {}, new size: {}",
-                dataset, store.manifest.total_size
+            Report::for_store(command, &dataset, &store)
+                .field("file", store.active_file_name())
+                .field("offset", offset)
+                .field("length", length)
+                .line(format!(
+                    "Delete complete. Dataset: {}, new size: {}",
+                    dataset,
+                    store.total_size()
+                ))
+        }
+        Commands::Apply {
+            script,
+            data,
+            parity,
+            force,
+            dataset,
+            file,
+            io_mode,
+        } => {
+            let edits = parse_edit_script(&fs::read_to_string(&script)?)
+                .map_err(with_code(ErrorCode::InvalidArgument))?;
+            let mut store = open_store_for_update(
+                &dataset,
+                file.as_deref(),
+                root_key,
+                io_mode.to_io_options(tuning),
+            )?;
+            let redundancy = resolve_redundancy(&dataset, &store, data, parity, force)?;
+            let mut transaction = store.transaction()?;
+            for (line, edit) in &edits {
+                match edit {
+                    ScriptEdit::Insert { offset, text } => transaction.insert_at(
+                        *offset,
+                        text,
+                        redundancy.data_shards,
+                        redundancy.parity_shards,
+                    ),
+                    ScriptEdit::Overwrite { offset, text } => transaction.write_at(*offset, text),
+                    ScriptEdit::Delete { offset, length } => {
+                        transaction.delete_range(*offset, *length)
+                    }
+                }
+                .map_err(|e| anyhow!("Line {}: {}", line, e))?;
+            }
+            transaction.commit()?;
+            Report::for_store(command, &dataset, &store)
+                .field("file", store.active_file_name())
+                .field("edits", edits.len())
+                .line(format!(
+                    "Applied {} edits in one commit. Dataset: {}, epoch: {}, new size: {}",
+                    edits.len(),
+                    dataset,
+                    store.manifest.epoch,
+                    store.total_size()
+                ))
+        }
+        Commands::Ls { dataset, io_mode } => {
+            let store = open_store(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            let mut report = Report::for_store(command, &dataset, &store);
+            report.total_size = None;
+            let mut files = Vec::new();
+            for file in &store.manifest.files {
+                report = report.line(format!(
+                    "{}\t{} bytes\t{} block(s)",
+                    file.name,
+                    file.total_size,
+                    file.blocks.len()
+                ));
+                files.push(serde_json::json!({
+                    "name": file.name,
+                    "size": file.total_size,
+                    "blocks": file.blocks.len(),
+                }));
+            }
+            report.field("files", files).line(format!(
+                "Dataset: {}, epoch: {}, files: {}",
+                dataset,
+                store.manifest.epoch,
+                store.manifest.files.len()
+            ))
+        }
+        Commands::Info { dataset, io_mode } => {
+            let dataset_path = existing_dataset_path(&dataset)?;
+            let backend = dataset_backend(&dataset_path, io_mode.to_io_options(tuning))?;
+            let meta_mac_key = RootKey(root_key).derive().meta_mac_key;
+            let discovery =
+                discover_manifest(backend.as_ref(), &meta_mac_key).map_err(|err| CliFailure {
+                    code: ErrorCode::DatasetUnavailable,
+                    error: anyhow!(
+                        "Dataset is not initialized: {} ({})",
+                        backend.location(),
+                        err
+                    ),
+                })?;
+            info_report(command, &dataset, &discovery)
+        }
+        Commands::Put {
+            input_file,
+            name,
+            data,
+            parity,
+            force,
+            block_size,
+            chunking,
+            dedup,
+            compression,
+            compression_level,
+            dataset,
+            io_mode,
+        } => {
+            let chunking = chunking_for(chunking, block_size)?;
+            let name = match name {
+                Some(name) => name,
+                None => input_file_name(&input_file)?,
+            };
+
+            let input = BufReader::new(File::open(&input_file)?);
+            let dataset_path =
+                dataset_path(&dataset).map_err(with_code(ErrorCode::InvalidArgument))?;
+            fs::create_dir_all(&dataset_path)?;
+            let io_options = io_mode.to_io_options(tuning);
+            let backend = dataset_backend(&dataset_path, io_options)?;
+            let creating = !BlockStore::is_initialized(backend.as_ref())?;
+            let mut store = BlockStore::open_or_create_with_backend(backend, root_key, io_options)
+                .map_err(unavailable)?;
+            store.lock_exclusive()?;
+            // A dataset this command creates takes the given layout as its
+            // default; an existing one keeps its own unless overridden.
+            let redundancy = if creating {
+                let redundancy = Redundancy {
+                    data_shards: data.unwrap_or(DEFAULT_DATA_SHARDS),
+                    parity_shards: parity.unwrap_or(DEFAULT_PARITY_SHARDS),
+                };
+                store
+                    .set_redundancy(redundancy)
+                    .map_err(with_code(ErrorCode::InvalidArgument))?;
+                redundancy
+            } else {
+                resolve_redundancy(&dataset, &store, data, parity, force)?
+            };
+            if dedup {
+                store.set_dedup(true)?;
+            }
+            if let Some(compression) = compression {
+                store
+                    .set_compression(compression.to_policy(compression_level))
+                    .map_err(with_code(ErrorCode::InvalidArgument))?;
+            }
+            store.put_file_chunked(
+                &name,
+                input,
+                chunking,
+                redundancy.data_shards,
+                redundancy.parity_shards,
+            )?;
+            Report::for_store(command, &dataset, &store)
+                .field("file", &name)
+                .field("blocks", store.blocks().len())
+                .line(format!(
+                    "Put complete. Dataset: {}, file: {}, size: {}, blocks: {}",
+                    dataset,
+                    name,
+                    store.total_size(),
+                    store.blocks().len()
+                ))
+        }
+        Commands::Get {
+            name,
+            output_file,
+            dataset,
+            read_repair,
+            io_mode,
+        } => {
+            let store = open_store(
+                &dataset,
+                Some(&name),
+                root_key,
+                io_mode.to_io_options(tuning).with_read_repair(read_repair),
+            )?;
+            let banner = format!(
+                "Reading file '{}' from dataset '{}' (size {})",
+                name,
+                dataset,
+                store.total_size()
             );
+            stream_to_output(
+                Report::new(command, &dataset),
+                format,
+                &store,
+                store.reader(),
+                &output_file,
+                read_repair,
+                banner,
+            )?
+            .field("file", &name)
+            .field("bytes", store.total_size())
+        }
+        Commands::Rm {
+            name,
+            dataset,
+            io_mode,
+        } => {
+            let mut store =
+                open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            store.remove_file(&name)?;
+            let mut report = Report::for_store(command, &dataset, &store);
+            report.total_size = None;
+            report
+                .field("file", &name)
+                .field("files_left", store.manifest.files.len())
+                .line(format!(
+                    "Remove complete. Dataset: {}, file: {}, files left: {}",
+                    dataset,
+                    name,
+                    store.manifest.files.len()
+                ))
+        }
+        Commands::Mv {
+            from,
+            to,
+            dataset,
+            io_mode,
+        } => {
+            let mut store =
+                open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            store.rename_file(&from, &to)?;
+            let mut report = Report::for_store(command, &dataset, &store);
+            report.total_size = None;
+            report.field("from", &from).field("to", &to).line(format!(
+                "Rename complete. Dataset: {}, {} -> {}",
+                dataset, from, to
+            ))
+        }
+        Commands::Datasets { action } => match action {
+            DatasetAction::List { io_mode } => {
+                let mut report = Report::new(command, "");
+                report.dataset = None;
+                let mut datasets = Vec::new();
+                for name in list_datasets()? {
+                    let entry =
+                        match open_store(&name, None, root_key, io_mode.to_io_options(tuning)) {
+                            Ok(store) => {
+                                let size: u64 = store
+                                    .manifest
+                                    .files
+                                    .iter()
+                                    .map(|file| file.total_size)
+                                    .sum();
+                                report = report.line(format!(
+                                    "{}\tepoch {}\t{} file(s)\t{} bytes",
+                                    name,
+                                    store.manifest.epoch,
+                                    store.manifest.files.len(),
+                                    size
+                                ));
+                                serde_json::json!({
+                                    "name": name,
+                                    "epoch": store.manifest.epoch,
+                                    "files": store.manifest.files.len(),
+                                    "size": size,
+                                })
+                            }
+                            Err(failure) => {
+                                report = report.line(format!("{}\tunavailable", name));
+                                serde_json::json!({
+                                    "name": name,
+                                    "error": error_json(&failure),
+                                })
+                            }
+                        };
+                    datasets.push(entry);
+                }
+                let count = datasets.len();
+                report
+                    .field("datasets", datasets)
+                    .line(format!("datasets: {}", count))
+            }
+            DatasetAction::Create {
+                dataset,
+                roots,
+                data,
+                parity,
+                io_mode,
+            } => {
+                validate_shard_config(data, parity)
+                    .map_err(with_code(ErrorCode::InvalidArgument))?;
+                validate_roots(&roots, data, parity)
+                    .map_err(with_code(ErrorCode::InvalidArgument))?;
+                let dataset_path =
+                    dataset_path(&dataset).map_err(with_code(ErrorCode::InvalidArgument))?;
+                let io_options = io_mode.to_io_options(tuning);
+                if dataset_path.exists()
+                    && BlockStore::is_initialized(
+                        dataset_backend(&dataset_path, io_options)?.as_ref(),
+                    )?
+                {
+                    return Err(dataset_exists(&dataset, "it is already initialized"));
+                }
+                fs::create_dir_all(&dataset_path)?;
+                if !roots.is_empty() {
+                    write_layout(&dataset_path, roots)?;
+                }
+                let mut store = BlockStore::create_empty_with_backend(
+                    dataset_backend(&dataset_path, io_options)?,
+                    root_key,
+                    io_options,
+                )?;
+                let redundancy = Redundancy {
+                    data_shards: data,
+                    parity_shards: parity,
+                };
+                store.set_redundancy(redundancy)?;
+                // The default layout needs no settings commit, but the dataset
+                // still has to be persisted to count as initialized.
+                if store.manifest.epoch == 0 {
+                    store.save_manifest()?;
+                }
+                Report::for_store(command, &dataset, &store)
+                    .field("redundancy", redundancy)
+                    .line(format!(
+                        "Dataset created. Dataset: {}, epoch: {}, default layout: {}",
+                        dataset, store.manifest.epoch, redundancy
+                    ))
+            }
+            DatasetAction::Destroy {
+                dataset,
+                force,
+                io_mode,
+            } => {
+                let dataset_path = existing_dataset_path(&dataset)?;
+                if !force && !confirm_destroy(&dataset)? {
+                    return Err(CliFailure {
+                        code: ErrorCode::OperationFailed,
+                        error: anyhow!(
+                            "Destroy of dataset '{}' was not confirmed (use --force to skip the prompt)",
+                            dataset
+                        ),
+                    });
+                }
+                let io_options = io_mode.to_io_options(tuning);
+                let backend = dataset_backend(&dataset_path, io_options)?;
+                let removed =
+                    BlockStore::destroy_with_backend(backend.as_ref(), io_options.lock_timeout)?;
+                fs::remove_dir_all(&dataset_path)?;
+                Report::new(command, &dataset)
+                    .field("objects_removed", removed)
+                    .line(format!(
+                        "Dataset destroyed. Dataset: {}, objects removed: {}",
+                        dataset, removed
+                    ))
+            }
+            DatasetAction::Rename { from, to } => {
+                let from_path = existing_dataset_path(&from)?;
+                let to_path = dataset_path(&to).map_err(with_code(ErrorCode::InvalidArgument))?;
+                if to_path.exists() {
+                    return Err(dataset_exists(&to, "choose another name"));
+                }
+                let io_options = IoModeArg::default().to_io_options(tuning);
+                let backend = dataset_backend(&from_path, io_options)?;
+                let lock = backend.lock(LockMode::Exclusive, io_options.lock_timeout)?;
+                fs::rename(&from_path, &to_path)?;
+                drop(lock);
+                Report::new(command, &to)
+                    .field("from", &from)
+                    .line(format!("Dataset renamed. {} -> {}", from, to))
+            }
+        },
+        Commands::Snapshot { action } => match action {
+            SnapshotAction::Create {
+                name,
+                dataset,
+                io_mode,
+            } => {
+                let mut store =
+                    open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+                let pinned = store.manifest.epoch;
+                store.create_snapshot(&name)?;
+                let mut report = Report::for_store(command, &dataset, &store);
+                report.total_size = None;
+                report
+                    .field("snapshot", &name)
+                    .field("pinned_epoch", pinned)
+                    .line(format!(
+                        "Snapshot created. Dataset: {}, snapshot: {}, epoch: {}",
+                        dataset, name, pinned
+                    ))
+            }
+            SnapshotAction::List { dataset, io_mode } => {
+                let store = open_store(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+                let mut report = Report::for_store(command, &dataset, &store);
+                report.total_size = None;
+                let mut snapshots = Vec::new();
+                for snapshot in &store.manifest.snapshots {
+                    let size: u64 = snapshot.files.iter().map(|file| file.total_size).sum();
+                    report = report.line(format!(
+                        "{}\tepoch {}\t{} file(s)\t{} bytes",
+                        snapshot.name,
+                        snapshot.epoch,
+                        snapshot.files.len(),
+                        size
+                    ));
+                    snapshots.push(serde_json::json!({
+                        "name": snapshot.name,
+                        "epoch": snapshot.epoch,
+                        "files": snapshot.files.len(),
+                        "size": size,
+                    }));
+                }
+                report.field("snapshots", snapshots).line(format!(
+                    "Dataset: {}, current epoch: {}, snapshots: {}",
+                    dataset,
+                    store.manifest.epoch,
+                    store.manifest.snapshots.len()
+                ))
+            }
+            SnapshotAction::Delete {
+                name,
+                dataset,
+                io_mode,
+            } => {
+                let mut store =
+                    open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+                store.delete_snapshot(&name)?;
+                let mut report = Report::for_store(command, &dataset, &store);
+                report.total_size = None;
+                report.field("snapshot", &name).line(format!(
+                    "Snapshot deleted. Dataset: {}, snapshot: {}",
+                    dataset, name
+                ))
+            }
+        },
+        Commands::Rollback {
+            epoch,
+            dataset,
+            io_mode,
+        } => {
+            let mut store =
+                open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            store.rollback(epoch)?;
+            Report::for_store(command, &dataset, &store)
+                .field("restored_epoch", epoch)
+                .line(format!(
+                    "Rollback complete. Dataset: {}, restored epoch: {}, new epoch: {}",
+                    dataset, epoch, store.manifest.epoch
+                ))
+        }
+        Commands::Compact {
+            small_block_size,
+            target_block_size,
+            auto_ratio,
+            no_auto,
+            dataset,
+            io_mode,
+        } => {
+            let policy = CompactionPolicy {
+                small_block_size,
+                target_block_size,
+            };
+            policy
+                .validate()
+                .map_err(with_code(ErrorCode::InvalidArgument))?;
+            let auto_compact = auto_ratio.map(|ratio| AutoCompaction { policy, ratio });
+            if let Some(auto_compact) = &auto_compact {
+                auto_compact
+                    .validate()
+                    .map_err(with_code(ErrorCode::InvalidArgument))?;
+            }
+            let mut store =
+                open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            let fragmentation = policy.fragmentation(&store.manifest.files);
+            let compacted = store.compact(&policy)?;
+            if auto_compact.is_some() || no_auto {
+                store.set_auto_compaction(auto_compact)?;
+            }
+            let auto_ratio = store
+                .manifest
+                .settings
+                .auto_compact
+                .map(|auto_compact| auto_compact.ratio);
+            Report::for_store(command, &dataset, &store)
+                .field("fragmentation", fragmentation)
+                .field("blocks_before", compacted.blocks_before)
+                .field("blocks_after", compacted.blocks_after)
+                .field("blocks_merged", compacted.blocks_merged)
+                .field("auto_ratio", auto_ratio)
+                .line(format!(
+                    "Compaction complete. Dataset: {}, blocks: {} -> {} ({} merged), epoch: {}",
+                    dataset,
+                    compacted.blocks_before,
+                    compacted.blocks_after,
+                    compacted.blocks_merged,
+                    store.manifest.epoch
+                ))
+                .line(match auto_ratio {
+                    Some(ratio) => format!(
+                        "Automatic compaction: after edits once {:.0}% of blocks can be merged",
+                        ratio * 100.0
+                    ),
+                    None => "Automatic compaction: off".to_string(),
+                })
+        }
+        Commands::Restripe {
+            data,
+            parity,
+            dataset,
+            file,
+            offset,
+            length,
+            io_mode,
+        } => {
+            validate_shard_config(data, parity).map_err(with_code(ErrorCode::InvalidArgument))?;
+            let mut store = open_store_for_update(
+                &dataset,
+                file.as_deref(),
+                root_key,
+                io_mode.to_io_options(tuning),
+            )?;
+            let restriped = match (offset, length) {
+                (Some(offset), Some(length)) => {
+                    store.restripe_range(offset, length, data, parity)?
+                }
+                _ if file.is_some() => store.restripe_range(0, store.total_size(), data, parity)?,
+                _ => store.restripe(data, parity)?,
+            };
+            Report::for_store(command, &dataset, &store)
+                .field("file", file.as_deref())
+                .field("data_shards", data)
+                .field("parity_shards", parity)
+                .field("blocks_restriped", restriped.blocks_restriped)
+                .field("blocks_skipped", restriped.blocks_skipped)
+                .field("bytes", restriped.bytes)
+                .field("steps", restriped.steps)
+                .line(format!(
+                    "Restripe complete. Dataset: {}, blocks re-encoded to {}+{}: {} ({} bytes, {} already matching), commits: {}, epoch: {}",
+                    dataset,
+                    data,
+                    parity,
+                    restriped.blocks_restriped,
+                    restriped.bytes,
+                    restriped.blocks_skipped,
+                    restriped.steps,
+                    store.manifest.epoch
+                ))
+        }
+        Commands::RotateKey {
+            new_root_key_hex,
+            dataset,
+            io_mode,
+        } => {
+            let new_root_key = RootKey::from_hex(&new_root_key_hex)
+                .map_err(with_code(ErrorCode::InvalidArgument))?
+                .0;
+            let mut store =
+                open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            store.rotate_key(new_root_key)?;
+            let blocks = store.manifest.referenced_blocks().count();
+            Report::for_store(command, &dataset, &store)
+                .field("blocks", blocks)
+                .line(format!(
+                    "Key rotation complete. Dataset: {}, epoch: {}, blocks: {}",
+                    dataset, store.manifest.epoch, blocks
+                ))
+        }
+        Commands::Prove {
+            offset,
+            length,
+            proof_file,
+            dataset,
+            file,
+            io_mode,
+        } => {
+            let store = open_store(
+                &dataset,
+                file.as_deref(),
+                root_key,
+                io_mode.to_io_options(tuning),
+            )?;
+            let proof = store.prove_range(offset, length)?;
+            write_json_file(&proof_file, &proof)?;
+            Report::for_store(command, &dataset, &store)
+                .field("file", &proof.file)
+                .field("root", &proof.root)
+                .field("blocks", proof.leaves.len())
+                .line(format!(
+                    "Proof written to {}. File: {}, bytes {}..{}, blocks: {}, epoch: {}, root: {}",
+                    proof_file.display(),
+                    proof.file,
+                    offset,
+                    offset.saturating_add(length),
+                    proof.leaves.len(),
+                    proof.epoch,
+                    proof.root
+                ))
+        }
+        Commands::Audit {
+            action:
+                AuditAction::Challenge {
+                    challenge_file,
+                    secret_file,
+                    samples,
+                    dataset,
+                    io_mode,
+                },
+        } => {
+            let store = open_store(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            let created = !secret_file.exists();
+            let secret = if created {
+                let secret = AuditSecret::generate();
+                fs::write(&secret_file, format!("{}\n", secret.to_hex()))?;
+                secret
+            } else {
+                AuditSecret::from_hex(&fs::read_to_string(&secret_file)?)
+                    .map_err(with_code(ErrorCode::InvalidArgument))?
+            };
+            let challenge = store.audit_challenge(&secret, samples)?;
+            write_json_file(&challenge_file, &challenge)?;
+            let mut report = Report::for_store(command, &dataset, &store);
+            report.total_size = None;
+            if created {
+                report = report.line(format!(
+                    "Created audit secret {}; keep it away from the storage host",
+                    secret_file.display()
+                ));
+            }
+            report
+                .field("samples", challenge.items.len())
+                .field("secret_created", created)
+                .line(format!(
+                    "Challenge written to {}. Dataset: {}, epoch: {}, shards sampled: {}",
+                    challenge_file.display(),
+                    dataset,
+                    challenge.epoch,
+                    challenge.items.len()
+                ))
+        }
+        Commands::Export {
+            archive,
+            dataset,
+            io_mode,
+        } => {
+            let store = open_store(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            // Written next to the target and renamed, so a failed export
+            // never leaves a truncated archive under the requested name.
+            let mut partial = archive.clone().into_os_string();
+            partial.push(".partial");
+            let partial = PathBuf::from(partial);
+            let exported = File::create(&partial)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| store.export_archive(BufWriter::new(file)));
+            let exported = match exported {
+                Ok(exported) => exported,
+                Err(err) => {
+                    let _ = fs::remove_file(&partial);
+                    return Err(err.into());
+                }
+            };
+            fs::rename(&partial, &archive)?;
+            let mut report = Report::for_store(command, &dataset, &store);
+            report.total_size = None;
+            if !exported.missing.is_empty() {
+                report.warnings.push(format!(
+                    "{} referenced shard file(s) were missing and are not in the archive; check the dataset with scrub",
+                    exported.missing.len()
+                ));
+            }
+            report
+                .field("objects", exported.objects)
+                .field("bytes", exported.bytes)
+                .field("missing", &exported.missing)
+                .line(format!(
+                    "Export complete. Dataset: {}, epoch: {}, objects: {} ({} bytes), archive: {}",
+                    dataset,
+                    exported.epoch,
+                    exported.objects,
+                    exported.bytes,
+                    archive.display()
+                ))
+        }
+        Commands::Import {
+            archive,
+            dataset,
+            io_mode,
+        } => {
+            let target = dataset_path(&dataset).map_err(with_code(ErrorCode::InvalidArgument))?;
+            if target.exists() {
+                return Err(dataset_exists(&dataset, "choose another name"));
+            }
+            let reader = BufReader::new(File::open(&archive)?);
+            // Unpacked and validated under a name `datasets list` skips, then
+            // moved into place, so a bad archive never shows up as a dataset.
+            let staging =
+                Path::new(STORAGE_DIR).join(format!(".{}.import-{}", dataset, std::process::id()));
+            fs::create_dir_all(&staging)?;
+            let io_options = io_mode.to_io_options(tuning);
+            let backend: Arc<dyn ShardBackend> = Arc::new(LocalDirBackend::new(
+                staging.clone(),
+                io_options.durability_sync,
+            ));
+            let imported =
+                archive::import(reader, backend, root_key, io_options).and_then(|imported| {
+                    if target.exists() {
+                        return Err(anyhow!("Dataset '{}' appeared during the import", dataset));
+                    }
+                    fs::rename(&staging, &target)?;
+                    Ok(imported)
+                });
+            let imported = match imported {
+                Ok(imported) => imported,
+                Err(err) => {
+                    let _ = fs::remove_dir_all(&staging);
+                    return Err(unavailable(err));
+                }
+            };
+            let mut report = Report::new(command, &dataset);
+            report.epoch = Some(imported.epoch);
+            if imported.degraded_blocks > 0 {
+                report.warnings.push(format!(
+                    "{} block(s) arrived with missing shards; run repair to restore full redundancy",
+                    imported.degraded_blocks
+                ));
+            }
+            report
+                .field("objects", imported.objects)
+                .field("bytes", imported.bytes)
+                .field("degraded_blocks", imported.degraded_blocks)
+                .line(format!(
+                    "Import complete. Dataset: {}, epoch: {}, objects: {} ({} bytes)",
+                    dataset, imported.epoch, imported.objects, imported.bytes
+                ))
+        }
+        Commands::VerifyProof { .. } | Commands::Audit { .. } => {
+            unreachable!("handled before the root key is resolved")
         }
+        Commands::Repair {
+            dataset,
+            block,
+            io_mode,
+        } => {
+            let store =
+                open_store_for_update(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            let repair = match block {
+                Some(block_id) => RepairReport {
+                    epoch: store.manifest.epoch,
+                    blocks: vec![store.repair_block(block_id)?],
+                },
+                None => store.repair()?,
+            };
+            let mut report = Report::for_store(command, &dataset, &store);
+            report.total_size = None;
+            let mut blocks = Vec::new();
+            for block in &repair.blocks {
+                let (outcome, shards) = match &block.outcome {
+                    RepairOutcome::AlreadyHealthy => ("healthy", Vec::new()),
+                    RepairOutcome::Repaired { shards } => {
+                        let list: Vec<String> = shards.iter().map(|i| i.to_string()).collect();
+                        report = report.line(format!(
+                            "Block {}: rewrote shards {}",
+                            block.block_id,
+                            list.join(", ")
+                        ));
+                        ("repaired", shards.clone())
+                    }
+                    RepairOutcome::Unrecoverable => {
+                        report = report.line(format!("Block {}: UNRECOVERABLE", block.block_id));
+                        ("unrecoverable", Vec::new())
+                    }
+                };
+                blocks.push(serde_json::json!({
+                    "block_id": block.block_id,
+                    "outcome": outcome,
+                    "rewritten_shards": shards,
+                }));
+            }
+            report = report
+                .field("repaired_blocks", repair.repaired_blocks())
+                .field("repaired_shards", repair.repaired_shards())
+                .field("unrecoverable_blocks", repair.unrecoverable_blocks())
+                .field("blocks", blocks)
+                .line(format!(
+                    "Repair complete. Dataset: {}, blocks repaired: {}, shards rewritten: {}, unrecoverable: {}",
+                    dataset,
+                    repair.repaired_blocks(),
+                    repair.repaired_shards(),
+                    repair.unrecoverable_blocks()
+                ));
+            if repair.unrecoverable_blocks() > 0 {
+                report = report.fail(CliFailure {
+                    code: ErrorCode::Unrecoverable,
+                    error: anyhow!(
+                        "Repair left {} unrecoverable block(s) in dataset '{}'",
+                        repair.unrecoverable_blocks(),
+                        dataset
+                    ),
+                });
+            }
+            report
+        }
+        Commands::Scrub {
+            dataset,
+            verify_data,
+            io_mode,
+        } => {
+            let store = open_store(&dataset, None, root_key, io_mode.to_io_options(tuning))?;
+            let scrub = store.scrub(verify_data)?;
+            let mut report = Report::for_store(command, &dataset, &store);
+            report.total_size = None;
+            let mut blocks = Vec::new();
+            for block in &scrub.blocks {
+                report = report.line(describe_block_health(block));
+                blocks.push(block_health_json(block));
+            }
+            report = report
+                .field("healthy_blocks", scrub.healthy_blocks())
+                .field("degraded_blocks", scrub.degraded_blocks())
+                .field("unrecoverable_blocks", scrub.unrecoverable_blocks())
+                .field("min_parity_remaining", scrub.min_parity_remaining())
+                .field("blocks", blocks)
+                .line(format!(
+                    "Scrub complete. Dataset: {}, epoch: {}, blocks: {}, healthy: {}, degraded: {}, unrecoverable: {}",
+                    dataset,
+                    scrub.epoch,
+                    scrub.blocks.len(),
+                    scrub.healthy_blocks(),
+                    scrub.degraded_blocks(),
+                    scrub.unrecoverable_blocks()
+                ));
+            if scrub.unrecoverable_blocks() > 0 {
+                report = report.fail(CliFailure {
+                    code: ErrorCode::Unrecoverable,
+                    error: anyhow!(
+                        "Scrub found {} unrecoverable block(s) in dataset '{}'",
+                        scrub.unrecoverable_blocks(),
+                        dataset
+                    ),
+                });
+            }
+            report
+        }
+    };
+
+    Ok(report)
+}
+
+/// Opens a dataset and selects `file`, or the first file when none is given.
+fn open_store(
+    dataset: &str,
+    file: Option<&str>,
+    root_key: [u8; 32],
+    io_options: IoOptions,
+) -> CliResult<BlockStore> {
+    let dataset_path = existing_dataset_path(dataset)?;
+    let mut store = match read_layout(&dataset_path)? {
+        Some(layout) => BlockStore::open_with_backend(
+            striped_backend(&layout, io_options)?,
+            root_key,
+            io_options,
+        ),
+        None => BlockStore::open_with_options(dataset_path, root_key, io_options),
+    }
+    .map_err(unavailable)?;
+    if let Some(name) = file {
+        store.select_file(name)?;
     }
+    Ok(store)
+}
 
+/// Opens a dataset for a mutation, holding its exclusive lock until the
+/// store is dropped.
+fn open_store_for_update(
+    dataset: &str,
+    file: Option<&str>,
+    root_key: [u8; 32],
+    io_options: IoOptions,
+) -> CliResult<BlockStore> {
+    let mut store = open_store(dataset, file, root_key, io_options)?;
+    store.lock_exclusive()?;
+    Ok(store)
+}
+
+/// Path of a dataset that must already exist.
+fn existing_dataset_path(dataset: &str) -> CliResult<PathBuf> {
+    let dataset_path = dataset_path(dataset).map_err(with_code(ErrorCode::InvalidArgument))?;
+    if !dataset_path.exists() {
+        return Err(CliFailure {
+            code: ErrorCode::DatasetNotFound,
+            error: anyhow!("Dataset path does not exist: {}", dataset_path.display()),
+        });
+    }
+    Ok(dataset_path)
+}
+
+fn dataset_exists(dataset: &str, hint: &str) -> CliFailure {
+    CliFailure {
+        code: ErrorCode::DatasetExists,
+        error: anyhow!("Dataset '{}' already exists; {}", dataset, hint),
+    }
+}
+
+/// Names of the dataset directories under the storage directory, sorted.
+fn list_datasets() -> Result<Vec<String>> {
+    let storage = Path::new(STORAGE_DIR);
+    if !storage.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(storage)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if dataset_path(&name).is_ok() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Layout for the blocks a command adds: the dataset's default unless
+/// `--data`/`--parity` override it. An override that differs from the default
+/// must be confirmed at a prompt or forced.
+fn resolve_redundancy(
+    dataset: &str,
+    store: &BlockStore,
+    data: Option<usize>,
+    parity: Option<usize>,
+    force: bool,
+) -> CliResult<Redundancy> {
+    let default = store.redundancy();
+    let chosen = Redundancy {
+        data_shards: data.unwrap_or(default.data_shards),
+        parity_shards: parity.unwrap_or(default.parity_shards),
+    };
+    validate_shard_config(chosen.data_shards, chosen.parity_shards)
+        .map_err(with_code(ErrorCode::InvalidArgument))?;
+    if chosen != default && !force && !confirm_override(dataset, default, chosen)? {
+        return Err(CliFailure {
+            code: ErrorCode::OperationFailed,
+            error: anyhow!(
+                "Writing {} blocks into dataset '{}' (default {}) was not confirmed (use --force to skip the prompt)",
+                chosen,
+                dataset,
+                default
+            ),
+        });
+    }
+    Ok(chosen)
+}
+
+fn confirm_override(dataset: &str, default: Redundancy, chosen: Redundancy) -> Result<bool> {
+    eprint!(
+        "Dataset '{}' stores new blocks as {}. Write these as {} instead? [y/N]: ",
+        dataset, default, chosen
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Asks on stderr for the dataset name to be typed back before destroying it.
+fn confirm_destroy(dataset: &str) -> Result<bool> {
+    eprint!(
+        "Destroy dataset '{}' and all of its shards? Type its name to confirm: ",
+        dataset
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim() == dataset)
+}
+
+/// Writes `value` to `path` as pretty-printed JSON.
+fn write_json_file(path: &Path, value: &impl Serialize) -> CliResult<()> {
+    let json = serde_json::to_vec_pretty(value).map_err(anyhow::Error::from)?;
+    fs::write(path, json)?;
     Ok(())
 }
 
+/// Parses the JSON `what` stored at `path`; a malformed file is an invalid argument.
+fn read_json_file<T: serde::de::DeserializeOwned>(path: &Path, what: &str) -> CliResult<T> {
+    let bytes = fs::read(path)?;
+    serde_json::from_slice(&bytes).map_err(|err| CliFailure {
+        code: ErrorCode::InvalidArgument,
+        error: anyhow!("Cannot parse {} {}: {}", what, path.display(), err),
+    })
+}
+
+/// Answers an audit challenge on the storage side, from the shard files alone.
+fn audit_respond_report(
+    command: &'static str,
+    dataset: &str,
+    challenge_file: &Path,
+    response_file: &Path,
+    io_options: IoOptions,
+) -> CliResult<Report> {
+    let challenge: Challenge = read_json_file(challenge_file, "challenge")?;
+    let dataset_path = existing_dataset_path(dataset)?;
+    let backend = dataset_backend(&dataset_path, io_options)?;
+    let response = challenge.respond(backend.as_ref())?;
+    write_json_file(response_file, &response)?;
+
+    let mut report = Report::new(command, dataset);
+    report.epoch = Some(challenge.epoch);
+    for missing in &response.missing {
+        report.warnings.push(format!(
+            "Cannot answer for shard {} of block {}: {}",
+            missing.shard_index, missing.block_id, missing.reason
+        ));
+    }
+    Ok(report
+        .field("answered", response.proofs.len())
+        .field("missing", response.missing.len())
+        .line(format!(
+            "Response written to {}. Shards answered: {} of {}",
+            response_file.display(),
+            response.proofs.len(),
+            challenge.items.len()
+        )))
+}
+
+/// Checks an audit response; any shard that was not proven fails the command.
+fn audit_verify_report(
+    command: &'static str,
+    challenge_file: &Path,
+    response_file: &Path,
+    secret_file: &Path,
+) -> CliResult<Report> {
+    let challenge: Challenge = read_json_file(challenge_file, "challenge")?;
+    let response: AuditResponse = read_json_file(response_file, "response")?;
+    let secret = AuditSecret::from_hex(&fs::read_to_string(secret_file)?)
+        .map_err(with_code(ErrorCode::InvalidArgument))?;
+    let audit = challenge.verify(&secret, &response)?;
+
+    let detection = audit.detection_probability(0.01);
+    let mut report = Report {
+        dataset: None,
+        epoch: Some(audit.epoch),
+        ..Report::new(command, "")
+    }
+    .field("samples", audit.samples)
+    .field("passed", audit.passed)
+    .field("detection_at_1pct_loss", detection)
+    .field(
+        "failures",
+        audit
+            .failures
+            .iter()
+            .map(|failure| {
+                serde_json::json!({
+                    "block_id": failure.block_id,
+                    "shard_index": failure.shard_index,
+                    "reason": failure.reason,
+                })
+            })
+            .collect::<Vec<_>>(),
+    );
+    for failure in &audit.failures {
+        report = report.line(format!(
+            "  Shard {} of block {}: {}",
+            failure.shard_index, failure.block_id, failure.reason
+        ));
+    }
+    report = report.line(format!(
+        "Audit of epoch {}: {} of {} sampled shards proven. A loss of 1% of all shards would have been caught with probability {:.1}%",
+        audit.epoch,
+        audit.passed,
+        audit.samples,
+        detection * 100.0
+    ));
+    if !audit.is_clean() {
+        report = report.fail(CliFailure {
+            code: ErrorCode::OperationFailed,
+            error: anyhow!(
+                "Audit failed: {} of {} sampled shards could not be proven",
+                audit.failures.len(),
+                audit.samples
+            ),
+        });
+    }
+    Ok(report)
+}
+
+/// Checks a proof file on its own and, if given, against a trusted root and
+/// epoch. A mismatch is reported as a failed command.
+fn verify_proof_report(
+    command: &'static str,
+    proof_file: &Path,
+    root: Option<&str>,
+    epoch: Option<u64>,
+) -> CliResult<Report> {
+    let proof: RangeProof = read_json_file(proof_file, "proof")?;
+
+    let mut report = Report {
+        dataset: None,
+        epoch: Some(proof.epoch),
+        ..Report::new(command, "")
+    }
+    .field("file", &proof.file)
+    .field("offset", proof.offset)
+    .field("length", proof.length)
+    .field("root", &proof.root)
+    .field("blocks", proof.leaves.len());
+    let mut outcome = proof.verify();
+    if outcome.is_ok()
+        && let Some(root) = root
+        && !root.eq_ignore_ascii_case(&proof.root)
+    {
+        outcome = Err(anyhow!(
+            "Proof is for root {}, expected {}",
+            proof.root,
+            root
+        ));
+    }
+    if outcome.is_ok()
+        && let Some(epoch) = epoch
+        && epoch != proof.epoch
+    {
+        outcome = Err(anyhow!(
+            "Proof is for epoch {}, expected {}",
+            proof.epoch,
+            epoch
+        ));
+    }
+    report = report.field("valid", outcome.is_ok());
+    Ok(match outcome {
+        Ok(()) => report.line(format!(
+            "Proof valid. File: {}, bytes {}..{} in {} block(s) at epoch {}, root: {}",
+            proof.file,
+            proof.offset,
+            proof.offset.saturating_add(proof.length),
+            proof.leaves.len(),
+            proof.epoch,
+            proof.root
+        )),
+        Err(error) => report.fail(CliFailure {
+            code: ErrorCode::OperationFailed,
+            error,
+        }),
+    })
+}
+
+/// Describes a discovered manifest: every file and block, and the metadata
+/// copies behind the winning epoch and any losing candidates.
+fn info_report(command: &'static str, dataset: &str, discovery: &ManifestDiscovery) -> Report {
+    let manifest = &discovery.manifest;
+    let merkle_root = blake3::Hash::from(manifest.merkle_root)
+        .to_hex()
+        .to_string();
+    let mut report = Report::new(command, dataset);
+    report.epoch = Some(manifest.epoch);
+    report.total_size = manifest.files.first().map(|file| file.total_size);
+    report = report
+        .line(format!(
+            "Dataset: {}, epoch: {}, files: {}, snapshots: {}",
+            dataset,
+            manifest.epoch,
+            manifest.files.len(),
+            manifest.snapshots.len()
+        ))
+        .line(format!(
+            "Default layout for new blocks: {}",
+            manifest.settings.redundancy
+        ))
+        .line(format!("Merkle root: {}", merkle_root))
+        .field("redundancy", manifest.settings.redundancy)
+        .field("merkle_root", &merkle_root);
+
+    let mut files = Vec::new();
+    for file in &manifest.files {
+        report = report.line(format!(
+            "File: {}, total size: {}, blocks: {}",
+            file.name,
+            file.total_size,
+            file.blocks.len()
+        ));
+        let mut blocks = Vec::new();
+        let mut offset = 0u64;
+        for block in &file.blocks {
+            let end = offset.saturating_add(block.original_size);
+            let overhead =
+                (block.data_shards + block.parity_shards) as f64 / block.data_shards as f64;
+            report = report.line(format!(
+                "  Block {} [{}..{}): {} bytes, {}+{} shards, overhead {:.2}x",
+                block.id,
+                offset,
+                end,
+                block.original_size,
+                block.data_shards,
+                block.parity_shards,
+                overhead
+            ));
+            blocks.push(serde_json::json!({
+                "id": block.id,
+                "offset": offset,
+                "end": end,
+                "size": block.original_size,
+                "data_shards": block.data_shards,
+                "parity_shards": block.parity_shards,
+                "overhead": overhead,
+            }));
+            offset = end;
+        }
+        files.push(serde_json::json!({
+            "name": file.name,
+            "total_size": file.total_size,
+            "blocks": blocks,
+        }));
+    }
+
+    report = report.line(format!(
+        "Metadata copies for epoch {}: {}",
+        discovery.winner.epoch, discovery.winner.copies
+    ));
+    for loser in &discovery.losers {
+        report = report.line(format!(
+            "Losing candidate epoch {}: {} cop{}{}",
+            loser.epoch,
+            loser.copies,
+            if loser.copies == 1 { "y" } else { "ies" },
+            if loser.has_quorum() {
+                ""
+            } else {
+                " (below quorum)"
+            }
+        ));
+    }
+    report.field("files", files).field(
+        "metadata",
+        serde_json::json!({
+            "winner": candidate_json(&discovery.winner),
+            "losers": discovery.losers.iter().map(candidate_json).collect::<Vec<_>>(),
+        }),
+    )
+}
+
+fn candidate_json(candidate: &ManifestCandidate) -> serde_json::Value {
+    serde_json::json!({
+        "epoch": candidate.epoch,
+        "manifest_hash": blake3::Hash::from(candidate.manifest_hash).to_hex().to_string(),
+        "copies": candidate.copies,
+        "quorum": candidate.has_quorum(),
+    })
+}
+
+/// Backend for a dataset: its striped roots if it has a layout, else its own directory.
+fn dataset_backend(dataset_path: &Path, io_options: IoOptions) -> Result<Arc<dyn ShardBackend>> {
+    match read_layout(dataset_path)? {
+        Some(layout) => striped_backend(&layout, io_options),
+        None => Ok(Arc::new(LocalDirBackend::new(
+            dataset_path.to_path_buf(),
+            io_options.durability_sync,
+        ))),
+    }
+}
+
+fn striped_backend(layout: &DatasetLayout, io_options: IoOptions) -> Result<Arc<dyn ShardBackend>> {
+    Ok(Arc::new(StripedBackend::local(
+        &layout.roots,
+        io_options.durability_sync,
+    )?))
+}
+
+fn read_layout(dataset_path: &Path) -> Result<Option<DatasetLayout>> {
+    let path = dataset_path.join(LAYOUT_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let layout: DatasetLayout = serde_json::from_slice(&fs::read(&path)?)
+        .map_err(|e| anyhow!("Invalid dataset layout {}: {}", path.display(), e))?;
+    if layout.roots.is_empty() {
+        return Err(anyhow!("Dataset layout {} lists no roots", path.display()));
+    }
+    Ok(Some(layout))
+}
+
+/// Whether any of `roots` already holds shards of the dataset at `dataset_path`.
+fn shares_roots(dataset_path: &Path, roots: &[PathBuf]) -> Result<bool> {
+    let Some(layout) = read_layout(dataset_path)? else {
+        return Ok(false);
+    };
+    Ok(roots.iter().any(|root| {
+        root.canonicalize()
+            .is_ok_and(|root| layout.roots.contains(&root))
+    }))
+}
+
+/// Replaces the dataset at `dataset_path` with the one committed under
+/// `staging`. The old dataset is moved aside under its exclusive lock, so the
+/// name never holds a half-written dataset; returns where it was moved.
+fn swap_in_staged(staging: &Path, dataset_path: &Path, io_options: IoOptions) -> Result<PathBuf> {
+    let mut retired = staging.as_os_str().to_owned();
+    retired.push(".retired");
+    let retired = PathBuf::from(retired);
+
+    let old = dataset_backend(dataset_path, io_options)?;
+    let _lock = old.lock(LockMode::Exclusive, io_options.lock_timeout)?;
+    fs::rename(dataset_path, &retired)?;
+    if let Err(err) = fs::rename(staging, dataset_path) {
+        fs::rename(&retired, dataset_path)?;
+        return Err(err.into());
+    }
+    Ok(retired)
+}
+
+/// Destroys a dataset that is no longer reachable under its name, along with
+/// its directory. Best effort: whatever is left stays in a hidden directory.
+fn discard_dataset_dir(path: &Path, io_options: IoOptions) {
+    if let Ok(backend) = dataset_backend(path, io_options) {
+        let _ = BlockStore::destroy_with_backend(backend.as_ref(), io_options.lock_timeout);
+    }
+    let _ = fs::remove_dir_all(path);
+}
+
+/// Striping only survives losing `parity` roots if every shard of a block
+/// lands on its own root.
+fn validate_roots(roots: &[PathBuf], data: usize, parity: usize) -> Result<()> {
+    if !roots.is_empty() && roots.len() < data + parity {
+        return Err(anyhow!(
+            "A {}+{} layout needs at least {} storage roots, got {}",
+            data,
+            parity,
+            data + parity,
+            roots.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Records the shard roots of a dataset, creating each root directory.
+fn write_layout(dataset_path: &Path, roots: Vec<PathBuf>) -> Result<()> {
+    let mut resolved = Vec::with_capacity(roots.len());
+    for root in roots {
+        fs::create_dir_all(&root)?;
+        let root = root.canonicalize()?;
+        if resolved.contains(&root) {
+            return Err(anyhow!("Storage root listed twice: {}", root.display()));
+        }
+        resolved.push(root);
+    }
+
+    let bytes = serde_json::to_vec_pretty(&DatasetLayout { roots: resolved })?;
+    fs::write(dataset_path.join(LAYOUT_FILE), bytes)?;
+    Ok(())
+}
+
+/// Copies a file reader to `output_file`, or to stdout when it is "-".
+///
+/// In text mode the banner is printed before copying starts; the completion
+/// line and I/O counters are added to `report`, which is returned.
+fn stream_to_output(
+    report: Report,
+    format: OutputFormat,
+    store: &BlockStore,
+    mut reader: DatasetReader<'_>,
+    output_file: &Path,
+    read_repair: bool,
+    banner: String,
+) -> CliResult<Report> {
+    // Status lines go to stderr when the payload itself is streamed to stdout.
+    let to_stdout = output_file.as_os_str() == "-";
+    if format == OutputFormat::Text {
+        if to_stdout {
+            eprintln!("{}", banner);
+        } else {
+            println!("{}", banner);
+        }
+    }
+    if to_stdout {
+        let mut writer = BufWriter::new(io::stdout().lock());
+        io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+    } else {
+        // Written beside the target and renamed into place, so a failed read
+        // never leaves a truncated file under the requested name.
+        let mut partial = output_file.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let copied = File::create(&partial).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            io::copy(&mut reader, &mut writer)?;
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+        });
+        if let Err(err) = copied {
+            let _ = fs::remove_file(&partial);
+            return Err(err.into());
+        }
+        fs::rename(&partial, output_file)?;
+    }
+
+    let mut report = Report {
+        status_to_stderr: to_stdout,
+        ..Report::for_store(
+            report.command,
+            report.dataset.as_deref().unwrap_or_default(),
+            store,
+        )
+    };
+    report.total_size = Some(reader.total_size());
+    if read_repair {
+        let repaired = store.read_repaired_shards();
+        report = report.field("read_repaired_shards", repaired).line(format!(
+            "Read complete. Read-repair rewrote {} shard(s).",
+            repaired
+        ));
+    } else {
+        report = report.line("Read complete.".to_string());
+    }
+    Ok(report)
+}
+
+fn block_health_json(block: &BlockScrubReport) -> serde_json::Value {
+    let (health, parity_remaining) = match block.health {
+        BlockHealth::Healthy => ("healthy", None),
+        BlockHealth::Degraded {
+            parity_remaining, ..
+        } => ("degraded", Some(parity_remaining)),
+        BlockHealth::Unrecoverable => ("unrecoverable", None),
+    };
+    serde_json::json!({
+        "block_id": block.block_id,
+        "file": block.file_name,
+        "offset": block.offset,
+        "size": block.original_size,
+        "health": health,
+        "parity_remaining": parity_remaining,
+        "bad_shards": block.bad_shards(),
+        "plaintext_verified": block.plaintext_verified,
+    })
+}
+
+fn describe_block_health(block: &BlockScrubReport) -> String {
+    let range = format!(
+        "[{}..{})",
+        block.offset,
+        block.offset.saturating_add(block.original_size)
+    );
+    let state = match block.health {
+        BlockHealth::Healthy => "healthy".to_string(),
+        BlockHealth::Degraded {
+            parity_remaining,
+            parity_total,
+        } => format!(
+            "degraded, {} of {} parity remaining",
+            parity_remaining, parity_total
+        ),
+        BlockHealth::Unrecoverable => "UNRECOVERABLE".to_string(),
+    };
+    let bad_shards = block.bad_shards();
+    let mut line = format!(
+        "Block {} ({} {}): {}",
+        block.block_id, block.file_name, range, state
+    );
+    if !bad_shards.is_empty() {
+        let list: Vec<String> = bad_shards.iter().map(|i| i.to_string()).collect();
+        line.push_str(&format!(" (bad shards: {})", list.join(", ")));
+    }
+    if block.plaintext_verified == Some(false) {
+        line.push_str(" (plaintext verification failed)");
+    }
+    line
+}
+
 fn dataset_path(dataset: &str) -> Result<PathBuf> {
     if dataset.is_empty() {
         return Err(anyhow!("Dataset name cannot be empty"));
@@ -221,6 +2630,106 @@ fn dataset_path(dataset: &str) -> Result<PathBuf> {
     Ok(PathBuf::from(STORAGE_DIR).join(dataset))
 }
 
+/// Chunking for `--chunking` with a `--block-size` maximum.
+fn chunking_for(chunking: ChunkingArg, block_size: usize) -> CliResult<Chunking> {
+    if block_size == 0 {
+        return Err(CliFailure {
+            code: ErrorCode::InvalidArgument,
+            error: anyhow!("--block-size must be greater than zero"),
+        });
+    }
+    chunking
+        .to_chunking(block_size)
+        .map_err(with_code(ErrorCode::InvalidArgument))
+}
+
+fn input_file_name(input_file: &Path) -> Result<String> {
+    Ok(input_file
+        .file_name()
+        .ok_or_else(|| anyhow!("Input path has no file name: {}", input_file.display()))?
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// One line of an `apply` edit script.
+enum ScriptEdit {
+    Insert { offset: u64, text: Vec<u8> },
+    Overwrite { offset: u64, text: Vec<u8> },
+    Delete { offset: u64, length: u64 },
+}
+
+/// Parses an edit script into edits tagged with their line numbers. Text
+/// runs from the single space after the offset to the end of the line and
+/// may use `\n`, `\t` and `\\` escapes; blank lines and `#` comments are
+/// skipped.
+fn parse_edit_script(script: &str) -> Result<Vec<(usize, ScriptEdit)>> {
+    let mut edits = Vec::new();
+    for (index, raw) in script.lines().enumerate() {
+        let line = index + 1;
+        let trimmed = raw.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let (op, rest) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+        let (offset, argument) = rest.split_once(' ').unwrap_or((rest, ""));
+        let offset: u64 = offset
+            .parse()
+            .map_err(|_| anyhow!("Line {}: invalid offset '{}'", line, offset))?;
+        let text = || -> Result<Vec<u8>> {
+            if argument.is_empty() {
+                return Err(anyhow!("Line {}: {} needs text", line, op));
+            }
+            unescape_script_text(argument).map_err(|e| anyhow!("Line {}: {}", line, e))
+        };
+        let edit = match op {
+            "insert" => ScriptEdit::Insert {
+                offset,
+                text: text()?,
+            },
+            "overwrite" => ScriptEdit::Overwrite {
+                offset,
+                text: text()?,
+            },
+            "delete" => ScriptEdit::Delete {
+                offset,
+                length: argument
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Line {}: invalid length '{}'", line, argument))?,
+            },
+            other => {
+                return Err(anyhow!(
+                    "Line {}: unknown edit '{}' (expected insert, overwrite or delete)",
+                    line,
+                    other
+                ));
+            }
+        };
+        edits.push((line, edit));
+    }
+    Ok(edits)
+}
+
+fn unescape_script_text(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some(other) => return Err(anyhow!("unknown escape '\\{}'", other)),
+            None => return Err(anyhow!("text ends with a lone '\\'")),
+        }
+    }
+    Ok(bytes)
+}
+
 fn validate_shard_config(data_shards: usize, parity_shards: usize) -> Result<()> {
     if data_shards == 0 {
         return Err(anyhow!("data_shards must be greater than zero"));
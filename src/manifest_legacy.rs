@@ -0,0 +1,106 @@
+//! The manifest layout of the first release, which carried no format
+//! version.
+//!
+//! It held one file with a flat block list. Copies in this layout are only
+//! ever decoded, and are upgraded to the current `Manifest` in memory; the
+//! next commit writes the versioned encoding.
+
+use crate::compression::Compression;
+use crate::integrity::{BlockMetadata, Manifest};
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct BaselineBlock {
+    id: usize,
+    original_size: u64,
+    data_shards: usize,
+    parity_shards: usize,
+    shard_hashes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct BaselineManifest {
+    epoch: u64,
+    file_name: String,
+    total_size: u64,
+    blocks: Vec<BaselineBlock>,
+}
+
+impl BaselineBlock {
+    fn upgrade(self) -> BlockMetadata {
+        BlockMetadata {
+            id: self.id,
+            original_size: self.original_size,
+            data_shards: self.data_shards,
+            parity_shards: self.parity_shards,
+            shard_hashes: self.shard_hashes,
+            content_key: None,
+            compression: Compression::None,
+        }
+    }
+}
+
+impl BaselineManifest {
+    fn upgrade(self) -> Manifest {
+        let mut manifest = Manifest::new(&self.file_name);
+        let file = &mut manifest.files[0];
+        file.total_size = self.total_size;
+        file.blocks = self
+            .blocks
+            .into_iter()
+            .map(BaselineBlock::upgrade)
+            .collect();
+        manifest.with_epoch(self.epoch)
+    }
+}
+
+/// Decodes a manifest written before versioning and upgrades it.
+pub fn decode_unversioned(bytes: &[u8]) -> Result<Manifest> {
+    let config = bincode::config::standard();
+    let (baseline, used) = bincode::serde::decode_from_slice::<BaselineManifest, _>(bytes, config)?;
+    if used != bytes.len() {
+        return Err(anyhow!("Trailing bytes in unversioned manifest"));
+    }
+    Ok(baseline.upgrade())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Block {
+        id: usize,
+        original_size: u64,
+        data_shards: usize,
+        parity_shards: usize,
+        shard_hashes: Vec<String>,
+    }
+
+    #[test]
+    fn test_baseline_manifest_is_upgraded() {
+        let block = Block {
+            id: 2,
+            original_size: 10,
+            data_shards: 1,
+            parity_shards: 1,
+            shard_hashes: vec!["a".to_string(), "b".to_string()],
+        };
+        let baseline = (9u64, "data.bin", 10u64, vec![block]);
+        let bytes = bincode::serde::encode_to_vec(&baseline, bincode::config::standard()).unwrap();
+        let manifest = decode_unversioned(&bytes).unwrap();
+        assert_eq!(manifest.epoch, 9);
+        assert_eq!(manifest.files.len(), 1);
+        let file = &manifest.files[0];
+        assert_eq!((file.name.as_str(), file.total_size), ("data.bin", 10));
+        assert_eq!(file.blocks[0].shard_hashes, vec!["a", "b"]);
+        manifest.validate().unwrap();
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(decode_unversioned(&trailing).is_err());
+        assert!(decode_unversioned(b"not a manifest").is_err());
+    }
+}
@@ -1,12 +1,18 @@
+use crate::backend::ShardBackend;
 use crate::chunk_format::{ChunkEnvelope, decode_envelope};
 use crate::integrity::Manifest;
+use crate::manifest_legacy;
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
-use std::fs;
 use std::io::Cursor;
-use std::path::Path;
 
 const REQUIRED_METADATA_QUORUM: usize = 2;
+/// Leads every versioned manifest encoding. A bincode varint never starts
+/// with 0xFF, so manifests written before versioning cannot carry it.
+const MANIFEST_MAGIC: [u8; 4] = [0xFF, b'I', b'M', b'F'];
+/// Bump whenever `Manifest` or anything inside it changes shape, and keep
+/// decoding the previous version.
+const MANIFEST_FORMAT_VERSION: u16 = 1;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct CandidateKey {
@@ -14,23 +20,54 @@ struct CandidateKey {
     manifest_hash: [u8; 32],
 }
 
-pub fn load_manifest_from_chunks(root_path: &Path, meta_mac_key: &[u8; 32]) -> Result<Manifest> {
+/// One distinct manifest seen during discovery and how many authenticated
+/// copies (shard envelopes and fallback objects) carry it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManifestCandidate {
+    pub epoch: u64,
+    pub manifest_hash: [u8; 32],
+    pub copies: usize,
+}
+
+impl ManifestCandidate {
+    pub fn has_quorum(&self) -> bool {
+        self.copies >= REQUIRED_METADATA_QUORUM
+    }
+}
+
+/// The manifest that won discovery, with every candidate that was seen.
+#[derive(Debug, Clone)]
+pub struct ManifestDiscovery {
+    pub manifest: Manifest,
+    pub winner: ManifestCandidate,
+    /// Every other candidate, newest epoch first, including those below quorum.
+    pub losers: Vec<ManifestCandidate>,
+}
+
+pub fn load_manifest_from_chunks(
+    backend: &dyn ShardBackend,
+    meta_mac_key: &[u8; 32],
+) -> Result<Manifest> {
+    discover_manifest(backend, meta_mac_key).map(|discovery| discovery.manifest)
+}
+
+/// Scans every object for authenticated manifest copies and picks the
+/// highest epoch that reached quorum, keeping the copy counts of all candidates.
+pub fn discover_manifest(
+    backend: &dyn ShardBackend,
+    meta_mac_key: &[u8; 32],
+) -> Result<ManifestDiscovery> {
     let mut counts: HashMap<CandidateKey, usize> = HashMap::new();
     let mut manifests: HashMap<CandidateKey, Manifest> = HashMap::new();
 
-    for entry in fs::read_dir(root_path)? {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        if !file_type.is_file() {
-            continue;
-        }
-        if entry.path().extension().and_then(|e| e.to_str()) != Some("bin") {
+    for name in backend.list()? {
+        if !name.ends_with(".bin") {
             continue;
         }
 
-        let bytes = match fs::read(entry.path()) {
-            Ok(bytes) => bytes,
-            Err(_) => continue,
+        let bytes = match backend.get(&name) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) | Err(_) => continue,
         };
         let envelope = match decode_envelope(&bytes, meta_mac_key) {
             Ok(envelope) => envelope,
@@ -49,50 +86,60 @@ pub fn load_manifest_from_chunks(root_path: &Path, meta_mac_key: &[u8; 32]) -> R
         manifests.entry(key).or_insert(manifest);
     }
 
-    let mut qualified: Vec<(CandidateKey, usize)> = counts
+    let mut candidates: Vec<ManifestCandidate> = counts
         .into_iter()
-        .filter(|(_, count)| *count >= REQUIRED_METADATA_QUORUM)
-        .collect();
-    if qualified.is_empty() {
-        return Err(anyhow!(
-            "Critical Failure: No committed manifest quorum found in chunk files"
-        ));
-    }
-
-    qualified.sort_by_key(|(key, _)| key.epoch);
-    let highest_epoch = qualified
-        .last()
-        .map(|(key, _)| key.epoch)
-        .ok_or_else(|| anyhow!("No manifest candidates after quorum filtering"))?;
-
-    let mut winners: Vec<CandidateKey> = qualified
-        .into_iter()
-        .filter_map(|(key, _)| (key.epoch == highest_epoch).then_some(key))
+        .map(|(key, copies)| ManifestCandidate {
+            epoch: key.epoch,
+            manifest_hash: key.manifest_hash,
+            copies,
+        })
         .collect();
+    candidates.sort_by(|a, b| {
+        b.epoch
+            .cmp(&a.epoch)
+            .then(a.manifest_hash.cmp(&b.manifest_hash))
+    });
 
-    winners.sort_by_key(|k| k.manifest_hash);
-    winners.dedup();
+    let highest_epoch = candidates
+        .iter()
+        .find(|candidate| candidate.has_quorum())
+        .map(|candidate| candidate.epoch)
+        .ok_or_else(|| {
+            anyhow!("Critical Failure: No committed manifest quorum found in chunk files")
+        })?;
 
-    if winners.len() > 1 {
+    let mut winners = candidates
+        .iter()
+        .filter(|candidate| candidate.epoch == highest_epoch && candidate.has_quorum());
+    let winner = *winners
+        .next()
+        .ok_or_else(|| anyhow!("No winner for manifest consensus"))?;
+    if winners.next().is_some() {
         return Err(anyhow!(
             "Integrity Failure: Multiple manifest quorums at epoch {}",
             highest_epoch
         ));
     }
 
-    let winner = winners
-        .into_iter()
-        .next()
-        .ok_or_else(|| anyhow!("No winner for manifest consensus"))?;
-
-    manifests
-        .remove(&winner)
-        .ok_or_else(|| anyhow!("Manifest winner missing payload"))
+    let manifest = manifests
+        .remove(&CandidateKey {
+            epoch: winner.epoch,
+            manifest_hash: winner.manifest_hash,
+        })
+        .ok_or_else(|| anyhow!("Manifest winner missing payload"))?;
+    candidates.retain(|candidate| *candidate != winner);
+    Ok(ManifestDiscovery {
+        manifest,
+        winner,
+        losers: candidates,
+    })
 }
 
 pub fn encode_manifest_snapshot(manifest: &Manifest) -> Result<(Vec<u8>, [u8; 32])> {
     let config = bincode::config::standard();
-    let manifest_bytes = bincode::serde::encode_to_vec(manifest, config)?;
+    let mut manifest_bytes = MANIFEST_MAGIC.to_vec();
+    manifest_bytes.extend_from_slice(&MANIFEST_FORMAT_VERSION.to_le_bytes());
+    manifest_bytes.extend(bincode::serde::encode_to_vec(manifest, config)?);
     let manifest_hash = *blake3::hash(&manifest_bytes).as_bytes();
     let compressed = zstd::stream::encode_all(Cursor::new(manifest_bytes), 3)?;
     Ok((compressed, manifest_hash))
@@ -105,32 +152,48 @@ pub fn decode_embedded_manifest(envelope: &ChunkEnvelope) -> Result<Manifest> {
         return Err(anyhow!("Manifest hash mismatch in envelope"));
     }
 
+    let manifest = decode_manifest_bytes(&decompressed)?;
+    manifest.validate()?;
+    manifest.verify_merkle_root()?;
+    Ok(manifest)
+}
+
+/// Decodes a versioned manifest, or upgrades one written before versioning.
+fn decode_manifest_bytes(bytes: &[u8]) -> Result<Manifest> {
+    let Some(rest) = bytes.strip_prefix(&MANIFEST_MAGIC) else {
+        return manifest_legacy::decode_unversioned(bytes);
+    };
+    let (version, body) = rest
+        .split_first_chunk::<2>()
+        .ok_or_else(|| anyhow!("Embedded manifest is truncated"))?;
+    let version = u16::from_le_bytes(*version);
+    if version != MANIFEST_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Manifest format version {} is not supported (expected {})",
+            version,
+            MANIFEST_FORMAT_VERSION
+        ));
+    }
     let config = bincode::config::standard();
-    let (manifest, used) = bincode::serde::decode_from_slice::<Manifest, _>(&decompressed, config)?;
-    if used != decompressed.len() {
+    let (manifest, used) = bincode::serde::decode_from_slice::<Manifest, _>(body, config)?;
+    if used != body.len() {
         return Err(anyhow!("Trailing bytes in embedded manifest"));
     }
-    manifest.validate()?;
     Ok(manifest)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::MemoryBackend;
     use crate::chunk_format::{ChunkEnvelope, encode_envelope};
-    use tempfile::tempdir;
 
     fn manifest(epoch: u64, name: &str) -> Manifest {
-        Manifest {
-            epoch,
-            file_name: name.to_string(),
-            total_size: 0,
-            blocks: Vec::new(),
-        }
+        Manifest::new(name).with_epoch(epoch)
     }
 
     fn write_meta_copy(
-        root: &Path,
+        backend: &MemoryBackend,
         name: &str,
         m: &Manifest,
         key: &[u8; 32],
@@ -139,59 +202,114 @@ mod tests {
         let (blob, hash) = encode_manifest_snapshot(m)?;
         let env = ChunkEnvelope::meta_only(epoch, hash, blob);
         let bytes = encode_envelope(&env, key)?;
-        fs::write(root.join(name), bytes)?;
+        backend.put(name, &bytes)?;
         Ok(())
     }
 
     #[test]
     fn test_recovery_selects_highest_epoch_quorum() {
-        let dir = tempdir().expect("tempdir");
+        let backend = MemoryBackend::new();
         let key = [5u8; 32];
         let old = manifest(2, "old");
         let new = manifest(3, "new");
 
-        write_meta_copy(dir.path(), "meta_2_0.bin", &old, &key, 2).expect("old0");
-        write_meta_copy(dir.path(), "meta_2_1.bin", &old, &key, 2).expect("old1");
-        write_meta_copy(dir.path(), "meta_3_0.bin", &new, &key, 3).expect("new0");
-        write_meta_copy(dir.path(), "meta_3_1.bin", &new, &key, 3).expect("new1");
+        write_meta_copy(&backend, "meta_2_0.bin", &old, &key, 2).expect("old0");
+        write_meta_copy(&backend, "meta_2_1.bin", &old, &key, 2).expect("old1");
+        write_meta_copy(&backend, "meta_3_0.bin", &new, &key, 3).expect("new0");
+        write_meta_copy(&backend, "meta_3_1.bin", &new, &key, 3).expect("new1");
 
-        let recovered = load_manifest_from_chunks(dir.path(), &key).expect("recover");
+        let recovered = load_manifest_from_chunks(&backend, &key).expect("recover");
         assert_eq!(recovered, new);
     }
 
     #[test]
     fn test_recovery_conflict_same_epoch_fails() {
-        let dir = tempdir().expect("tempdir");
+        let backend = MemoryBackend::new();
         let key = [6u8; 32];
         let a = manifest(4, "a");
         let b = manifest(4, "b");
 
-        write_meta_copy(dir.path(), "meta_4_a0.bin", &a, &key, 4).expect("a0");
-        write_meta_copy(dir.path(), "meta_4_a1.bin", &a, &key, 4).expect("a1");
-        write_meta_copy(dir.path(), "meta_4_b0.bin", &b, &key, 4).expect("b0");
-        write_meta_copy(dir.path(), "meta_4_b1.bin", &b, &key, 4).expect("b1");
+        write_meta_copy(&backend, "meta_4_a0.bin", &a, &key, 4).expect("a0");
+        write_meta_copy(&backend, "meta_4_a1.bin", &a, &key, 4).expect("a1");
+        write_meta_copy(&backend, "meta_4_b0.bin", &b, &key, 4).expect("b0");
+        write_meta_copy(&backend, "meta_4_b1.bin", &b, &key, 4).expect("b1");
 
-        let err = load_manifest_from_chunks(dir.path(), &key).expect_err("must fail");
+        let err = load_manifest_from_chunks(&backend, &key).expect_err("must fail");
         assert!(err.to_string().contains("Multiple manifest quorums"));
     }
 
+    fn write_raw_meta_copy(backend: &MemoryBackend, name: &str, raw: Vec<u8>, key: &[u8; 32]) {
+        let hash = *blake3::hash(&raw).as_bytes();
+        let blob = zstd::stream::encode_all(Cursor::new(raw), 3).unwrap();
+        let env = ChunkEnvelope::meta_only(1, hash, blob);
+        backend
+            .put(name, &encode_envelope(&env, key).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_baseline_copies_are_upgraded_and_unknown_versions_rejected() {
+        let backend = MemoryBackend::new();
+        let key = [9u8; 32];
+        let config = bincode::config::standard();
+        let no_blocks: Vec<()> = Vec::new();
+        let baseline = bincode::serde::encode_to_vec((1u64, "plain", 0u64, no_blocks), config)
+            .expect("encode");
+        write_raw_meta_copy(&backend, "meta_1_0.bin", baseline.clone(), &key);
+        write_raw_meta_copy(&backend, "meta_1_1.bin", baseline, &key);
+        let recovered = load_manifest_from_chunks(&backend, &key).expect("recover");
+        assert_eq!(recovered, manifest(1, "plain"));
+
+        let (blob, _) = encode_manifest_snapshot(&recovered).expect("encode");
+        let mut future = zstd::stream::decode_all(Cursor::new(blob)).expect("decompress");
+        future[MANIFEST_MAGIC.len()] += 1;
+        let err = decode_manifest_bytes(&future).unwrap_err();
+        assert!(err.to_string().contains("not supported"));
+    }
+
     #[test]
     fn test_recovery_ignores_bad_mac_copy() {
-        let dir = tempdir().expect("tempdir");
+        let backend = MemoryBackend::new();
         let key = [7u8; 32];
         let m = manifest(1, "ok");
 
-        write_meta_copy(dir.path(), "meta_1_0.bin", &m, &key, 1).expect("copy0");
-        write_meta_copy(dir.path(), "meta_1_1.bin", &m, &key, 1).expect("copy1");
-        write_meta_copy(dir.path(), "meta_1_bad.bin", &m, &key, 1).expect("bad");
+        write_meta_copy(&backend, "meta_1_0.bin", &m, &key, 1).expect("copy0");
+        write_meta_copy(&backend, "meta_1_1.bin", &m, &key, 1).expect("copy1");
+        write_meta_copy(&backend, "meta_1_bad.bin", &m, &key, 1).expect("bad");
 
-        let bad_path = dir.path().join("meta_1_bad.bin");
-        let mut bytes = fs::read(&bad_path).expect("read");
+        let mut bytes = backend.get("meta_1_bad.bin").expect("read").expect("copy");
         let idx = bytes.len() / 2;
         bytes[idx] ^= 0x01;
-        fs::write(&bad_path, bytes).expect("write");
+        backend.put("meta_1_bad.bin", &bytes).expect("write");
 
-        let recovered = load_manifest_from_chunks(dir.path(), &key).expect("recover");
+        let recovered = load_manifest_from_chunks(&backend, &key).expect("recover");
         assert_eq!(recovered, m);
     }
+
+    #[test]
+    fn test_discovery_counts_copies_of_winner_and_losers() {
+        let backend = MemoryBackend::new();
+        let key = [8u8; 32];
+        let old = manifest(2, "old");
+        let new = manifest(3, "new");
+        let torn = manifest(4, "torn");
+
+        for i in 0..2 {
+            write_meta_copy(&backend, &format!("meta_2_{}.bin", i), &old, &key, 2).expect("old");
+        }
+        for i in 0..3 {
+            write_meta_copy(&backend, &format!("meta_3_{}.bin", i), &new, &key, 3).expect("new");
+        }
+        write_meta_copy(&backend, "meta_4_0.bin", &torn, &key, 4).expect("torn");
+
+        let discovery = discover_manifest(&backend, &key).expect("discover");
+        assert_eq!(discovery.manifest, new);
+        assert_eq!((discovery.winner.epoch, discovery.winner.copies), (3, 3));
+        let losers: Vec<(u64, usize, bool)> = discovery
+            .losers
+            .iter()
+            .map(|c| (c.epoch, c.copies, c.has_quorum()))
+            .collect();
+        assert_eq!(losers, vec![(4, 1, false), (2, 2, true)]);
+    }
 }
@@ -0,0 +1,376 @@
+use crate::integrity::{FileEntry, Manifest};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+// Domain separation so a leaf can never be passed off as an inner node and
+// neither can be passed off as a root.
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+const ROOT_TAG: u8 = 2;
+
+/// What the tree commits to for one block: where its bytes sit in which file
+/// and which shards hold them. Plaintext-derived keys are left out so a proof
+/// reveals nothing about content.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleLeaf {
+    pub file: String,
+    pub offset: u64,
+    pub size: u64,
+    pub block_id: usize,
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub shard_hashes: Vec<String>,
+}
+
+impl MerkleLeaf {
+    fn hash(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[LEAF_TAG]);
+        hash_bytes(&mut hasher, self.file.as_bytes());
+        for value in [
+            self.offset,
+            self.size,
+            self.block_id as u64,
+            self.data_shards as u64,
+            self.parity_shards as u64,
+            self.shard_hashes.len() as u64,
+        ] {
+            hasher.update(&value.to_le_bytes());
+        }
+        for shard_hash in &self.shard_hashes {
+            hash_bytes(&mut hasher, shard_hash.as_bytes());
+        }
+        *hasher.finalize().as_bytes()
+    }
+}
+
+/// Feeds a length-prefixed field, so adjacent fields cannot run together.
+fn hash_bytes(hasher: &mut blake3::Hasher, bytes: &[u8]) {
+    hasher.update(&(bytes.len() as u64).to_le_bytes());
+    hasher.update(bytes);
+}
+
+/// One leaf per block of every file, files in manifest order and blocks in
+/// file order.
+pub fn leaves(files: &[FileEntry]) -> Vec<MerkleLeaf> {
+    let mut leaves = Vec::new();
+    for file in files {
+        let mut offset = 0u64;
+        for block in &file.blocks {
+            leaves.push(MerkleLeaf {
+                file: file.name.clone(),
+                offset,
+                size: block.original_size,
+                block_id: block.id,
+                data_shards: block.data_shards,
+                parity_shards: block.parity_shards,
+                shard_hashes: block.shard_hashes.clone(),
+            });
+            offset = offset.saturating_add(block.original_size);
+        }
+    }
+    leaves
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_TAG]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+/// Binds the tree to an epoch and its size, so a root names one dataset
+/// version and a proof cannot be replayed against a shorter tree.
+fn root_hash(epoch: u64, leaf_count: usize, tree: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[ROOT_TAG]);
+    hasher.update(&epoch.to_le_bytes());
+    hasher.update(&(leaf_count as u64).to_le_bytes());
+    hasher.update(tree);
+    *hasher.finalize().as_bytes()
+}
+
+/// Every level of the tree, leaves first. A level with an odd count carries
+/// its last node up unchanged.
+fn levels(leaf_hashes: Vec<[u8; 32]>) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaf_hashes];
+    loop {
+        let current = &levels[levels.len() - 1];
+        if current.len() <= 1 {
+            return levels;
+        }
+        let next = current
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                _ => pair[0],
+            })
+            .collect();
+        levels.push(next);
+    }
+}
+
+/// Root of the tree over `files` at `epoch`.
+pub fn root(epoch: u64, files: &[FileEntry]) -> [u8; 32] {
+    let leaf_hashes: Vec<[u8; 32]> = leaves(files).iter().map(MerkleLeaf::hash).collect();
+    let leaf_count = leaf_hashes.len();
+    let tree = levels(leaf_hashes)
+        .last()
+        .and_then(|level| level.first().copied())
+        .unwrap_or([0u8; 32]);
+    root_hash(epoch, leaf_count, &tree)
+}
+
+/// A leaf with the sibling hashes leading from it to the tree's top.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LeafProof {
+    pub index: usize,
+    pub leaf: MerkleLeaf,
+    /// Hex sibling hashes, bottom up; levels where the node was carried up
+    /// have no entry.
+    pub path: Vec<String>,
+}
+
+/// Evidence that bytes `offset..offset + length` of `file` are held by the
+/// listed blocks in the dataset version whose root is `root` at `epoch`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RangeProof {
+    pub epoch: u64,
+    /// Hex Merkle root recorded in the manifest of `epoch`.
+    pub root: String,
+    pub leaf_count: usize,
+    pub file: String,
+    pub offset: u64,
+    pub length: u64,
+    pub leaves: Vec<LeafProof>,
+}
+
+impl RangeProof {
+    /// Builds a proof for a non-empty byte range of `file` in `manifest`.
+    pub fn build(manifest: &Manifest, file: &str, offset: u64, length: u64) -> Result<Self> {
+        let entry = manifest
+            .file(file)
+            .ok_or_else(|| anyhow!("File '{}' not found", file))?;
+        if length == 0 {
+            return Err(anyhow!("Proof range must not be empty"));
+        }
+        let end = offset
+            .checked_add(length)
+            .filter(|&end| end <= entry.total_size)
+            .ok_or_else(|| anyhow!("Proof range out of bounds"))?;
+
+        let all_leaves = leaves(&manifest.files);
+        let leaf_hashes: Vec<[u8; 32]> = all_leaves.iter().map(MerkleLeaf::hash).collect();
+        let leaf_count = leaf_hashes.len();
+        let levels = levels(leaf_hashes);
+
+        let mut proofs = Vec::new();
+        for (index, leaf) in all_leaves.iter().enumerate() {
+            let leaf_end = leaf.offset.saturating_add(leaf.size);
+            if leaf.file != file || leaf_end <= offset || leaf.offset >= end {
+                continue;
+            }
+            let mut path = Vec::new();
+            let mut position = index;
+            for level in &levels[..levels.len() - 1] {
+                if let Some(sibling) = level.get(position ^ 1) {
+                    path.push(blake3::Hash::from(*sibling).to_hex().to_string());
+                }
+                position /= 2;
+            }
+            proofs.push(LeafProof {
+                index,
+                leaf: leaf.clone(),
+                path,
+            });
+        }
+
+        Ok(RangeProof {
+            epoch: manifest.epoch,
+            root: blake3::Hash::from(manifest.merkle_root)
+                .to_hex()
+                .to_string(),
+            leaf_count,
+            file: file.to_string(),
+            offset,
+            length,
+            leaves: proofs,
+        })
+    }
+
+    /// Checks that the leaves are adjacent blocks of `file` covering the whole
+    /// range and that each one hashes up to `root`. Needs no key and no access
+    /// to the dataset; whether `root` and `epoch` are the ones to trust is up
+    /// to the caller.
+    pub fn verify(&self) -> Result<()> {
+        let expected = parse_hash(&self.root)?;
+        let end = self
+            .offset
+            .checked_add(self.length)
+            .ok_or_else(|| anyhow!("Proof range overflows"))?;
+        if self.length == 0 {
+            return Err(anyhow!("Proof range must not be empty"));
+        }
+        let (first, last) = match (self.leaves.first(), self.leaves.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(anyhow!("Proof contains no blocks")),
+        };
+        if first.leaf.offset > self.offset || last.leaf.offset.saturating_add(last.leaf.size) < end
+        {
+            return Err(anyhow!(
+                "Proven blocks do not cover bytes {}..{}",
+                self.offset,
+                end
+            ));
+        }
+        for pair in self.leaves.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if b.index != a.index + 1 || b.leaf.offset != a.leaf.offset.saturating_add(a.leaf.size)
+            {
+                return Err(anyhow!(
+                    "Proven blocks {} and {} are not adjacent",
+                    a.index,
+                    b.index
+                ));
+            }
+        }
+
+        for proof in &self.leaves {
+            if proof.leaf.file != self.file {
+                return Err(anyhow!(
+                    "Block {} belongs to '{}', not '{}'",
+                    proof.index,
+                    proof.leaf.file,
+                    self.file
+                ));
+            }
+            if proof.index >= self.leaf_count {
+                return Err(anyhow!("Block index {} outside the tree", proof.index));
+            }
+            let tree = self.climb(proof)?;
+            if root_hash(self.epoch, self.leaf_count, &tree) != expected {
+                return Err(anyhow!(
+                    "Block {} does not hash to the root of epoch {}",
+                    proof.index,
+                    self.epoch
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Recomputes the top of the tree from one leaf and its path.
+    fn climb(&self, proof: &LeafProof) -> Result<[u8; 32]> {
+        let mut hash = proof.leaf.hash();
+        let mut position = proof.index;
+        let mut width = self.leaf_count;
+        let mut siblings = proof.path.iter();
+        while width > 1 {
+            let carried = position == width - 1 && !width.is_multiple_of(2);
+            if !carried {
+                let sibling = siblings
+                    .next()
+                    .ok_or_else(|| anyhow!("Proof path for block {} is too short", proof.index))
+                    .and_then(|hex| parse_hash(hex))?;
+                hash = if position.is_multiple_of(2) {
+                    node_hash(&hash, &sibling)
+                } else {
+                    node_hash(&sibling, &hash)
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        if siblings.next().is_some() {
+            return Err(anyhow!("Proof path for block {} is too long", proof.index));
+        }
+        Ok(hash)
+    }
+}
+
+fn parse_hash(hex: &str) -> Result<[u8; 32]> {
+    blake3::Hash::from_hex(hex)
+        .map(|hash| *hash.as_bytes())
+        .map_err(|_| anyhow!("Invalid hash '{}'", hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use crate::integrity::BlockMetadata;
+
+    fn manifest(sizes: &[u64]) -> Manifest {
+        let mut manifest = Manifest::new("a.txt");
+        manifest.files.push(FileEntry::new("b.txt"));
+        for (id, &size) in sizes.iter().enumerate() {
+            manifest.files[id % 2].add_block(BlockMetadata {
+                id,
+                original_size: size,
+                data_shards: 1,
+                parity_shards: 1,
+                shard_hashes: vec![format!("{:064x}", id), format!("{:064x}", id + 100)],
+                content_key: None,
+                compression: Compression::None,
+            });
+        }
+        manifest.with_epoch(9)
+    }
+
+    #[test]
+    fn test_range_proofs_verify_for_every_tree_shape() {
+        for count in 1..=9 {
+            let sizes: Vec<u64> = (0..count).map(|i| 10 + i).collect();
+            let manifest = manifest(&sizes);
+            for file in manifest.files.iter().filter(|file| file.total_size > 0) {
+                for offset in 0..file.total_size {
+                    let proof = RangeProof::build(&manifest, &file.name, offset, 1).unwrap();
+                    proof.verify().unwrap();
+                }
+                let whole = RangeProof::build(&manifest, &file.name, 0, file.total_size).unwrap();
+                assert_eq!(whole.leaves.len(), file.blocks.len());
+                whole.verify().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_root_depends_on_epoch_and_block_metadata() {
+        let manifest = manifest(&[5, 6, 7]);
+        let root = manifest.merkle_root;
+        assert_eq!(root, super::root(9, &manifest.files));
+        assert_ne!(root, super::root(10, &manifest.files));
+
+        let mut changed = manifest.clone();
+        changed.files[0].blocks[0].shard_hashes[1] = format!("{:064x}", 7);
+        assert_ne!(root, super::root(9, &changed.files));
+    }
+
+    #[test]
+    fn test_tampered_proofs_are_rejected() {
+        let manifest = manifest(&[5, 6, 7, 8, 9]);
+        let proof = RangeProof::build(&manifest, "a.txt", 3, 6).unwrap();
+        assert_eq!(proof.leaves.len(), 2);
+
+        let mut forged = proof.clone();
+        forged.leaves[0].leaf.shard_hashes[0] = format!("{:064x}", 42);
+        assert!(forged.verify().is_err());
+
+        let mut shifted = proof.clone();
+        shifted.epoch += 1;
+        assert!(shifted.verify().is_err());
+
+        let mut gap = proof.clone();
+        gap.leaves.remove(1);
+        let err = gap.verify().unwrap_err();
+        assert!(err.to_string().contains("do not cover"));
+
+        let mut renamed = proof;
+        renamed.file = "b.txt".to_string();
+        assert!(renamed.verify().is_err());
+
+        assert!(RangeProof::build(&manifest, "a.txt", 20, 10).is_err());
+        assert!(RangeProof::build(&manifest, "a.txt", 0, 0).is_err());
+    }
+}
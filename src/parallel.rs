@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Worker count used when the caller does not choose one.
+pub fn default_parallelism() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Applies `f` to every item on at most `workers` scoped threads.
+///
+/// Results come back in input order regardless of which worker finished
+/// first, so callers stay deterministic. With one worker or one item the
+/// work runs on the calling thread.
+pub fn map_ordered<T, R, F>(items: &[T], workers: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let workers = workers.clamp(1, items.len().max(1));
+    if workers == 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut indexed: Vec<(usize, R)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            return done;
+                        };
+                        done.push((index, f(item)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| match handle.join() {
+                Ok(done) => done,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect()
+    });
+
+    indexed.sort_unstable_by_key(|(index, _)| *index);
+    indexed.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_ordered_preserves_input_order() {
+        let items: Vec<u64> = (0..200).collect();
+        let squares = map_ordered(&items, 8, |n| {
+            // Make later items finish first to exercise reordering.
+            thread::sleep(std::time::Duration::from_micros(200 - n));
+            n * n
+        });
+        assert_eq!(squares, items.iter().map(|n| n * n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_map_ordered_handles_single_worker_and_empty_input() {
+        assert_eq!(map_ordered(&[1, 2, 3], 0, |n| n + 1), vec![2, 3, 4]);
+        assert!(map_ordered(&[] as &[u8], 4, |n| *n).is_empty());
+    }
+}
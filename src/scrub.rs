@@ -0,0 +1,225 @@
+/// State of one stored shard as seen by a scrub pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardStatus {
+    Healthy,
+    Missing,
+    Corrupt,
+}
+
+/// Redundancy left for a block after its shards have been checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockHealth {
+    Healthy,
+    /// Still decodable; `parity_remaining` more shard losses can be tolerated.
+    Degraded {
+        parity_remaining: usize,
+        parity_total: usize,
+    },
+    Unrecoverable,
+}
+
+impl BlockHealth {
+    /// Classifies a block from per-shard statuses and its RS parameters.
+    pub fn classify(statuses: &[ShardStatus], data_shards: usize, parity_shards: usize) -> Self {
+        let healthy = statuses
+            .iter()
+            .filter(|status| **status == ShardStatus::Healthy)
+            .count();
+        let total = data_shards.saturating_add(parity_shards);
+
+        if healthy < data_shards {
+            BlockHealth::Unrecoverable
+        } else if healthy >= total {
+            BlockHealth::Healthy
+        } else {
+            BlockHealth::Degraded {
+                parity_remaining: healthy - data_shards,
+                parity_total: parity_shards,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockScrubReport {
+    pub file_name: String,
+    pub block_id: usize,
+    /// Offset of the block within its file.
+    pub offset: u64,
+    pub original_size: u64,
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub shard_status: Vec<ShardStatus>,
+    pub health: BlockHealth,
+    /// `None` unless the scrub was asked to decrypt; `Some(false)` means the
+    /// shards looked usable but reconstruction or decryption failed.
+    pub plaintext_verified: Option<bool>,
+}
+
+impl BlockScrubReport {
+    /// Indices of shards that are missing or failed verification.
+    pub fn bad_shards(&self) -> Vec<usize> {
+        self.shard_status
+            .iter()
+            .enumerate()
+            .filter_map(|(i, status)| (*status != ShardStatus::Healthy).then_some(i))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrubReport {
+    pub epoch: u64,
+    pub blocks: Vec<BlockScrubReport>,
+}
+
+impl ScrubReport {
+    pub fn healthy_blocks(&self) -> usize {
+        self.count(|health| matches!(health, BlockHealth::Healthy))
+    }
+
+    pub fn degraded_blocks(&self) -> usize {
+        self.count(|health| matches!(health, BlockHealth::Degraded { .. }))
+    }
+
+    pub fn unrecoverable_blocks(&self) -> usize {
+        self.count(|health| matches!(health, BlockHealth::Unrecoverable))
+    }
+
+    /// Smallest remaining parity margin across all decodable blocks.
+    pub fn min_parity_remaining(&self) -> Option<usize> {
+        self.blocks
+            .iter()
+            .filter_map(|block| match block.health {
+                BlockHealth::Healthy => Some(block.parity_shards),
+                BlockHealth::Degraded {
+                    parity_remaining, ..
+                } => Some(parity_remaining),
+                BlockHealth::Unrecoverable => None,
+            })
+            .min()
+    }
+
+    fn count(&self, predicate: impl Fn(&BlockHealth) -> bool) -> usize {
+        self.blocks
+            .iter()
+            .filter(|block| predicate(&block.health))
+            .count()
+    }
+}
+
+/// Returned when a block has fewer intact shards than it needs to decode.
+#[derive(Debug)]
+pub struct UnrecoverableBlock {
+    pub block_id: usize,
+    pub intact_shards: usize,
+    pub data_shards: usize,
+}
+
+impl std::fmt::Display for UnrecoverableBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Block {} is unrecoverable: {} intact shard(s), {} needed",
+            self.block_id, self.intact_shards, self.data_shards
+        )
+    }
+}
+
+impl std::error::Error for UnrecoverableBlock {}
+
+/// What an in-place repair did for one block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepairOutcome {
+    AlreadyHealthy,
+    /// The listed shard indices were rebuilt and rewritten.
+    Repaired {
+        shards: Vec<usize>,
+    },
+    /// Too few healthy shards remain to rebuild anything.
+    Unrecoverable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRepairReport {
+    pub block_id: usize,
+    pub outcome: RepairOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairReport {
+    pub epoch: u64,
+    pub blocks: Vec<BlockRepairReport>,
+}
+
+impl RepairReport {
+    pub fn repaired_blocks(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter(|block| matches!(block.outcome, RepairOutcome::Repaired { .. }))
+            .count()
+    }
+
+    pub fn repaired_shards(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| match &block.outcome {
+                RepairOutcome::Repaired { shards } => shards.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    pub fn unrecoverable_blocks(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter(|block| block.outcome == RepairOutcome::Unrecoverable)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_all_healthy() {
+        let statuses = vec![ShardStatus::Healthy; 6];
+        assert_eq!(BlockHealth::classify(&statuses, 4, 2), BlockHealth::Healthy);
+    }
+
+    #[test]
+    fn test_classify_degraded_counts_remaining_parity() {
+        let statuses = vec![
+            ShardStatus::Healthy,
+            ShardStatus::Missing,
+            ShardStatus::Healthy,
+            ShardStatus::Corrupt,
+            ShardStatus::Healthy,
+            ShardStatus::Healthy,
+            ShardStatus::Healthy,
+            ShardStatus::Healthy,
+        ];
+        assert_eq!(
+            BlockHealth::classify(&statuses, 4, 4),
+            BlockHealth::Degraded {
+                parity_remaining: 2,
+                parity_total: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_classify_below_data_threshold_is_unrecoverable() {
+        let statuses = vec![
+            ShardStatus::Healthy,
+            ShardStatus::Missing,
+            ShardStatus::Missing,
+            ShardStatus::Healthy,
+        ];
+        assert_eq!(
+            BlockHealth::classify(&statuses, 3, 1),
+            BlockHealth::Unrecoverable
+        );
+    }
+}
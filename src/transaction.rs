@@ -0,0 +1,389 @@
+use crate::block_store::{BlockBuilder, BlockStore, ChunkJob, PendingBlock, StoreLock};
+use crate::integrity::BlockMetadata;
+use crate::parallel;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A batch of edits to the selected file that commits as one epoch.
+///
+/// Inserts, overwrites and deletes apply to an in-memory view of the file's
+/// block list, so each edit sees the ones before it. Blocks an edit creates
+/// are encoded right away but only kept in memory; nothing reaches the
+/// backend until `commit`, which writes the new blocks the final view still
+/// uses and publishes it in a single manifest commit. Dropping the
+/// transaction instead discards every edit. The exclusive dataset lock is
+/// held from `BlockStore::transaction` until the transaction ends.
+pub struct Transaction<'a> {
+    store: &'a mut BlockStore,
+    blocks: Vec<BlockMetadata>,
+    total_size: u64,
+    builder: BlockBuilder,
+    pending: Vec<PendingBlock>,
+    /// Plaintext of every block encoded by this transaction, since later
+    /// edits cannot read those blocks back from the backend.
+    staged: HashMap<usize, Vec<u8>>,
+    /// Committed blocks dropped from the view, collected on commit unless
+    /// something else still references them.
+    obsolete: Vec<BlockMetadata>,
+    edits: usize,
+    _lock: StoreLock,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(store: &'a mut BlockStore) -> Result<Self> {
+        let lock = store.write_lock()?;
+        let file = store.active_file()?;
+        let blocks = file.blocks.clone();
+        let total_size = file.total_size;
+        let builder = store.block_builder()?;
+        Ok(Self {
+            store,
+            blocks,
+            total_size,
+            builder,
+            pending: Vec::new(),
+            staged: HashMap::new(),
+            obsolete: Vec::new(),
+            edits: 0,
+            _lock: lock,
+        })
+    }
+
+    /// Size of the file as staged so far.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Block list of the file as staged so far.
+    pub fn blocks(&self) -> &[BlockMetadata] {
+        &self.blocks
+    }
+
+    /// Number of edits that changed the staged view.
+    pub fn edits(&self) -> usize {
+        self.edits
+    }
+
+    /// Reads bytes from the staged view, including uncommitted edits.
+    pub fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let read_end = offset
+            .checked_add(length)
+            .ok_or_else(|| anyhow!("Read range overflow"))?;
+        if read_end > self.total_size {
+            return Err(anyhow!("Read out of bounds"));
+        }
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+
+        let (range, first_start) = BlockStore::overlapping_blocks(&self.blocks, offset, read_end)?;
+        let touched = &self.blocks[range];
+        let mut collected = Vec::new();
+        let mut block_start = first_start;
+        for (block, block_data) in touched.iter().zip(self.read_blocks(touched)) {
+            let block_data = block_data?;
+            let block_end = block_start
+                .checked_add(block.original_size)
+                .ok_or_else(|| anyhow!("Block range overflow"))?;
+            let from = usize::try_from(offset.saturating_sub(block_start))
+                .map_err(|_| anyhow!("Read offset too large for this platform"))?;
+            let to = usize::try_from(u64::min(read_end, block_end) - block_start)
+                .map_err(|_| anyhow!("Read offset too large for this platform"))?;
+            if to > block_data.len() {
+                return Err(anyhow!("Read range is inconsistent with block data"));
+            }
+            collected.extend_from_slice(&block_data[from..to]);
+            block_start = block_end;
+        }
+        Ok(collected)
+    }
+
+    /// Stages an insert at `offset` using the dataset's default layout.
+    pub fn insert(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let redundancy = self.store.redundancy();
+        self.insert_at(
+            offset,
+            data,
+            redundancy.data_shards,
+            redundancy.parity_shards,
+        )
+    }
+
+    /// Stages an insert at `offset` with an explicit layout, splitting the
+    /// block it lands in into [Left, Inserted, Right].
+    pub fn insert_at(
+        &mut self,
+        offset: u64,
+        data: &[u8],
+        data_shards: usize,
+        parity_shards: usize,
+    ) -> Result<()> {
+        BlockStore::validate_shard_config(data_shards, parity_shards)?;
+        if offset > self.total_size {
+            return Err(anyhow!("Insert out of bounds (can append at exact end)"));
+        }
+
+        let inserted = ChunkJob {
+            data,
+            data_shards,
+            parity_shards,
+        };
+        if offset == self.total_size {
+            let new_blocks = self.build(&[inserted])?;
+            let end = self.blocks.len();
+            return self.splice(end..end, new_blocks);
+        }
+
+        let (range, block_start) =
+            BlockStore::overlapping_blocks(&self.blocks, offset, offset + 1)?;
+        let block_to_split = self
+            .blocks
+            .get(range.start)
+            .cloned()
+            .ok_or_else(|| anyhow!("Insert offset not found in manifest blocks"))?;
+        let full_data = self.read_block(&block_to_split)?;
+        let split_idx = usize::try_from(offset - block_start)
+            .map_err(|_| anyhow!("Split offset too large for this platform"))?;
+        if split_idx > full_data.len() {
+            return Err(anyhow!("Split offset is inconsistent with block data"));
+        }
+        let (left_data, right_data) = full_data.split_at(split_idx);
+
+        let split_job = |piece| ChunkJob {
+            data: piece,
+            data_shards: block_to_split.data_shards,
+            parity_shards: block_to_split.parity_shards,
+        };
+        let mut jobs = Vec::with_capacity(3);
+        if !left_data.is_empty() {
+            jobs.push(split_job(left_data));
+        }
+        jobs.push(inserted);
+        if !right_data.is_empty() {
+            jobs.push(split_job(right_data));
+        }
+
+        let new_blocks = self.build(&jobs)?;
+        self.splice(range.start..range.start + 1, new_blocks)
+    }
+
+    /// Stages an overwrite starting at `offset`. Only blocks overlapping the
+    /// written range are re-encoded, each keeping its own shard configuration.
+    /// Bytes past the current end are appended as a new block using the
+    /// dataset's default layout.
+    pub fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let total_size = self.total_size;
+        if offset > total_size {
+            return Err(anyhow!("Write out of bounds (can extend from exact end)"));
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        let write_end = offset
+            .checked_add(data.len() as u64)
+            .ok_or_else(|| anyhow!("Write range overflow"))?;
+
+        let (range, first_start) =
+            BlockStore::overlapping_blocks(&self.blocks, offset, u64::min(write_end, total_size))?;
+        let touched = self.blocks[range.clone()].to_vec();
+        let decoded = self.read_blocks(&touched);
+
+        let mut patched = Vec::with_capacity(touched.len());
+        let mut block_start = first_start;
+        for (block, block_data) in touched.iter().zip(decoded) {
+            let mut block_data = block_data?;
+            let block_end = block_start
+                .checked_add(block.original_size)
+                .ok_or_else(|| anyhow!("Block range overflow"))?;
+            let overlap_start = u64::max(offset, block_start);
+            let overlap_end = u64::min(write_end, block_end);
+
+            let dst_start = usize::try_from(overlap_start - block_start)
+                .map_err(|_| anyhow!("Write offset too large for this platform"))?;
+            let dst_end = usize::try_from(overlap_end - block_start)
+                .map_err(|_| anyhow!("Write offset too large for this platform"))?;
+            let src_start = usize::try_from(overlap_start - offset)
+                .map_err(|_| anyhow!("Write offset too large for this platform"))?;
+            if dst_end > block_data.len() {
+                return Err(anyhow!("Write range is inconsistent with block data"));
+            }
+            let src_end = src_start + (dst_end - dst_start);
+            block_data[dst_start..dst_end].copy_from_slice(&data[src_start..src_end]);
+            patched.push(block_data);
+            block_start = block_end;
+        }
+
+        let mut jobs: Vec<ChunkJob<'_>> = touched
+            .iter()
+            .zip(&patched)
+            .map(|(block, patched)| ChunkJob {
+                data: patched,
+                data_shards: block.data_shards,
+                parity_shards: block.parity_shards,
+            })
+            .collect();
+        if write_end > total_size {
+            let redundancy = self.store.redundancy();
+            let tail_start = usize::try_from(total_size - offset)
+                .map_err(|_| anyhow!("Write offset too large for this platform"))?;
+            jobs.push(ChunkJob {
+                data: &data[tail_start..],
+                data_shards: redundancy.data_shards,
+                parity_shards: redundancy.parity_shards,
+            });
+        }
+
+        let new_blocks = self.build(&jobs)?;
+        self.splice(range, new_blocks)
+    }
+
+    /// Stages deleting the range [offset, offset + length). Only the blocks
+    /// at either edge of the range are decoded, to keep the bytes outside it;
+    /// blocks fully inside the range are simply dropped.
+    pub fn delete_range(&mut self, offset: u64, length: u64) -> Result<()> {
+        if length == 0 {
+            return Ok(());
+        }
+        let delete_end = offset
+            .checked_add(length)
+            .ok_or_else(|| anyhow!("Delete range overflow"))?;
+        if delete_end > self.total_size {
+            return Err(anyhow!("Delete out of bounds"));
+        }
+
+        let (range, first_start) =
+            BlockStore::overlapping_blocks(&self.blocks, offset, delete_end)?;
+        let touched = self.blocks[range.clone()].to_vec();
+        let last_start = touched[..touched.len() - 1]
+            .iter()
+            .try_fold(first_start, |acc, block| {
+                acc.checked_add(block.original_size)
+            })
+            .ok_or_else(|| anyhow!("Block range overflow"))?;
+        let first = &touched[0];
+        let last = &touched[touched.len() - 1];
+
+        let keep_left = usize::try_from(offset - first_start)
+            .map_err(|_| anyhow!("Delete offset too large for this platform"))?;
+        let keep_right_from = usize::try_from(delete_end - last_start)
+            .map_err(|_| anyhow!("Delete offset too large for this platform"))?;
+        let keeps_right = (keep_right_from as u64) < last.original_size;
+
+        // Decode each edge block once, even when both edges are the same block.
+        let mut edges: Vec<usize> = Vec::new();
+        if keep_left > 0 {
+            edges.push(0);
+        }
+        if keeps_right && edges.last() != Some(&(touched.len() - 1)) {
+            edges.push(touched.len() - 1);
+        }
+        let edge_blocks: Vec<BlockMetadata> = edges.iter().map(|&i| touched[i].clone()).collect();
+        let mut edge_data: HashMap<usize, Vec<u8>> = HashMap::new();
+        for (&i, data) in edges.iter().zip(self.read_blocks(&edge_blocks)) {
+            edge_data.insert(i, data?);
+        }
+
+        let mut jobs = Vec::with_capacity(2);
+        if keep_left > 0 {
+            let data = &edge_data[&0];
+            if keep_left > data.len() {
+                return Err(anyhow!("Delete range is inconsistent with block data"));
+            }
+            jobs.push(ChunkJob {
+                data: &data[..keep_left],
+                data_shards: first.data_shards,
+                parity_shards: first.parity_shards,
+            });
+        }
+        if keeps_right {
+            let data = &edge_data[&(touched.len() - 1)];
+            if keep_right_from > data.len() {
+                return Err(anyhow!("Delete range is inconsistent with block data"));
+            }
+            jobs.push(ChunkJob {
+                data: &data[keep_right_from..],
+                data_shards: last.data_shards,
+                parity_shards: last.parity_shards,
+            });
+        }
+
+        let new_blocks = self.build(&jobs)?;
+        self.splice(range, new_blocks)
+    }
+
+    /// Writes the blocks the final view uses and commits it as one epoch.
+    /// A transaction whose edits changed nothing commits nothing. Afterwards
+    /// the dataset's automatic compaction runs if fragmentation calls for it.
+    pub fn commit(self) -> Result<()> {
+        let Transaction {
+            store,
+            blocks,
+            mut pending,
+            obsolete,
+            edits,
+            _lock,
+            ..
+        } = self;
+        if edits == 0 {
+            return Ok(());
+        }
+
+        let next_manifest = store.manifest_with_active_blocks(blocks)?;
+        // Blocks staged by one edit and replaced by a later one never reach
+        // the backend.
+        let reference_counts = next_manifest.reference_counts();
+        pending.retain(|block| reference_counts.contains_key(&block.metadata.id));
+        store.commit_manifest(next_manifest, obsolete, pending)?;
+
+        // The edits have landed. Compaction only tidies the layout and is
+        // retried after the next edit, so its failure is not reported here.
+        let _ = store.compact_if_fragmented();
+        Ok(())
+    }
+
+    /// Reads a block of the view, from memory when this transaction made it.
+    fn read_block(&self, block: &BlockMetadata) -> Result<Vec<u8>> {
+        match self.staged.get(&block.id) {
+            Some(data) => Ok(data.clone()),
+            None => self.store.read_block(block),
+        }
+    }
+
+    fn read_blocks(&self, blocks: &[BlockMetadata]) -> Vec<Result<Vec<u8>>> {
+        parallel::map_ordered(blocks, self.store.parallelism(), |block| {
+            self.read_block(block)
+        })
+    }
+
+    /// Encodes `jobs` into blocks, keeping new ones in memory until commit.
+    fn build(&mut self, jobs: &[ChunkJob<'_>]) -> Result<Vec<BlockMetadata>> {
+        let built = self.store.build_blocks(&mut self.builder, jobs)?;
+        let mut new_blocks = Vec::with_capacity(built.len());
+        for (job, (metadata, pending)) in jobs.iter().zip(built) {
+            if let Some(pending) = pending {
+                self.staged.insert(metadata.id, job.data.to_vec());
+                self.pending.push(pending);
+            }
+            new_blocks.push(metadata);
+        }
+        Ok(new_blocks)
+    }
+
+    /// Replaces `range` of the view with `new_blocks` and counts the edit.
+    fn splice(&mut self, range: Range<usize>, new_blocks: Vec<BlockMetadata>) -> Result<()> {
+        let displaced: Vec<BlockMetadata> = self.blocks.splice(range, new_blocks).collect();
+        for block in displaced {
+            if !self.staged.contains_key(&block.id) {
+                self.obsolete.push(block);
+            }
+        }
+        self.total_size = self
+            .blocks
+            .iter()
+            .try_fold(0u64, |acc, block| acc.checked_add(block.original_size))
+            .ok_or_else(|| anyhow!("File total_size overflow"))?;
+        self.edits += 1;
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
+use ironclad::lock::{LOCK_FILE_NAME, LockMode, lock_file};
 use std::fs;
 use std::path::Path;
 use std::process::Command;
+use std::time::Duration;
 use tempfile::tempdir;
 
 const ROOT_KEY_HEX: &str = "5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a";
@@ -212,3 +214,1245 @@ fn test_cli_fails_on_malformed_root_key() {
     let stderr = String::from_utf8_lossy(&output.stderr);
     assert!(stderr.contains("Root key must be"));
 }
+
+#[test]
+fn test_scrub_fails_on_unrecoverable_block() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"scrub me").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+
+    let write_output = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "write",
+            input_arg.as_str(),
+            "--dataset",
+            "scrubbed",
+        ],
+    );
+    assert!(write_output.status.success());
+
+    let healthy = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "scrub",
+            "--dataset",
+            "scrubbed",
+        ],
+    );
+    assert!(healthy.status.success());
+    assert!(String::from_utf8_lossy(&healthy.stdout).contains("healthy: 1"));
+
+    let dataset_root = dir.path().join("storage").join("scrubbed");
+    for i in 0..5 {
+        fs::remove_file(dataset_root.join(format!("block_1_{}.bin", i))).unwrap();
+    }
+
+    let broken = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "scrub",
+            "--dataset",
+            "scrubbed",
+        ],
+    );
+    assert!(!broken.status.success());
+    assert!(String::from_utf8_lossy(&broken.stdout).contains("UNRECOVERABLE"));
+    assert!(String::from_utf8_lossy(&broken.stderr).contains("unrecoverable block"));
+}
+
+#[test]
+fn test_write_with_block_size_round_trips() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let payload: Vec<u8> = (0..5_000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(&input, &payload).unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+
+    let write_output = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "write",
+            input_arg.as_str(),
+            "--block-size",
+            "2048",
+            "--dataset",
+            "chunked",
+        ],
+    );
+    assert!(write_output.status.success());
+    assert!(String::from_utf8_lossy(&write_output.stdout).contains("blocks: 3"));
+
+    let dataset_root = dir.path().join("storage").join("chunked");
+    assert!(dataset_root.join("block_3_0.bin").exists());
+
+    let read_output = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "read",
+            "out.bin",
+            "--dataset",
+            "chunked",
+        ],
+    );
+    assert!(read_output.status.success());
+    assert_eq!(fs::read(dir.path().join("out.bin")).unwrap(), payload);
+
+    let stdout_output = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "read",
+            "-",
+            "--dataset",
+            "chunked",
+        ],
+    );
+    assert!(stdout_output.status.success());
+    assert_eq!(stdout_output.stdout, payload);
+}
+
+#[test]
+fn test_put_get_ls_mv_rm_named_files() {
+    let dir = tempdir().unwrap();
+    let first = dir.path().join("first.txt");
+    let second = dir.path().join("second.txt");
+    fs::write(&first, b"first file").unwrap();
+    fs::write(&second, b"second file").unwrap();
+    let first_arg = first.to_string_lossy().to_string();
+    let second_arg = second.to_string_lossy().to_string();
+
+    let run = |args: &[&str]| {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX];
+        full.extend_from_slice(args);
+        full.extend_from_slice(&["--dataset", "multi"]);
+        let output = run_cli(dir.path(), &full);
+        assert!(
+            output.status.success(),
+            "args: {:?}\nstderr: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    run(&["put", first_arg.as_str()]);
+    run(&["put", second_arg.as_str(), "--name", "docs/second"]);
+    let listing = run(&["ls"]);
+    assert!(listing.contains("first.txt\t10 bytes"));
+    assert!(listing.contains("docs/second\t11 bytes"));
+
+    run(&["mv", "docs/second", "renamed"]);
+    run(&["get", "renamed", "out.txt"]);
+    assert_eq!(
+        fs::read(dir.path().join("out.txt")).unwrap(),
+        b"second file"
+    );
+
+    run(&["overwrite", "0", "F", "--file", "first.txt"]);
+    run(&["read", "first_out.txt", "--file", "first.txt"]);
+    assert_eq!(
+        fs::read(dir.path().join("first_out.txt")).unwrap(),
+        b"First file"
+    );
+
+    run(&["rm", "first.txt"]);
+    let listing = run(&["ls"]);
+    assert!(!listing.contains("first.txt"));
+    assert!(listing.contains("files: 1"));
+
+    let missing = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "get",
+            "first.txt",
+            "out.txt",
+            "--dataset",
+            "multi",
+        ],
+    );
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("not found"));
+}
+
+#[test]
+fn test_snapshot_read_epoch_and_rollback() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"version one").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+
+    let run = |args: &[&str]| {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX];
+        full.extend_from_slice(args);
+        full.extend_from_slice(&["--dataset", "snap"]);
+        let output = run_cli(dir.path(), &full);
+        assert!(
+            output.status.success(),
+            "args: {:?}\nstderr: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    run(&["write", input_arg.as_str()]);
+    let created = run(&["snapshot", "create", "v1"]);
+    let epoch = created.trim().rsplit("epoch: ").next().unwrap().to_string();
+    assert!(run(&["snapshot", "list"]).contains(&format!("v1\tepoch {}", epoch)));
+
+    run(&["delete", "0", "8"]);
+    run(&["read", "old.txt", "--epoch", epoch.as_str()]);
+    assert_eq!(
+        fs::read(dir.path().join("old.txt")).unwrap(),
+        b"version one"
+    );
+
+    run(&["rollback", epoch.as_str()]);
+    run(&["read", "current.txt"]);
+    assert_eq!(
+        fs::read(dir.path().join("current.txt")).unwrap(),
+        b"version one"
+    );
+
+    run(&["snapshot", "delete", "v1"]);
+    assert!(run(&["snapshot", "list"]).contains("snapshots: 0"));
+}
+
+#[test]
+fn test_rotate_key_switches_required_root_key() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"rotate via cli").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let new_key = "6b".repeat(32);
+
+    let write = run_cli(
+        dir.path(),
+        &["--root-key-hex", ROOT_KEY_HEX, "write", input_arg.as_str()],
+    );
+    assert!(write.status.success());
+
+    let rotate = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "rotate-key",
+            "--new-root-key-hex",
+            new_key.as_str(),
+        ],
+    );
+    assert!(
+        rotate.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&rotate.stderr)
+    );
+
+    let old_read = run_cli(
+        dir.path(),
+        &["--root-key-hex", ROOT_KEY_HEX, "read", "old.txt"],
+    );
+    assert!(!old_read.status.success());
+
+    let new_read = run_cli(
+        dir.path(),
+        &["--root-key-hex", new_key.as_str(), "read", "new.txt"],
+    );
+    assert!(new_read.status.success());
+    assert_eq!(
+        fs::read(dir.path().join("new.txt")).unwrap(),
+        b"rotate via cli"
+    );
+}
+
+#[test]
+fn test_write_with_roots_survives_lost_devices() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let payload: Vec<u8> = (0..9_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&input, &payload).unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+
+    let too_few = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "write",
+            input_arg.as_str(),
+            "--roots",
+            "disk_a,disk_b,disk_c",
+            "-d",
+            "2",
+            "-p",
+            "2",
+            "--dataset",
+            "striped",
+        ],
+    );
+    assert!(!too_few.status.success());
+    assert!(String::from_utf8_lossy(&too_few.stderr).contains("needs at least 4 storage roots"));
+    assert!(!dir.path().join("storage/striped/roots.json").exists());
+
+    let write = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "write",
+            input_arg.as_str(),
+            "--roots",
+            "disk_a,disk_b,disk_c,disk_d",
+            "-d",
+            "2",
+            "-p",
+            "2",
+            "--dataset",
+            "striped",
+        ],
+    );
+    assert!(
+        write.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&write.stderr)
+    );
+    assert!(
+        dir.path()
+            .join("storage")
+            .join("striped")
+            .join("roots.json")
+            .exists()
+    );
+    assert!(dir.path().join("disk_c").join("block_1_2.bin").exists());
+
+    let wider = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "insert",
+            "0",
+            "x",
+            "-d",
+            "3",
+            "-p",
+            "2",
+            "--force",
+            "--dataset",
+            "striped",
+        ],
+    );
+    assert!(!wider.status.success());
+    assert!(String::from_utf8_lossy(&wider.stderr).contains("needs 5 failure domains"));
+
+    fs::remove_dir_all(dir.path().join("disk_a")).unwrap();
+    fs::remove_dir_all(dir.path().join("disk_d")).unwrap();
+
+    let read = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "read",
+            "out.bin",
+            "--dataset",
+            "striped",
+        ],
+    );
+    assert!(
+        read.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&read.stderr)
+    );
+    assert_eq!(fs::read(dir.path().join("out.bin")).unwrap(), payload);
+}
+
+#[test]
+fn test_put_with_cdc_dedup_stores_repeated_content_once() {
+    let dir = tempdir().unwrap();
+    let mut state = 0x9e37_79b9_u32;
+    let payload: Vec<u8> = (0..48 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let input = dir.path().join("payload.bin");
+    fs::write(&input, &payload).unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let dataset_dir = dir.path().join("storage").join("dedup");
+    let count_blocks = || {
+        fs::read_dir(&dataset_dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("block_")
+            })
+            .count()
+    };
+
+    let run = |args: &[&str]| {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX];
+        full.extend_from_slice(args);
+        full.extend_from_slice(&["--dataset", "dedup"]);
+        let output = run_cli(dir.path(), &full);
+        assert!(
+            output.status.success(),
+            "args: {:?}\nstderr: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    let put_args = [
+        "--chunking",
+        "cdc",
+        "--block-size",
+        "8192",
+        "--dedup",
+        "-d",
+        "2",
+        "-p",
+        "1",
+    ];
+    let mut first = vec!["put", input_arg.as_str(), "--name", "one"];
+    first.extend_from_slice(&put_args);
+    run(&first);
+    let blocks_after_first = count_blocks();
+
+    let mut second = vec!["put", input_arg.as_str(), "--name", "two"];
+    second.extend_from_slice(&put_args);
+    run(&second);
+    assert_eq!(count_blocks(), blocks_after_first);
+
+    run(&["rm", "one"]);
+    run(&["get", "two", "out.bin"]);
+    assert_eq!(fs::read(dir.path().join("out.bin")).unwrap(), payload);
+}
+
+#[test]
+fn test_write_with_compression_round_trips_and_stores_less() {
+    let dir = tempdir().unwrap();
+    let text = b"{\"level\":\"info\",\"msg\":\"heartbeat\"}\n".repeat(2000);
+    let input = dir.path().join("events.jsonl");
+    fs::write(&input, &text).unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let stored_bytes = |dataset: &str| -> u64 {
+        fs::read_dir(dir.path().join("storage").join(dataset))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    };
+
+    for (dataset, compression) in [("raw", "none"), ("packed", "auto")] {
+        let output = run_cli(
+            dir.path(),
+            &[
+                "--root-key-hex",
+                ROOT_KEY_HEX,
+                "write",
+                input_arg.as_str(),
+                "--dataset",
+                dataset,
+                "--compression",
+                compression,
+            ],
+        );
+        assert!(
+            output.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    assert!(stored_bytes("packed") * 4 < stored_bytes("raw"));
+
+    let output = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "read",
+            "out.jsonl",
+            "--dataset",
+            "packed",
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(fs::read(dir.path().join("out.jsonl")).unwrap(), text);
+}
+
+#[test]
+fn test_json_output_reports_stats_warnings_and_error_codes() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"structured output for scripts").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let run_json = |args: &[&str]| -> (bool, serde_json::Value) {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX, "--output", "json"];
+        full.extend_from_slice(args);
+        let output = run_cli(dir.path(), &full);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let value = serde_json::from_str(stdout.trim())
+            .unwrap_or_else(|e| panic!("invalid JSON {:?}: {}", stdout, e));
+        (output.status.success(), value)
+    };
+
+    let (ok, write) = run_json(&["write", input_arg.as_str(), "--dataset", "js"]);
+    assert!(ok, "{}", write);
+    assert_eq!(write["ok"], true);
+    assert_eq!(write["command"], "write");
+    assert_eq!(write["dataset"], "js");
+    assert_eq!(write["total_size"], 29);
+    assert_eq!(write["blocks_touched"], 1);
+    assert_eq!(write["shards_written"], 8);
+    let epoch = write["epoch"].as_u64().unwrap();
+
+    let (ok, insert) = run_json(&["insert", "0", "hi ", "--dataset", "js"]);
+    assert!(ok, "{}", insert);
+    assert_eq!(insert["total_size"], 32);
+    assert_eq!(insert["epoch"].as_u64().unwrap(), epoch + 1);
+
+    fs::remove_file(dir.path().join("storage/js/block_2_0.bin")).unwrap();
+    let (ok, read) = run_json(&["read", "out.txt", "--dataset", "js"]);
+    assert!(ok, "{}", read);
+    assert_eq!(read["shards_written"], 0);
+    assert_eq!(read["io"]["degraded_blocks_read"], 1);
+    assert_eq!(read["warnings"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        fs::read(dir.path().join("out.txt")).unwrap(),
+        b"hi structured output for scripts"
+    );
+
+    let (ok, missing) = run_json(&["delete", "0", "1", "--dataset", "nope"]);
+    assert!(!ok);
+    assert_eq!(missing["ok"], false);
+    assert_eq!(missing["command"], "delete");
+    assert_eq!(missing["error"]["code"], "dataset_not_found");
+
+    let (ok, invalid) = run_json(&["write", input_arg.as_str(), "--data", "0"]);
+    assert!(!ok);
+    assert_eq!(invalid["error"]["code"], "invalid_argument");
+
+    let held = lock_file(
+        &dir.path().join("storage/js").join(LOCK_FILE_NAME),
+        LockMode::Exclusive,
+        Duration::ZERO,
+    )
+    .unwrap();
+    let (ok, locked) = run_json(&["--lock-timeout", "0", "read", "out.txt", "--dataset", "js"]);
+    assert!(!ok);
+    assert_eq!(locked["error"]["code"], "locked");
+    drop(held);
+
+    // A block lost while streaming is reported as such, and the previous
+    // output file is left as it was.
+    for shard in 1..5 {
+        fs::remove_file(dir.path().join(format!("storage/js/block_2_{}.bin", shard))).unwrap();
+    }
+    let (ok, lost) = run_json(&["read", "out.txt", "--dataset", "js"]);
+    assert!(!ok);
+    assert_eq!(lost["error"]["code"], "unrecoverable");
+    assert_eq!(
+        fs::read(dir.path().join("out.txt")).unwrap(),
+        b"hi structured output for scripts"
+    );
+    assert!(!dir.path().join("out.txt.partial").exists());
+}
+
+#[test]
+fn test_info_shows_layout_and_metadata_copies() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    fs::write(&input, vec![7u8; 2500]).unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let run = |args: &[&str]| {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX];
+        full.extend_from_slice(args);
+        let output = run_cli(dir.path(), &full);
+        assert!(
+            output.status.success(),
+            "args: {:?}\nstderr: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    run(&["write", input_arg.as_str(), "--block-size", "1024"]);
+    run(&["insert", "0", "x"]);
+
+    let text = run(&["stat"]);
+    assert!(text.contains("File: input.bin, total size: 2501, blocks: 4"));
+    assert!(text.contains("[1025..2049): 1024 bytes, 4+4 shards, overhead 2.00x"));
+    assert!(text.contains("Losing candidate epoch"));
+
+    let info: serde_json::Value =
+        serde_json::from_str(run(&["--output", "json", "info"]).trim()).unwrap();
+    assert_eq!(info["total_size"], 2501);
+    assert_eq!(info["files"][0]["blocks"].as_array().unwrap().len(), 4);
+    assert_eq!(info["files"][0]["blocks"][0]["overhead"], 2.0);
+    let winner = &info["metadata"]["winner"];
+    assert_eq!(winner["epoch"], info["epoch"]);
+    assert!(winner["copies"].as_u64().unwrap() >= 2);
+    assert!(!info["metadata"]["losers"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_dataset_lifecycle_and_write_overwrite_protection() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"precious").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let run = |args: &[&str]| {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX];
+        full.extend_from_slice(args);
+        run_cli(dir.path(), &full)
+    };
+    let run_ok = |args: &[&str]| {
+        let output = run(args);
+        assert!(
+            output.status.success(),
+            "args: {:?}\nstderr: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    run_ok(&["write", input_arg.as_str(), "--dataset", "keep"]);
+    let clobber = run(&["write", input_arg.as_str(), "--dataset", "keep"]);
+    assert!(!clobber.status.success());
+    assert!(String::from_utf8_lossy(&clobber.stderr).contains("--replace"));
+    run_ok(&[
+        "write",
+        input_arg.as_str(),
+        "--dataset",
+        "keep",
+        "--replace",
+    ]);
+
+    // A replacement that fails leaves the old dataset readable, and one that
+    // succeeds leaves no staging directory behind.
+    fs::write(&input, b"renewed").unwrap();
+    let invalid = run(&[
+        "write",
+        input_arg.as_str(),
+        "--dataset",
+        "keep",
+        "--replace",
+        "--compression",
+        "zstd",
+        "--compression-level",
+        "99",
+    ]);
+    assert!(!invalid.status.success());
+    run_ok(&["read", "out.txt", "--dataset", "keep"]);
+    assert_eq!(fs::read(dir.path().join("out.txt")).unwrap(), b"precious");
+    run_ok(&[
+        "write",
+        input_arg.as_str(),
+        "--dataset",
+        "keep",
+        "--replace",
+    ]);
+    run_ok(&["read", "out.txt", "--dataset", "keep"]);
+    assert_eq!(fs::read(dir.path().join("out.txt")).unwrap(), b"renewed");
+    let storage: Vec<_> = fs::read_dir(dir.path().join("storage"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(storage, vec!["keep"]);
+    fs::write(&input, b"precious").unwrap();
+    run_ok(&[
+        "write",
+        input_arg.as_str(),
+        "--dataset",
+        "keep",
+        "--replace",
+    ]);
+
+    run_ok(&["datasets", "create", "empty"]);
+    assert!(!run(&["datasets", "create", "empty"]).status.success());
+    let listing = run_ok(&["datasets", "list"]);
+    assert!(listing.contains("empty\tepoch 0\t0 file(s)"));
+    assert!(listing.contains("keep\tepoch"));
+    assert!(listing.contains("datasets: 2"));
+
+    run_ok(&["datasets", "rename", "keep", "kept"]);
+    assert!(
+        !run(&["datasets", "rename", "kept", "empty"])
+            .status
+            .success()
+    );
+    run_ok(&["read", "out.txt", "--dataset", "kept"]);
+    assert_eq!(fs::read(dir.path().join("out.txt")).unwrap(), b"precious");
+
+    let destroy = |answer: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_ironclad"))
+            .current_dir(dir.path())
+            .args([
+                "--root-key-hex",
+                ROOT_KEY_HEX,
+                "datasets",
+                "destroy",
+                "kept",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(answer.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+    assert!(!destroy("nope\n").status.success());
+    assert!(dir.path().join("storage/kept").exists());
+    assert!(destroy("kept\n").status.success());
+    assert!(!dir.path().join("storage/kept").exists());
+
+    run_ok(&["datasets", "destroy", "empty", "--force"]);
+    assert!(run_ok(&["datasets", "list"]).contains("datasets: 0"));
+}
+
+#[test]
+fn test_concurrent_inserts_are_serialized_by_the_dataset_lock() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"|").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let write = run_cli(
+        dir.path(),
+        &["--root-key-hex", ROOT_KEY_HEX, "write", input_arg.as_str()],
+    );
+    assert!(write.status.success());
+
+    let children: Vec<_> = ["a", "b", "c", "d", "e", "f"]
+        .iter()
+        .map(|text| {
+            Command::new(env!("CARGO_BIN_EXE_ironclad"))
+                .current_dir(dir.path())
+                .args(["--root-key-hex", ROOT_KEY_HEX, "insert", "0", text])
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for child in children {
+        assert!(child.wait_with_output().unwrap().status.success());
+    }
+
+    let read = run_cli(
+        dir.path(),
+        &["--root-key-hex", ROOT_KEY_HEX, "read", "out.txt"],
+    );
+    assert!(
+        read.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&read.stderr)
+    );
+    let mut content = fs::read(dir.path().join("out.txt")).unwrap();
+    assert_eq!(content.pop(), Some(b'|'));
+    content.sort_unstable();
+    assert_eq!(content, b"abcdef");
+}
+
+#[test]
+fn test_apply_commits_edit_script_in_one_epoch() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"hello world").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let run_json = |args: &[&str]| -> (bool, serde_json::Value) {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX, "--output", "json"];
+        full.extend_from_slice(args);
+        let output = run_cli(dir.path(), &full);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let value = serde_json::from_str(stdout.trim())
+            .unwrap_or_else(|e| panic!("invalid JSON {:?}: {}", stdout, e));
+        (output.status.success(), value)
+    };
+
+    let (ok, write) = run_json(&["write", input_arg.as_str()]);
+    assert!(ok, "{}", write);
+    let epoch = write["epoch"].as_u64().unwrap();
+
+    let script = dir.path().join("edits.txt");
+    fs::write(
+        &script,
+        "# greet properly\n\
+         overwrite 0 H\n\
+         insert 5 , big\n\
+         \n\
+         delete 10 6\n\
+         insert 5 \\tthere\\n\n",
+    )
+    .unwrap();
+    let script_arg = script.to_string_lossy().to_string();
+    let (ok, apply) = run_json(&["apply", script_arg.as_str()]);
+    assert!(ok, "{}", apply);
+    assert_eq!(apply["command"], "apply");
+    assert_eq!(apply["edits"], 4);
+    assert_eq!(apply["epoch"].as_u64().unwrap(), epoch + 1);
+
+    let (ok, read) = run_json(&["read", "out.txt"]);
+    assert!(ok, "{}", read);
+    assert_eq!(
+        fs::read(dir.path().join("out.txt")).unwrap(),
+        b"Hello\tthere\n, big"
+    );
+
+    // A failing edit aborts the whole script and names its line.
+    fs::write(&script, "insert 0 kept?\ndelete 0 999\n").unwrap();
+    let (ok, failed) = run_json(&["apply", script_arg.as_str()]);
+    assert!(!ok);
+    let message = failed["error"]["message"].as_str().unwrap();
+    assert!(message.contains("Line 2"), "{}", message);
+
+    fs::write(&script, "append 0 x\n").unwrap();
+    let (ok, invalid) = run_json(&["apply", script_arg.as_str()]);
+    assert!(!ok);
+    assert_eq!(invalid["error"]["code"], "invalid_argument");
+
+    let (_, info) = run_json(&["info"]);
+    assert_eq!(info["epoch"].as_u64().unwrap(), epoch + 1);
+    assert_eq!(info["total_size"], 17);
+}
+
+#[test]
+fn test_compact_merges_small_blocks_and_stores_auto_setting() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"abcdefghij").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let run_json = |args: &[&str]| -> serde_json::Value {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX, "--output", "json"];
+        full.extend_from_slice(args);
+        let output = run_cli(dir.path(), &full);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{:?}: {}", args, stdout);
+        serde_json::from_str(stdout.trim()).unwrap()
+    };
+
+    run_json(&["write", input_arg.as_str(), "--block-size", "2"]);
+    run_json(&["insert", "3", "XY"]);
+
+    let compact = run_json(&[
+        "compact",
+        "--small-block-size",
+        "4",
+        "--target-block-size",
+        "6",
+        "--auto-ratio",
+        "0.4",
+    ]);
+    assert_eq!(compact["blocks_before"], 7);
+    assert_eq!(compact["blocks_after"], 2);
+    assert_eq!(compact["blocks_merged"], 7);
+    assert_eq!(compact["auto_ratio"], 0.4);
+    assert_eq!(compact["total_size"], 12);
+
+    // Inserts that fragment the file get merged again right away.
+    run_json(&["insert", "1", "-"]);
+    let info = run_json(&["info"]);
+    assert_eq!(info["files"][0]["blocks"].as_array().unwrap().len(), 3);
+    run_json(&["insert", "8", "+"]);
+    let info = run_json(&["info"]);
+    assert_eq!(info["files"][0]["blocks"].as_array().unwrap().len(), 4);
+
+    run_json(&["read", "out.txt"]);
+    assert_eq!(
+        fs::read(dir.path().join("out.txt")).unwrap(),
+        b"a-bcXYde+fghij"
+    );
+
+    let off = run_json(&["compact", "--no-auto"]);
+    assert_eq!(off["auto_ratio"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_restripe_changes_layout_of_selected_blocks() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    fs::write(&input, vec![3u8; 4000]).unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let run_json = |args: &[&str]| -> serde_json::Value {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX, "--output", "json"];
+        full.extend_from_slice(args);
+        let output = run_cli(dir.path(), &full);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{:?}: {}", args, stdout);
+        serde_json::from_str(stdout.trim()).unwrap()
+    };
+    let layouts = || -> Vec<(u64, u64)> {
+        let info = run_json(&["info"]);
+        info["files"][0]["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|block| {
+                (
+                    block["data_shards"].as_u64().unwrap(),
+                    block["parity_shards"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    };
+
+    run_json(&["write", input_arg.as_str(), "--block-size", "1000"]);
+    let ranged = run_json(&[
+        "restripe", "--data", "2", "--parity", "6", "--offset", "1500", "--length", "1000",
+    ]);
+    assert_eq!(ranged["blocks_restriped"], 2);
+    assert_eq!(layouts(), vec![(4, 4), (2, 6), (2, 6), (4, 4)]);
+
+    let all = run_json(&["restripe", "-d", "2", "-p", "6"]);
+    assert_eq!(all["blocks_restriped"], 2);
+    assert_eq!(all["blocks_skipped"], 2);
+    assert_eq!(layouts(), vec![(2, 6); 4]);
+    assert!(
+        dir.path()
+            .join("storage/default")
+            .read_dir()
+            .unwrap()
+            .any(|entry| entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with("_7.bin"))
+    );
+
+    run_json(&["read", "out.bin"]);
+    assert_eq!(
+        fs::read(dir.path().join("out.bin")).unwrap(),
+        vec![3u8; 4000]
+    );
+}
+
+#[test]
+fn test_dataset_default_layout_applies_to_edits_unless_confirmed_override() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"0123456789").unwrap();
+    let input_arg = input.to_string_lossy().to_string();
+    let run = |args: &[&str]| {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX, "--output", "json"];
+        full.extend_from_slice(args);
+        run_cli(dir.path(), &full)
+    };
+    let run_json = |args: &[&str]| -> serde_json::Value {
+        let output = run(args);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{:?}: {}", args, stdout);
+        serde_json::from_str(stdout.trim()).unwrap()
+    };
+    let layouts = |dataset: &str| -> Vec<(u64, u64)> {
+        let info = run_json(&["info", "--dataset", dataset]);
+        info["files"][0]["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|block| {
+                (
+                    block["data_shards"].as_u64().unwrap(),
+                    block["parity_shards"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    };
+
+    run_json(&["write", input_arg.as_str(), "--data", "10", "--parity", "2"]);
+    run_json(&["insert", "10", "!"]);
+    assert_eq!(layouts("default"), vec![(10, 2), (10, 2)]);
+    let info = run_json(&["info"]);
+    assert_eq!(info["redundancy"]["data_shards"], 10);
+
+    // A different layout is refused without confirmation...
+    let refused = run(&["insert", "0", "x", "--data", "4", "--parity", "4"]);
+    assert!(!refused.status.success());
+    assert_eq!(layouts("default").len(), 2);
+
+    // ...and accepted at the prompt or with --force.
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_ironclad"))
+        .current_dir(dir.path())
+        .args([
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "insert",
+            "0",
+            "x",
+            "--parity",
+            "4",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"y\n").unwrap();
+    let confirmed = child.wait_with_output().unwrap();
+    assert!(String::from_utf8_lossy(&confirmed.stderr).contains("stores new blocks as 10+2"));
+    assert!(confirmed.status.success());
+    run_json(&["insert", "0", "y", "-d", "3", "-p", "3", "--force"]);
+    assert_eq!(layouts("default")[..2], [(3, 3), (10, 4)]);
+
+    let created = run_json(&["datasets", "create", "cold", "--data", "6", "--parity", "1"]);
+    assert_eq!(created["redundancy"]["parity_shards"], 1);
+    run_json(&["put", input_arg.as_str(), "--dataset", "cold"]);
+    assert_eq!(layouts("cold"), vec![(6, 1)]);
+    assert!(
+        !run(&["put", input_arg.as_str(), "--dataset", "cold", "-p", "2"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn test_prove_and_verify_range_against_recorded_merkle_root() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.txt");
+    fs::write(&input, b"abcdefghijklmnopqrstuvwxyz").unwrap();
+    let run_json = |args: &[&str]| -> serde_json::Value {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX, "--output", "json"];
+        full.extend_from_slice(args);
+        let output = run_cli(dir.path(), &full);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{:?}: {}", args, stdout);
+        serde_json::from_str(stdout.trim()).unwrap()
+    };
+
+    run_json(&["write", input.to_str().unwrap(), "--block-size", "4"]);
+    let info = run_json(&["info"]);
+    let root = info["merkle_root"].as_str().unwrap().to_string();
+    let epoch = info["epoch"].as_u64().unwrap().to_string();
+
+    let proved = run_json(&["prove", "5", "6", "proof.json"]);
+    assert_eq!(proved["root"], root.as_str());
+    assert_eq!(proved["blocks"], 2);
+
+    // The auditor needs no key, only the proof and the root they trust.
+    let verified = run_cli(
+        dir.path(),
+        &[
+            "--output",
+            "json",
+            "verify-proof",
+            "proof.json",
+            "--root",
+            &root,
+            "--epoch",
+            &epoch,
+        ],
+    );
+    assert!(verified.status.success());
+    let verified: serde_json::Value =
+        serde_json::from_slice(&verified.stdout).expect("verify-proof json");
+    assert_eq!(verified["valid"], true);
+    assert_eq!(verified["length"], 6);
+
+    // A later epoch has a different root, so the old proof no longer matches it.
+    run_json(&["insert", "0", "!"]);
+    let new_root = run_json(&["info"])["merkle_root"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_ne!(new_root, root);
+    let stale = run_cli(
+        dir.path(),
+        &["verify-proof", "proof.json", "--root", &new_root],
+    );
+    assert!(!stale.status.success());
+    assert!(String::from_utf8_lossy(&stale.stderr).contains("expected"));
+
+    let proof = fs::read_to_string(dir.path().join("proof.json")).unwrap();
+    fs::write(
+        dir.path().join("forged.json"),
+        proof.replacen("\"size\": 4", "\"size\": 5", 1),
+    )
+    .unwrap();
+    let forged = run_cli(dir.path(), &["verify-proof", "forged.json"]);
+    assert!(!forged.status.success());
+}
+
+#[test]
+fn test_audit_challenge_respond_verify_round_trip() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(&input, &data).unwrap();
+    let write = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "write",
+            input.to_str().unwrap(),
+            "--block-size",
+            "8192",
+        ],
+    );
+    assert!(write.status.success());
+
+    let challenge = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            ROOT_KEY_HEX,
+            "--output",
+            "json",
+            "audit",
+            "challenge",
+            "challenge.json",
+            "--secret",
+            "audit.secret",
+            "--samples",
+            "10",
+        ],
+    );
+    assert!(challenge.status.success());
+    let challenge: serde_json::Value = serde_json::from_slice(&challenge.stdout).unwrap();
+    assert_eq!(challenge["samples"], 10);
+    assert_eq!(challenge["secret_created"], true);
+    assert!(dir.path().join("audit.secret").exists());
+
+    // The host answers without the key; the verifier checks without the dataset.
+    let respond = run_cli(
+        dir.path(),
+        &["audit", "respond", "challenge.json", "response.json"],
+    );
+    assert!(respond.status.success());
+    let verify_args = [
+        "--output",
+        "json",
+        "audit",
+        "verify",
+        "challenge.json",
+        "response.json",
+        "--secret",
+        "audit.secret",
+    ];
+    let verified = run_cli(dir.path(), &verify_args);
+    assert!(verified.status.success());
+    let verified: serde_json::Value = serde_json::from_slice(&verified.stdout).unwrap();
+    assert_eq!(verified["passed"], 10);
+    assert!(verified["detection_at_1pct_loss"].as_f64().unwrap() > 0.09);
+
+    // Drop every shard the challenge asked for and answer again.
+    let challenge: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.path().join("challenge.json")).unwrap()).unwrap();
+    for item in challenge["items"].as_array().unwrap() {
+        let name = format!("block_{}_{}.bin", item["block_id"], item["shard_index"]);
+        fs::remove_file(dir.path().join("storage/default").join(name)).unwrap();
+    }
+    let respond = run_cli(
+        dir.path(),
+        &["audit", "respond", "challenge.json", "response.json"],
+    );
+    assert!(respond.status.success());
+    assert!(String::from_utf8_lossy(&respond.stderr).contains("Cannot answer"));
+    let failed = run_cli(dir.path(), &verify_args);
+    assert!(!failed.status.success());
+    let failed: serde_json::Value = serde_json::from_slice(&failed.stdout).unwrap();
+    assert_eq!(failed["passed"], 0);
+    assert_eq!(failed["failures"].as_array().unwrap().len(), 10);
+}
+
+#[test]
+fn test_export_import_moves_dataset_through_one_archive() {
+    let dir = tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let data: Vec<u8> = (0..30_000u32).map(|i| (i * 13 % 256) as u8).collect();
+    fs::write(&input, &data).unwrap();
+    let run = |args: &[&str]| {
+        let mut full = vec!["--root-key-hex", ROOT_KEY_HEX, "--output", "json"];
+        full.extend_from_slice(args);
+        run_cli(dir.path(), &full)
+    };
+    let run_json = |args: &[&str]| -> serde_json::Value {
+        let output = run(args);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{:?}: {}", args, stdout);
+        serde_json::from_str(stdout.trim()).unwrap()
+    };
+
+    run_json(&["write", input.to_str().unwrap(), "--block-size", "8192"]);
+    run_json(&["snapshot", "create", "v1"]);
+    run_json(&["insert", "0", "head"]);
+    let exported = run_json(&["export", "site.ira"]);
+    assert!(exported["objects"].as_u64().unwrap() > 0);
+    assert!(!dir.path().join("site.ira.partial").exists());
+
+    let imported = run_json(&["import", "site.ira", "--dataset", "moved"]);
+    assert_eq!(imported["epoch"], exported["epoch"]);
+    assert_eq!(imported["degraded_blocks"], 0);
+    run_json(&["read", "out.bin", "--dataset", "moved"]);
+    let mut expected = b"head".to_vec();
+    expected.extend_from_slice(&data);
+    assert_eq!(fs::read(dir.path().join("out.bin")).unwrap(), expected);
+    let snapshots = run_json(&["snapshot", "list", "--dataset", "moved"]);
+    assert_eq!(snapshots["snapshots"][0]["name"], "v1");
+
+    let again = run(&["import", "site.ira", "--dataset", "moved"]);
+    assert!(!again.status.success());
+    assert!(String::from_utf8_lossy(&again.stdout).contains("dataset_exists"));
+
+    // A flipped byte fails the checksums and nothing is installed.
+    let mut archive = fs::read(dir.path().join("site.ira")).unwrap();
+    let middle = archive.len() / 2;
+    archive[middle] ^= 0xff;
+    fs::write(dir.path().join("bad.ira"), &archive).unwrap();
+    let bad = run(&["import", "bad.ira", "--dataset", "broken"]);
+    assert!(!bad.status.success());
+    assert!(String::from_utf8_lossy(&bad.stdout).contains("mismatch"));
+    let leftovers: Vec<_> = fs::read_dir(dir.path().join("storage"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert!(!leftovers.iter().any(|name| name.contains("broken")));
+
+    // The archive is useless without the right key.
+    let wrong_key = "11".repeat(32);
+    let locked = run_cli(
+        dir.path(),
+        &[
+            "--root-key-hex",
+            &wrong_key,
+            "import",
+            "site.ira",
+            "--dataset",
+            "stranger",
+        ],
+    );
+    assert!(!locked.status.success());
+    assert!(String::from_utf8_lossy(&locked.stderr).contains("manifest quorum"));
+}
@@ -1,7 +1,22 @@
+use ironclad::archive;
+use ironclad::backend::{MemoryBackend, ShardBackend};
 use ironclad::block_store::BlockStore;
+use ironclad::chunk_format::{decode_envelope, encode_envelope};
+use ironclad::chunker::{CdcParams, Chunking};
+use ironclad::compaction::{AutoCompaction, CompactionPolicy};
+use ironclad::compression::{Compression, CompressionPolicy};
+use ironclad::integrity::Redundancy;
+use ironclad::io_guard::IoOptions;
+use ironclad::key_material::RootKey;
+use ironclad::lock::{DatasetLock, LockMode, LockTimeout};
+use ironclad::manifest_recovery::decode_embedded_manifest;
 use std::collections::HashSet;
 use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tempfile::tempdir;
 
 const MANIFEST_FAIL_MARKER: &str = ".ironclad_fail_manifest_commit";
@@ -28,13 +43,13 @@ fn test_block_store_basic_flow() {
 
     let data = store.read_at(0, 21).expect("Read full failed");
     assert_eq!(data, b"Hello Beautiful World");
-    assert_eq!(store.manifest.total_size, 21);
+    assert_eq!(store.total_size(), 21);
 
     store.delete_range(6, 10).expect("Delete failed");
 
     let data = store.read_at(0, 11).expect("Read after delete failed");
     assert_eq!(data, b"Hello World");
-    assert_eq!(store.manifest.total_size, 11);
+    assert_eq!(store.total_size(), 11);
 }
 
 #[test]
@@ -50,7 +65,7 @@ fn test_block_store_persistence_without_manifest_files() {
 
     {
         let store = BlockStore::open(root.clone(), ROOT_KEY).unwrap();
-        assert_eq!(store.manifest.total_size, 10);
+        assert_eq!(store.total_size(), 10);
         let data = store.read_at(0, 10).unwrap();
         assert_eq!(data, b"Persist Me");
         assert!(!root.join("manifest_0.json").exists());
@@ -64,7 +79,7 @@ fn test_block_store_gc() {
     let mut store = BlockStore::create(root.clone(), "gc.txt", ROOT_KEY).unwrap();
 
     store.insert_at(0, b"12345678", 4, 2).unwrap();
-    let block_id_1 = store.manifest.blocks[0].id;
+    let block_id_1 = store.blocks()[0].id;
     let file_1 = root.join(format!("block_{}_0.bin", block_id_1));
     assert!(file_1.exists(), "Block 1 file should exist");
 
@@ -72,7 +87,7 @@ fn test_block_store_gc() {
     assert!(!file_1.exists(), "Block 1 file should be deleted by GC");
 
     store.insert_at(0, b"NewData", 4, 2).unwrap();
-    let block_id_2 = store.manifest.blocks[0].id;
+    let block_id_2 = store.blocks()[0].id;
     let file_2 = root.join(format!("block_{}_0.bin", block_id_2));
     assert!(file_2.exists(), "Block 2 file should exist");
 }
@@ -90,7 +105,7 @@ fn test_insert_reaches_later_blocks() {
 
     store.insert_at(2, b"X", 4, 2).unwrap();
     assert_eq!(store.read_at(0, 4).unwrap(), b"ABXC");
-    assert_eq!(store.manifest.total_size, 4);
+    assert_eq!(store.total_size(), 4);
 }
 
 #[test]
@@ -148,8 +163,8 @@ fn test_full_delete_persists_empty_state_via_meta_fallback() {
     store.insert_at(0, b"abcdef", 4, 2).unwrap();
     store.delete_range(0, 6).unwrap();
 
-    assert_eq!(store.manifest.total_size, 0);
-    assert!(store.manifest.blocks.is_empty());
+    assert_eq!(store.total_size(), 0);
+    assert!(store.blocks().is_empty());
 
     let meta_files = list_meta_files(&root);
     assert!(
@@ -160,8 +175,8 @@ fn test_full_delete_persists_empty_state_via_meta_fallback() {
     );
 
     let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
-    assert_eq!(reopened.manifest.total_size, 0);
-    assert!(reopened.manifest.blocks.is_empty());
+    assert_eq!(reopened.total_size(), 0);
+    assert!(reopened.blocks().is_empty());
 }
 
 #[test]
@@ -194,9 +209,175 @@ fn test_open_with_wrong_root_key_fails() {
     assert!(err.to_string().contains("not initialized"));
 }
 
+#[test]
+fn test_write_stream_splits_into_bounded_blocks() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let payload: Vec<u8> = (0..10_500u32).map(|i| (i % 251) as u8).collect();
+    let mut store = BlockStore::create(root.clone(), "stream.bin", ROOT_KEY).unwrap();
+    let written = store
+        .write_stream(payload.as_slice(), 1_000, 4, 2)
+        .expect("stream write");
+
+    assert_eq!(written, payload.len() as u64);
+    assert_eq!(store.blocks().len(), 11);
+    assert!(
+        store
+            .blocks()
+            .iter()
+            .all(|block| block.original_size <= 1_000)
+    );
+    assert_unique_ids(&store);
+
+    let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
+    assert_eq!(reopened.total_size(), payload.len() as u64);
+    assert_eq!(reopened.read_at(0, payload.len() as u64).unwrap(), payload);
+}
+
+#[test]
+fn test_write_stream_failed_commit_leaves_no_staged_blocks() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let mut store = BlockStore::create(root.clone(), "stream-fail.bin", ROOT_KEY).unwrap();
+    store.insert_at(0, b"abcdef", 4, 2).unwrap();
+    let blocks_before = list_block_files(&root);
+
+    fs::write(root.join(MANIFEST_FAIL_MARKER), b"1").unwrap();
+    let err = store
+        .write_stream(&b"0123456789"[..], 4, 4, 2)
+        .expect_err("commit must fail");
+    assert!(err.to_string().contains("Manifest commit aborted"));
+    fs::remove_file(root.join(MANIFEST_FAIL_MARKER)).unwrap();
+
+    assert_eq!(list_block_files(&root), blocks_before);
+    let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
+    assert_eq!(reopened.read_at(0, 6).unwrap(), b"abcdef");
+}
+
+#[test]
+fn test_dataset_reader_streams_and_seeks_across_blocks() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let payload: Vec<u8> = (0..3_000u32).map(|i| (i % 241) as u8).collect();
+    let mut store = BlockStore::create(root, "reader.bin", ROOT_KEY).unwrap();
+    store.write_stream(payload.as_slice(), 700, 4, 2).unwrap();
+
+    let mut reader = store.reader();
+    let mut streamed = Vec::new();
+    reader.read_to_end(&mut streamed).unwrap();
+    assert_eq!(streamed, payload);
+
+    reader.seek(SeekFrom::Start(650)).unwrap();
+    let mut window = [0u8; 100];
+    reader.read_exact(&mut window).unwrap();
+    assert_eq!(&window[..], &payload[650..750]);
+
+    assert_eq!(reader.seek(SeekFrom::Current(-50)).unwrap(), 700);
+    assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 2_990);
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &payload[2_990..]);
+
+    assert!(reader.seek(SeekFrom::Current(-5_000)).is_err());
+    reader.seek(SeekFrom::Start(10_000)).unwrap();
+    let mut past_end = [0u8; 8];
+    assert_eq!(reader.read(&mut past_end).unwrap(), 0);
+}
+
+#[test]
+fn test_write_at_overwrites_across_blocks_in_one_epoch() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    let mut store = BlockStore::create(root.clone(), "overwrite.txt", ROOT_KEY).unwrap();
+
+    store
+        .write_stream(&b"aaaabbbbccccdddd"[..], 4, 4, 2)
+        .unwrap();
+    let untouched_first = store.blocks()[0].id;
+    let untouched_last = store.blocks()[3].id;
+    let epoch = store.manifest.epoch;
+
+    store.write_at(6, b"XYZW").unwrap();
+    assert_eq!(store.manifest.epoch, epoch + 1);
+    assert_eq!(store.read_at(0, 16).unwrap(), b"aaaabbXYZWccdddd");
+    assert_eq!(store.blocks().len(), 4);
+    assert_eq!(store.blocks()[0].id, untouched_first);
+    assert_eq!(store.blocks()[3].id, untouched_last);
+
+    store.write_at(14, b"END!!").unwrap();
+    assert_eq!(store.total_size(), 19);
+    assert_eq!(store.read_at(0, 19).unwrap(), b"aaaabbXYZWccddEND!!");
+    assert_unique_ids(&store);
+
+    assert!(store.write_at(20, b"gap").is_err());
+
+    let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
+    assert_eq!(reopened.read_at(0, 19).unwrap(), b"aaaabbXYZWccddEND!!");
+}
+
+/// Re-encodes every manifest copy under `root` in the single-file layout the
+/// first release wrote, which carried no format version.
+fn downgrade_to_baseline_manifest(root: &Path) {
+    let meta_mac_key = RootKey(ROOT_KEY).derive().meta_mac_key;
+    for name in list_meta_files(root) {
+        let path = root.join(name);
+        let mut envelope = decode_envelope(&fs::read(&path).unwrap(), &meta_mac_key).unwrap();
+        let recovered = decode_embedded_manifest(&envelope).unwrap();
+        let file = &recovered.files[0];
+        let blocks: Vec<_> = file
+            .blocks
+            .iter()
+            .map(|b| {
+                (
+                    b.id,
+                    b.original_size,
+                    b.data_shards,
+                    b.parity_shards,
+                    &b.shard_hashes,
+                )
+            })
+            .collect();
+        let baseline = (recovered.epoch, &file.name, file.total_size, blocks);
+        let raw = bincode::serde::encode_to_vec(baseline, bincode::config::standard()).unwrap();
+        envelope.manifest_hash = *blake3::hash(&raw).as_bytes();
+        envelope.manifest_blob_zstd = zstd::stream::encode_all(Cursor::new(raw), 3).unwrap();
+        fs::write(&path, encode_envelope(&envelope, &meta_mac_key).unwrap()).unwrap();
+    }
+}
+
+#[test]
+fn test_dataset_with_baseline_manifest_opens_and_upgrades_on_commit() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    let mut store = BlockStore::create(root.clone(), "old.txt", ROOT_KEY).unwrap();
+    store
+        .insert_at(0, b"written before versioning", 2, 1)
+        .unwrap();
+    let epoch = store.manifest.epoch;
+    drop(store);
+    downgrade_to_baseline_manifest(&root);
+
+    let mut store = BlockStore::open(root.clone(), ROOT_KEY).unwrap();
+    assert_eq!(store.manifest.epoch, epoch);
+    assert_eq!(store.active_file_name(), Some("old.txt"));
+    assert_eq!(store.read_at(0, 25).unwrap(), b"written before versioning");
+    store.insert_at(0, b"now ", 2, 1).unwrap();
+    drop(store);
+
+    let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
+    assert_eq!(reopened.manifest.epoch, epoch + 1);
+    assert_eq!(
+        reopened.read_at(0, 29).unwrap(),
+        b"now written before versioning"
+    );
+}
+
 fn assert_unique_ids(store: &BlockStore) {
     let mut ids = HashSet::new();
-    for block in &store.manifest.blocks {
+    for block in store.blocks() {
         assert!(ids.insert(block.id), "duplicate block id {}", block.id);
     }
 }
@@ -212,3 +393,829 @@ fn list_meta_files(root: &Path) -> Vec<String> {
     }
     result
 }
+
+fn list_block_files(root: &Path) -> Vec<String> {
+    let mut result: Vec<String> = fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("block_") && name.ends_with(".bin"))
+        .collect();
+    result.sort();
+    result
+}
+
+#[test]
+fn test_named_files_share_one_namespace() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let mut store =
+        BlockStore::open_or_create_with_options(root.clone(), ROOT_KEY, Default::default())
+            .unwrap();
+    assert!(store.manifest.files.is_empty());
+    store
+        .put_file("a.txt", b"alpha payload".as_slice(), 4, 2, 1)
+        .unwrap();
+    store
+        .put_file("b.txt", b"bravo".as_slice(), 1024, 2, 1)
+        .unwrap();
+    assert_eq!(store.active_file_name(), Some("b.txt"));
+
+    store.select_file("a.txt").unwrap();
+    store.insert_at(0, b">", 2, 1).unwrap();
+    store.rename_file("b.txt", "c.txt").unwrap();
+    assert!(store.rename_file("a.txt", "c.txt").is_err());
+    drop(store);
+
+    let mut reopened =
+        BlockStore::open_or_create_with_options(root.clone(), ROOT_KEY, Default::default())
+            .unwrap();
+    let names: Vec<&str> = reopened
+        .manifest
+        .files
+        .iter()
+        .map(|file| file.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["a.txt", "c.txt"]);
+    assert_eq!(reopened.read_at(0, 14).unwrap(), b">alpha payload");
+
+    let mut contents = Vec::new();
+    reopened
+        .file_reader("c.txt")
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, b"bravo");
+
+    let before = list_block_files(&root).len();
+    reopened.remove_file("a.txt").unwrap();
+    assert!(list_block_files(&root).len() < before);
+    assert_eq!(reopened.active_file_name(), Some("c.txt"));
+    assert!(reopened.select_file("a.txt").is_err());
+}
+
+#[test]
+fn test_snapshot_pins_blocks_until_deleted_and_supports_rollback() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let mut store = BlockStore::create(root.clone(), "prod.db", ROOT_KEY).unwrap();
+    store.insert_at(0, b"precious data", 2, 1).unwrap();
+    let pinned_epoch = store.manifest.epoch;
+    let pinned_files = list_block_files(&root);
+    store.create_snapshot("before-delete").unwrap();
+    assert!(store.create_snapshot("before-delete").is_err());
+
+    store.delete_range(0, 13).unwrap();
+    store.insert_at(0, b"oops", 2, 1).unwrap();
+    for name in &pinned_files {
+        assert!(
+            root.join(name).exists(),
+            "pinned shard {} was collected",
+            name
+        );
+    }
+    drop(store);
+
+    let mut store = BlockStore::open(root.clone(), ROOT_KEY).unwrap();
+    let mut historical = Vec::new();
+    store
+        .reader_at_epoch(pinned_epoch, None)
+        .unwrap()
+        .read_to_end(&mut historical)
+        .unwrap();
+    assert_eq!(historical, b"precious data");
+    assert!(store.reader_at_epoch(pinned_epoch + 1, None).is_err());
+
+    store.rollback(pinned_epoch).unwrap();
+    assert!(store.manifest.epoch > pinned_epoch);
+    assert_eq!(store.read_at(0, 13).unwrap(), b"precious data");
+
+    let after_rollback = list_block_files(&root);
+    store.delete_snapshot("before-delete").unwrap();
+    assert_eq!(list_block_files(&root), after_rollback);
+
+    store.delete_range(0, 13).unwrap();
+    assert!(list_block_files(&root).is_empty());
+}
+
+#[test]
+fn test_rotate_key_moves_dataset_and_snapshots_to_new_key() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let mut store = BlockStore::create(root.clone(), "rotate.txt", ROOT_KEY).unwrap();
+    store.insert_at(0, b"pinned", 2, 1).unwrap();
+    let pinned_epoch = store.manifest.epoch;
+    store.create_snapshot("v1").unwrap();
+    store.insert_at(6, b" and current", 2, 1).unwrap();
+    let old_files = list_block_files(&root);
+
+    store.rotate_key(WRONG_ROOT_KEY).unwrap();
+    assert!(
+        list_block_files(&root)
+            .iter()
+            .all(|f| !old_files.contains(f))
+    );
+    drop(store);
+
+    assert!(BlockStore::open(root.clone(), ROOT_KEY).is_err());
+    let store = BlockStore::open(root.clone(), WRONG_ROOT_KEY).unwrap();
+    assert_eq!(store.read_at(0, 18).unwrap(), b"pinned and current");
+    let mut historical = Vec::new();
+    store
+        .reader_at_epoch(pinned_epoch, None)
+        .unwrap()
+        .read_to_end(&mut historical)
+        .unwrap();
+    assert_eq!(historical, b"pinned");
+}
+
+#[test]
+fn test_interrupted_rotate_key_keeps_old_key_readable() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let mut store = BlockStore::create(root.clone(), "rotate.txt", ROOT_KEY).unwrap();
+    store.insert_at(0, b"still here", 2, 1).unwrap();
+    let before = list_block_files(&root);
+
+    fs::write(root.join(MANIFEST_FAIL_MARKER), b"1").unwrap();
+    assert!(store.rotate_key(WRONG_ROOT_KEY).is_err());
+    fs::remove_file(root.join(MANIFEST_FAIL_MARKER)).unwrap();
+    assert_eq!(list_block_files(&root), before);
+    assert_eq!(store.read_at(0, 10).unwrap(), b"still here");
+    drop(store);
+
+    let reopened = BlockStore::open(root.clone(), ROOT_KEY).unwrap();
+    assert_eq!(reopened.read_at(0, 10).unwrap(), b"still here");
+    let epoch = reopened.manifest.epoch;
+    drop(reopened);
+
+    // A crash after staging leaves the re-keyed shards next to the old ones,
+    // without the cleanup above. They embed the old manifest MACed under the
+    // new key, so the new key opens that manifest but reads none of its blocks.
+    let crashed = tempdir().unwrap();
+    for entry in fs::read_dir(&root).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, crashed.path().join(path.file_name().unwrap())).unwrap();
+    }
+    let mut rotated = BlockStore::open(root.clone(), ROOT_KEY).unwrap();
+    rotated.rotate_key(WRONG_ROOT_KEY).unwrap();
+    for name in list_block_files(&root) {
+        if !before.contains(&name) {
+            fs::copy(root.join(&name), crashed.path().join(&name)).unwrap();
+        }
+    }
+
+    let old_key = BlockStore::open(crashed.path().to_path_buf(), ROOT_KEY).unwrap();
+    assert_eq!(old_key.manifest.epoch, epoch);
+    assert_eq!(old_key.read_at(0, 10).unwrap(), b"still here");
+    let new_key = BlockStore::open(crashed.path().to_path_buf(), WRONG_ROOT_KEY).unwrap();
+    assert_eq!(new_key.manifest.epoch, epoch);
+    assert!(new_key.read_at(0, 10).is_err());
+}
+
+#[test]
+fn test_memory_backend_round_trip_without_disk() {
+    let backend = MemoryBackend::new();
+    let shared: Arc<dyn ShardBackend> = Arc::new(backend.clone());
+
+    let mut store =
+        BlockStore::create_with_backend(shared.clone(), "mem.txt", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    store.insert_at(0, b"in memory only", 2, 1).unwrap();
+    store.delete_range(2, 7).unwrap();
+    drop(store);
+
+    let names = backend.list().unwrap();
+    assert!(names.iter().any(|name| name.starts_with("block_")));
+
+    let reopened = BlockStore::open_with_backend(shared, ROOT_KEY, IoOptions::fast()).unwrap();
+    assert_eq!(reopened.read_at(0, 7).unwrap(), b"in only");
+    assert!(
+        BlockStore::open_with_backend(Arc::new(backend), WRONG_ROOT_KEY, IoOptions::fast())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_dedup_shares_blocks_and_collects_only_unreferenced_ones() {
+    let backend = MemoryBackend::new();
+    let shared: Arc<dyn ShardBackend> = Arc::new(backend.clone());
+    let block_objects = || {
+        backend
+            .list()
+            .unwrap()
+            .into_iter()
+            .filter(|name| name.starts_with("block_"))
+            .count()
+    };
+
+    let mut store =
+        BlockStore::create_empty_with_backend(shared, ROOT_KEY, IoOptions::fast()).unwrap();
+    store.set_dedup(true).unwrap();
+    assert!(store.manifest.settings.dedup);
+
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let payload: Vec<u8> = (0..64 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let cdc = Chunking::ContentDefined(CdcParams::with_max_size(8 * 1024).unwrap());
+
+    store
+        .put_file_chunked("a.bin", payload.as_slice(), cdc, 2, 1)
+        .unwrap();
+    let objects_after_first = block_objects();
+    let first_ids: Vec<usize> = store.blocks().iter().map(|b| b.id).collect();
+
+    store
+        .put_file_chunked("b.bin", payload.as_slice(), cdc, 2, 1)
+        .unwrap();
+    assert_eq!(block_objects(), objects_after_first);
+    assert_eq!(
+        store.blocks().iter().map(|b| b.id).collect::<Vec<_>>(),
+        first_ids
+    );
+
+    // A small prefix edit only re-encodes the chunks around it.
+    let mut edited = b"prefix".to_vec();
+    edited.extend_from_slice(&payload);
+    store
+        .put_file_chunked("c.bin", edited.as_slice(), cdc, 2, 1)
+        .unwrap();
+    let reused = store
+        .blocks()
+        .iter()
+        .filter(|block| first_ids.contains(&block.id))
+        .count();
+    assert!(reused + 2 >= first_ids.len(), "only {} reused", reused);
+
+    store.remove_file("a.bin").unwrap();
+    store.remove_file("c.bin").unwrap();
+    assert_eq!(block_objects(), objects_after_first);
+
+    // Content keys follow the dataset to a new root key.
+    store.rotate_key(WRONG_ROOT_KEY).unwrap();
+    store
+        .put_file_chunked("d.bin", payload.as_slice(), cdc, 2, 1)
+        .unwrap();
+    assert_eq!(block_objects(), objects_after_first);
+    store.remove_file("d.bin").unwrap();
+    let mut restored = Vec::new();
+    store
+        .file_reader("b.bin")
+        .unwrap()
+        .read_to_end(&mut restored)
+        .unwrap();
+    assert_eq!(restored, payload);
+
+    store.remove_file("b.bin").unwrap();
+    assert_eq!(block_objects(), 0);
+}
+
+#[test]
+fn test_compression_policy_shrinks_blocks_and_reads_back_transparently() {
+    let backend = MemoryBackend::new();
+    let shared: Arc<dyn ShardBackend> = Arc::new(backend.clone());
+    let stored_bytes = || -> usize {
+        backend
+            .list()
+            .unwrap()
+            .iter()
+            .filter(|name| name.starts_with("block_"))
+            .map(|name| backend.get(name).unwrap().unwrap().len())
+            .sum()
+    };
+    let text = b"2026-01-01T00:00:00Z INFO request served in 3ms\n".repeat(400);
+
+    let mut plain =
+        BlockStore::create_with_backend(shared.clone(), "plain.log", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    plain.insert_at(0, &text, 2, 1).unwrap();
+    let uncompressed_bytes = stored_bytes();
+
+    let mut store =
+        BlockStore::create_with_backend(shared.clone(), "app.log", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    store
+        .set_compression(CompressionPolicy::Auto { level: 3 })
+        .unwrap();
+    store.insert_at(0, &text, 2, 1).unwrap();
+    assert_eq!(store.blocks()[0].compression, Compression::Zstd);
+    assert!(stored_bytes() * 4 < uncompressed_bytes);
+
+    // Raw and compressed blocks can sit side by side in one file.
+    store.set_compression(CompressionPolicy::None).unwrap();
+    store.insert_at(text.len() as u64, b"-raw-", 2, 1).unwrap();
+    drop(store);
+
+    let reopened = BlockStore::open_with_backend(shared, ROOT_KEY, IoOptions::fast()).unwrap();
+    let mut expected = text.clone();
+    expected.extend_from_slice(b"-raw-");
+    assert_eq!(
+        reopened.read_at(0, expected.len() as u64).unwrap(),
+        expected
+    );
+    let compressions: Vec<Compression> = reopened.blocks().iter().map(|b| b.compression).collect();
+    assert_eq!(compressions, vec![Compression::Zstd, Compression::None]);
+    assert_eq!(reopened.scrub(true).unwrap().unrecoverable_blocks(), 0);
+}
+
+#[test]
+fn test_parallel_workers_match_sequential_layout_and_content() {
+    let payload: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+    let run = |workers: usize| {
+        let options = IoOptions::fast().with_parallelism(workers);
+        let backend: Arc<dyn ShardBackend> = Arc::new(MemoryBackend::new());
+        let mut store =
+            BlockStore::create_with_backend(backend.clone(), "big.bin", ROOT_KEY, options).unwrap();
+        store.write_stream(payload.as_slice(), 1_000, 3, 2).unwrap();
+        store.write_at(2_500, &[0xee; 7_000]).unwrap();
+        store.delete_range(10_500, 12_345).unwrap();
+        store.insert_at(20_000, b"inserted", 2, 1).unwrap();
+        drop(store);
+
+        let store = BlockStore::open_with_backend(backend, ROOT_KEY, options).unwrap();
+        let layout: Vec<(usize, u64)> = store
+            .blocks()
+            .iter()
+            .map(|block| (block.id, block.original_size))
+            .collect();
+        let mut streamed = Vec::new();
+        store.reader().read_to_end(&mut streamed).unwrap();
+        assert_eq!(store.read_at(0, store.total_size()).unwrap(), streamed);
+        assert_eq!(store.scrub(true).unwrap().healthy_blocks(), layout.len());
+        (layout, streamed)
+    };
+
+    let mut expected = payload.clone();
+    expected[2_500..9_500].fill(0xee);
+    expected.drain(10_500..22_845);
+    expected.splice(20_000..20_000, b"inserted".iter().copied());
+
+    let (sequential_layout, sequential_bytes) = run(1);
+    let (parallel_layout, parallel_bytes) = run(8);
+    assert_eq!(sequential_bytes, expected);
+    assert_eq!(parallel_bytes, expected);
+    assert_eq!(parallel_layout, sequential_layout);
+}
+
+#[test]
+fn test_stale_writer_is_refused_instead_of_forking_the_epoch() {
+    let backend = MemoryBackend::new();
+    let options = IoOptions::fast().with_lock_timeout(Duration::from_millis(50));
+    let mut first =
+        BlockStore::create_with_backend(Arc::new(backend.clone()), "shared.txt", ROOT_KEY, options)
+            .unwrap();
+    first.insert_at(0, b"base", 2, 1).unwrap();
+
+    let mut second =
+        BlockStore::open_with_backend(Arc::new(backend.clone()), ROOT_KEY, options).unwrap();
+    first.insert_at(4, b" one", 2, 1).unwrap();
+
+    let err = second.insert_at(0, b"two ", 2, 1).unwrap_err();
+    assert!(err.to_string().contains("modified by another writer"));
+    let reopened =
+        BlockStore::open_with_backend(Arc::new(backend.clone()), ROOT_KEY, options).unwrap();
+    assert_eq!(reopened.read_at(0, 8).unwrap(), b"base one");
+
+    // An explicit exclusive lock catches up with the latest commit first.
+    second.lock_exclusive().unwrap();
+    second.insert_at(0, b"two ", 2, 1).unwrap();
+    assert_eq!(second.read_at(0, 12).unwrap(), b"two base one");
+
+    // While it is held, other stores time out instead of interleaving.
+    let err = first.delete_range(0, 4).unwrap_err();
+    assert!(err.is::<LockTimeout>());
+    let mut buf = [0u8; 4];
+    assert!(reopened.reader().read(&mut buf).is_err());
+    second.unlock();
+    let mut first = BlockStore::open_with_backend(Arc::new(backend), ROOT_KEY, options).unwrap();
+    first.delete_range(0, 4).unwrap();
+    assert_eq!(first.read_at(0, 8).unwrap(), b"base one");
+}
+
+/// Memory backend whose `head.txt` writes can be made to fail.
+#[derive(Debug, Default)]
+struct HeadFailingBackend {
+    inner: MemoryBackend,
+    fail_head: AtomicBool,
+}
+
+impl ShardBackend for HeadFailingBackend {
+    fn put(&self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        if name == "head.txt" && self.fail_head.load(Ordering::SeqCst) {
+            anyhow::bail!("head write dropped");
+        }
+        self.inner.put(name, bytes)
+    }
+
+    fn get(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.inner.get(name)
+    }
+
+    fn delete(&self, name: &str) -> anyhow::Result<()> {
+        self.inner.delete(name)
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<String>> {
+        self.inner.list()
+    }
+
+    fn location(&self) -> String {
+        self.inner.location()
+    }
+
+    fn lock(&self, mode: LockMode, timeout: Duration) -> anyhow::Result<DatasetLock> {
+        self.inner.lock(mode, timeout)
+    }
+}
+
+#[test]
+fn test_commit_fails_when_head_write_is_dropped_and_stale_writer_is_refused() {
+    let backend = Arc::new(HeadFailingBackend::default());
+    let shared: Arc<dyn ShardBackend> = backend.clone();
+    let options = IoOptions::fast().with_lock_timeout(Duration::from_millis(50));
+    let mut writer =
+        BlockStore::create_with_backend(shared.clone(), "shared.txt", ROOT_KEY, options).unwrap();
+    writer.insert_at(0, b"base", 2, 1).unwrap();
+    let mut stale = BlockStore::open_with_backend(shared.clone(), ROOT_KEY, options).unwrap();
+
+    // Without a head update the commit must not land at all.
+    backend.fail_head.store(true, Ordering::SeqCst);
+    let mut objects_before = shared.list().unwrap();
+    assert!(writer.insert_at(4, b" one", 2, 1).is_err());
+    let mut objects_after = shared.list().unwrap();
+    objects_before.sort();
+    objects_after.sort();
+    assert_eq!(objects_after, objects_before);
+    backend.fail_head.store(false, Ordering::SeqCst);
+
+    writer.insert_at(4, b" one", 2, 1).unwrap();
+    let err = stale.insert_at(0, b"two ", 2, 1).unwrap_err();
+    assert!(err.to_string().contains("modified by another writer"));
+
+    let reopened = BlockStore::open_with_backend(shared, ROOT_KEY, options).unwrap();
+    assert_eq!(reopened.manifest.epoch, writer.manifest.epoch);
+    assert_eq!(reopened.read_at(0, 8).unwrap(), b"base one");
+}
+
+#[test]
+fn test_destroy_waits_for_the_exclusive_lock() {
+    let backend = MemoryBackend::new();
+    let shared: Arc<dyn ShardBackend> = Arc::new(backend.clone());
+    let options = IoOptions::fast().with_lock_timeout(Duration::from_millis(50));
+    let mut store =
+        BlockStore::create_with_backend(shared.clone(), "doomed.txt", ROOT_KEY, options).unwrap();
+    store.insert_at(0, b"still being read", 2, 1).unwrap();
+
+    let reading = shared.lock(LockMode::Shared, options.lock_timeout).unwrap();
+    let err = BlockStore::destroy_with_backend(shared.as_ref(), options.lock_timeout).unwrap_err();
+    assert!(err.is::<LockTimeout>());
+    assert_eq!(store.read_at(0, 16).unwrap(), b"still being read");
+    drop(reading);
+
+    let removed = BlockStore::destroy_with_backend(shared.as_ref(), options.lock_timeout).unwrap();
+    assert!(removed > 0);
+    assert!(!BlockStore::is_initialized(shared.as_ref()).unwrap());
+}
+
+#[test]
+fn test_transaction_commits_many_edits_as_one_epoch() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    let mut store = BlockStore::create(root.clone(), "batch.txt", ROOT_KEY).unwrap();
+    store
+        .write_stream(&b"aaaabbbbccccdddd"[..], 4, 2, 1)
+        .unwrap();
+    let epoch = store.manifest.epoch;
+    let files_before = list_block_files(&root);
+
+    // A dropped transaction leaves neither a commit nor any shard behind.
+    {
+        let mut transaction = store.transaction().unwrap();
+        transaction.insert_at(0, b"lost", 2, 1).unwrap();
+        assert_eq!(transaction.total_size(), 20);
+    }
+    assert_eq!(store.manifest.epoch, epoch);
+    assert_eq!(list_block_files(&root), files_before);
+
+    let mut transaction = store.transaction().unwrap();
+    transaction.insert_at(4, b"1234", 2, 1).unwrap();
+    transaction.write_at(5, b"XY").unwrap();
+    transaction.delete_range(12, 6).unwrap();
+    transaction.write_at(14, b"!!").unwrap();
+    assert_eq!(transaction.read_at(0, 16).unwrap(), b"aaaa1XY4bbbbdd!!");
+    assert!(transaction.delete_range(10, 7).is_err());
+    assert_eq!(transaction.edits(), 4);
+    transaction.commit().unwrap();
+
+    assert_eq!(store.manifest.epoch, epoch + 1);
+    assert_eq!(store.read_at(0, 16).unwrap(), b"aaaa1XY4bbbbdd!!");
+    assert_unique_ids(&store);
+    // The block inserted first was rewritten by the overwrite before the
+    // commit, so only blocks of the final view have shards on disk.
+    let shard_count: usize = store
+        .blocks()
+        .iter()
+        .map(|block| block.data_shards + block.parity_shards)
+        .sum();
+    assert_eq!(list_block_files(&root).len(), shard_count);
+
+    let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
+    assert_eq!(reopened.manifest.epoch, epoch + 1);
+    assert_eq!(reopened.read_at(0, 16).unwrap(), b"aaaa1XY4bbbbdd!!");
+}
+
+#[test]
+fn test_compact_merges_fragments_and_auto_compaction_follows_edits() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    let mut store = BlockStore::create(root.clone(), "frag.txt", ROOT_KEY).unwrap();
+    store.insert_at(0, b"0123456789", 2, 1).unwrap();
+    for (i, offset) in [2u64, 5, 9, 11].into_iter().enumerate() {
+        store
+            .insert_at(offset, format!("<{}>", i).as_bytes(), 2, 1)
+            .unwrap();
+    }
+    let expected = store.read_at(0, store.total_size()).unwrap();
+    let fragments = store.blocks().len();
+    assert!(fragments > 5);
+    let epoch = store.manifest.epoch;
+
+    let policy = CompactionPolicy {
+        small_block_size: 8,
+        target_block_size: 12,
+    };
+    let report = store.compact(&policy).unwrap();
+    assert_eq!(report.blocks_before, fragments);
+    assert_eq!(report.blocks_after, store.blocks().len());
+    assert!(report.blocks_merged > 0);
+    assert!(store.blocks().iter().all(|block| block.original_size <= 12));
+    assert_eq!(store.manifest.epoch, epoch + 1);
+    assert_eq!(store.read_at(0, store.total_size()).unwrap(), expected);
+    assert_eq!(list_block_files(&root).len(), store.blocks().len() * 3);
+    assert_unique_ids(&store);
+
+    // Nothing left to merge: no new epoch.
+    assert_eq!(store.compact(&policy).unwrap().blocks_merged, 0);
+    assert_eq!(store.manifest.epoch, epoch + 1);
+
+    store
+        .set_auto_compaction(Some(AutoCompaction {
+            policy: CompactionPolicy {
+                small_block_size: 64,
+                target_block_size: 64,
+            },
+            ratio: 0.5,
+        }))
+        .unwrap();
+    store.insert_at(3, b"!", 2, 1).unwrap();
+    assert_eq!(store.blocks().len(), 1);
+
+    let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
+    let mut edited = expected.clone();
+    edited.insert(3, b'!');
+    assert_eq!(reopened.read_at(0, reopened.total_size()).unwrap(), edited);
+    assert!(reopened.manifest.settings.auto_compact.is_some());
+}
+
+#[test]
+fn test_restripe_reencodes_in_steps_and_resumes_after_failure() {
+    let backend = MemoryBackend::new();
+    let shared: Arc<dyn ShardBackend> = Arc::new(backend.clone());
+    let block_objects = || {
+        let mut names: Vec<String> = backend
+            .list()
+            .unwrap()
+            .into_iter()
+            .filter(|name| name.starts_with("block_"))
+            .collect();
+        names.sort();
+        names
+    };
+    let mut store =
+        BlockStore::create_with_backend(shared.clone(), "stripes.txt", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    let content: Vec<u8> = (0..80u8).collect();
+    store.write_stream(&content[..], 1, 4, 2).unwrap();
+    let epoch = store.manifest.epoch;
+
+    let report = store.restripe_range(10, 15, 2, 4).unwrap();
+    assert_eq!(report.blocks_restriped, 15);
+    assert_eq!(report.steps, 1);
+    assert_eq!(store.manifest.epoch, epoch + 1);
+    assert!(store.blocks()[10..25].iter().all(|b| b.data_shards == 2));
+    assert_eq!(store.blocks()[9].data_shards, 4);
+
+    // A failed commit leaves the committed layout readable and no shards behind.
+    let objects_before = block_objects();
+    backend.put(MANIFEST_FAIL_MARKER, b"1").unwrap();
+    assert!(store.restripe(3, 3).is_err());
+    backend.delete(MANIFEST_FAIL_MARKER).unwrap();
+    assert_eq!(block_objects(), objects_before);
+    let mut store =
+        BlockStore::open_with_backend(shared.clone(), ROOT_KEY, IoOptions::fast()).unwrap();
+    assert_eq!(store.read_at(0, 80).unwrap(), content);
+
+    let report = store.restripe(2, 4).unwrap();
+    assert_eq!(report.blocks_skipped, 15);
+    assert_eq!(report.blocks_restriped, 65);
+    assert_eq!(report.bytes, 65);
+    assert_eq!(report.steps, 2);
+    assert!(store.blocks().iter().all(|b| b.parity_shards == 4));
+    assert_eq!(block_objects().len(), 80 * 6);
+    assert_unique_ids(&store);
+
+    let reopened = BlockStore::open_with_backend(shared, ROOT_KEY, IoOptions::fast()).unwrap();
+    assert_eq!(reopened.read_at(0, 80).unwrap(), content);
+    assert!(reopened.blocks().iter().all(|b| b.data_shards == 2));
+}
+
+#[test]
+fn test_restripe_collects_shared_blocks_whose_references_span_steps() {
+    let backend = MemoryBackend::new();
+    let shared: Arc<dyn ShardBackend> = Arc::new(backend.clone());
+    let mut store =
+        BlockStore::create_with_backend(shared.clone(), "shared.txt", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    store.set_dedup(true).unwrap();
+    // One-byte blocks: the first and last share a block, 71 positions apart.
+    let mut content = vec![0xaa];
+    content.extend(0..70u8);
+    content.push(0xaa);
+    store.write_stream(&content[..], 1, 4, 2).unwrap();
+    let first = store.blocks()[0].id;
+    assert_eq!(store.blocks()[71].id, first);
+
+    let report = store.restripe(2, 4).unwrap();
+    assert_eq!(report.steps, 2);
+    assert_eq!(report.blocks_restriped, 71);
+    let block_objects: Vec<String> = backend
+        .list()
+        .unwrap()
+        .into_iter()
+        .filter(|name| name.starts_with("block_"))
+        .collect();
+    assert_eq!(block_objects.len(), 71 * 6);
+    assert!(
+        !block_objects
+            .iter()
+            .any(|name| name.starts_with(&format!("block_{}_", first)))
+    );
+    assert_eq!(store.read_at(0, 72).unwrap(), content);
+}
+
+#[test]
+fn test_default_redundancy_is_persisted_and_used_by_edits() {
+    let backend: Arc<dyn ShardBackend> = Arc::new(MemoryBackend::new());
+    let mut store =
+        BlockStore::create_with_backend(backend.clone(), "policy.txt", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    assert_eq!(store.redundancy(), Redundancy::default());
+    let policy = Redundancy {
+        data_shards: 10,
+        parity_shards: 2,
+    };
+    store.set_redundancy(policy).unwrap();
+    assert!(
+        store
+            .set_redundancy(Redundancy {
+                data_shards: 0,
+                parity_shards: 2,
+            })
+            .is_err()
+    );
+
+    // An empty file can now be extended by write_at.
+    store.write_at(0, b"hello").unwrap();
+    store.insert(5, b" world").unwrap();
+    store.insert_at(0, b">", 3, 3).unwrap();
+    let layouts: Vec<(usize, usize)> = store
+        .blocks()
+        .iter()
+        .map(|block| (block.data_shards, block.parity_shards))
+        .collect();
+    assert_eq!(layouts, vec![(3, 3), (10, 2), (10, 2)]);
+
+    let mut reopened =
+        BlockStore::open_with_backend(backend.clone(), ROOT_KEY, IoOptions::fast()).unwrap();
+    assert_eq!(reopened.redundancy(), policy);
+    assert_eq!(reopened.read_at(0, 12).unwrap(), b">hello world");
+
+    reopened.restripe(2, 1).unwrap();
+    assert_eq!(
+        reopened.redundancy(),
+        Redundancy {
+            data_shards: 2,
+            parity_shards: 1,
+        }
+    );
+}
+
+#[test]
+fn test_merkle_root_is_recorded_per_epoch_and_proves_ranges() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    let mut store = BlockStore::create(root.clone(), "proof.txt", ROOT_KEY).unwrap();
+    store.insert(0, b"0123456789").unwrap();
+    store.insert(10, b"abcdef").unwrap();
+    let first_root = store.manifest.merkle_root;
+
+    let proof = store.prove_range(8, 4).unwrap();
+    assert_eq!(proof.epoch, store.manifest.epoch);
+    assert_eq!(proof.leaves.len(), 2);
+    proof.verify().unwrap();
+
+    let reopened = BlockStore::open(root.clone(), ROOT_KEY).unwrap();
+    assert_eq!(reopened.manifest.merkle_root, first_root);
+    assert_eq!(reopened.prove_range(8, 4).unwrap(), proof);
+
+    store.write_at(0, b"X").unwrap();
+    assert_ne!(store.manifest.merkle_root, first_root);
+    assert!(store.prove_range(12, 5).is_err());
+}
+
+#[test]
+fn test_archive_carries_current_epoch_and_survives_a_lost_shard() {
+    let source = MemoryBackend::new();
+    let shared: Arc<dyn ShardBackend> = Arc::new(source.clone());
+    let mut store =
+        BlockStore::create_with_backend(shared, "ship.txt", ROOT_KEY, IoOptions::fast()).unwrap();
+    let content: Vec<u8> = (0..200u8).collect();
+    store.write_stream(&content[..], 50, 2, 2).unwrap();
+    store.insert(0, b"v2:").unwrap();
+    let lost = format!("block_{}_0.bin", store.blocks()[1].id);
+    source.delete(&lost).unwrap();
+
+    let mut bytes = Vec::new();
+    let exported = store.export_archive(&mut bytes).unwrap();
+    assert_eq!(exported.epoch, store.manifest.epoch);
+    assert_eq!(exported.missing, vec![lost]);
+    let index = archive::read_index(&mut Cursor::new(&bytes)).unwrap();
+    assert!(
+        index
+            .entries
+            .iter()
+            .all(|entry| !entry.name.starts_with("meta_")
+                || entry.name.starts_with(&format!("meta_{}_", exported.epoch)))
+    );
+
+    // A failed validation installs nothing.
+    let target = MemoryBackend::new();
+    let err = archive::import(
+        Cursor::new(bytes.clone()),
+        Arc::new(target.clone()),
+        WRONG_ROOT_KEY,
+        IoOptions::fast(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("no valid manifest quorum"));
+    assert!(target.list().unwrap().is_empty());
+
+    // So does a damaged object, even in a block that could be rebuilt.
+    let mut damaged = bytes.clone();
+    let entry = &index.entries[0];
+    damaged[entry.offset as usize + entry.length as usize / 2] ^= 1;
+    assert!(
+        archive::import(
+            Cursor::new(damaged),
+            Arc::new(target.clone()),
+            ROOT_KEY,
+            IoOptions::fast(),
+        )
+        .is_err()
+    );
+    assert!(target.list().unwrap().is_empty());
+
+    let imported = archive::import(
+        Cursor::new(bytes.clone()),
+        Arc::new(target.clone()),
+        ROOT_KEY,
+        IoOptions::fast(),
+    )
+    .unwrap();
+    assert_eq!(imported.degraded_blocks, 1);
+    let copy = BlockStore::open_with_backend(Arc::new(target.clone()), ROOT_KEY, IoOptions::fast())
+        .unwrap();
+    let mut expected = b"v2:".to_vec();
+    expected.extend_from_slice(&content);
+    assert_eq!(copy.read_at(0, 203).unwrap(), expected);
+
+    // Installing over a dataset is refused.
+    let err = archive::import(
+        Cursor::new(bytes),
+        Arc::new(target),
+        ROOT_KEY,
+        IoOptions::fast(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("already holds a dataset"));
+}
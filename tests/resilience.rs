@@ -1,7 +1,12 @@
+use ironclad::backend::{MemoryBackend, ShardBackend, StripedBackend};
 use ironclad::block_store::BlockStore;
+use ironclad::integrity::Redundancy;
+use ironclad::io_guard::IoOptions;
+use ironclad::scrub::{BlockHealth, RepairOutcome, ShardStatus};
 use rand::prelude::*;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tempfile::tempdir;
 
 const ROOT_KEY: [u8; 32] = [0x5a; 32];
@@ -38,7 +43,7 @@ fn test_resilience_random_bitflips_blockstore() {
     store
         .insert_at(0, &original_data, 4, 8)
         .expect("Insert failed");
-    let block_id = store.manifest.blocks[0].id;
+    let block_id = store.blocks()[0].id;
 
     for i in 0..5 {
         let path = root.join(format!("block_{}_{}.bin", block_id, i));
@@ -61,7 +66,7 @@ fn test_resilience_erasure_loss_blockstore() {
     store
         .insert_at(0, &original_data, 4, 8)
         .expect("Insert failed");
-    let block_id = store.manifest.blocks[0].id;
+    let block_id = store.blocks()[0].id;
 
     for i in 0..4 {
         let path = root.join(format!("block_{}_{}.bin", block_id, i));
@@ -84,7 +89,7 @@ fn test_resilience_mixed_loss_and_corruption_blockstore() {
     store
         .insert_at(0, &original_data, 4, 8)
         .expect("Insert failed");
-    let block_id = store.manifest.blocks[0].id;
+    let block_id = store.blocks()[0].id;
 
     for i in 0..3 {
         let path = root.join(format!("block_{}_{}.bin", block_id, i));
@@ -151,6 +156,113 @@ fn test_open_fails_when_two_metadata_copies_corrupted() {
     assert!(err.to_string().contains("not initialized"));
 }
 
+#[test]
+fn test_scrub_reports_remaining_parity() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let mut store = BlockStore::create(root.clone(), "scrub.txt", ROOT_KEY).unwrap();
+    store.insert_at(0, b"first block", 4, 2).expect("insert");
+    store.insert_at(11, b"second block", 4, 2).expect("append");
+
+    let report = store.scrub(false).expect("scrub");
+    assert_eq!(report.blocks.len(), 2);
+    assert_eq!(report.healthy_blocks(), 2);
+
+    let first = store.blocks()[0].id;
+    fs::remove_file(root.join(format!("block_{}_1.bin", first))).unwrap();
+
+    let second = store.blocks()[1].id;
+    for i in 0..3 {
+        corrupt_file(&root.join(format!("block_{}_{}.bin", second, i)), 8);
+    }
+
+    let report = store.scrub(true).expect("scrub");
+    assert_eq!(
+        report.blocks[0].health,
+        BlockHealth::Degraded {
+            parity_remaining: 1,
+            parity_total: 2
+        }
+    );
+    assert_eq!(report.blocks[0].shard_status[1], ShardStatus::Missing);
+    assert_eq!(report.blocks[0].plaintext_verified, Some(true));
+    assert_eq!(report.blocks[1].health, BlockHealth::Unrecoverable);
+    assert_eq!(report.blocks[1].bad_shards(), vec![0, 1, 2]);
+    assert_eq!(report.min_parity_remaining(), Some(1));
+}
+
+#[test]
+fn test_repair_block_rewrites_only_bad_shards() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let original_data = generate_random_data(64 * 1024);
+    let mut store = BlockStore::create(root.clone(), "repair.txt", ROOT_KEY).unwrap();
+    store.insert_at(0, &original_data, 4, 4).expect("insert");
+    let block_id = store.blocks()[0].id;
+    let epoch = store.manifest.epoch;
+
+    let shard_path = |i: usize| root.join(format!("block_{}_{}.bin", block_id, i));
+    let untouched_before = fs::read(shard_path(5)).unwrap();
+    fs::remove_file(shard_path(0)).unwrap();
+    corrupt_file(&shard_path(2), 8);
+    corrupt_file(&shard_path(6), 8);
+
+    let report = store.repair_block(block_id).expect("repair");
+    assert_eq!(
+        report.outcome,
+        RepairOutcome::Repaired {
+            shards: vec![0, 2, 6]
+        }
+    );
+    assert_eq!(fs::read(shard_path(5)).unwrap(), untouched_before);
+
+    let scrub = store.scrub(true).expect("scrub");
+    assert_eq!(scrub.healthy_blocks(), 1);
+
+    let reopened = BlockStore::open(root, ROOT_KEY).unwrap();
+    assert_eq!(reopened.manifest.epoch, epoch);
+    assert_eq!(
+        reopened.read_at(0, original_data.len() as u64).unwrap(),
+        original_data
+    );
+    assert_eq!(
+        reopened.repair_block(block_id).unwrap().outcome,
+        RepairOutcome::AlreadyHealthy
+    );
+}
+
+#[test]
+fn test_read_repair_heals_rejected_shards() {
+    let dir = tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+
+    let original_data = generate_random_data(32 * 1024);
+    let mut store = BlockStore::create(root.clone(), "read-repair.txt", ROOT_KEY).unwrap();
+    store.insert_at(0, &original_data, 4, 4).expect("insert");
+    let block_id = store.blocks()[0].id;
+    drop(store);
+
+    fs::remove_file(root.join(format!("block_{}_1.bin", block_id))).unwrap();
+    corrupt_file(&root.join(format!("block_{}_7.bin", block_id)), 8);
+
+    let passive = BlockStore::open(root.clone(), ROOT_KEY).unwrap();
+    passive.read_at(0, original_data.len() as u64).unwrap();
+    assert_eq!(passive.read_repaired_shards(), 0);
+    assert_eq!(passive.scrub(false).unwrap().degraded_blocks(), 1);
+
+    let options = IoOptions::strict().with_read_repair(true);
+    let healing = BlockStore::open_with_options(root, ROOT_KEY, options).unwrap();
+    let recovered = healing.read_at(0, original_data.len() as u64).unwrap();
+    assert_eq!(recovered, original_data);
+    assert_eq!(healing.read_repaired_shards(), 2);
+    assert_eq!(healing.scrub(false).unwrap().healthy_blocks(), 1);
+
+    healing.read_at(0, original_data.len() as u64).unwrap();
+    assert_eq!(healing.read_repaired_shards(), 2);
+}
+
 fn metadata_files_for_epoch(root: &Path, epoch: u64) -> Vec<PathBuf> {
     let mut result = Vec::new();
     for entry in fs::read_dir(root).expect("read_dir") {
@@ -165,3 +277,104 @@ fn metadata_files_for_epoch(root: &Path, epoch: u64) -> Vec<PathBuf> {
     }
     result
 }
+
+#[test]
+fn test_striped_dataset_survives_losing_parity_count_of_domains() {
+    let dir = tempdir().unwrap();
+    let roots: Vec<PathBuf> = (0..6)
+        .map(|i| dir.path().join(format!("disk{}", i)))
+        .collect();
+    for root in &roots {
+        fs::create_dir_all(root).unwrap();
+    }
+    let backend: Arc<dyn ShardBackend> = Arc::new(StripedBackend::local(&roots, false).unwrap());
+
+    let original_data = generate_random_data(64 * 1024);
+    let mut store =
+        BlockStore::create_with_backend(backend, "striped.bin", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    store
+        .write_stream(original_data.as_slice(), 16 * 1024, 4, 2)
+        .unwrap();
+    drop(store);
+
+    for root in &roots {
+        let shards = fs::read_dir(root).unwrap().count();
+        assert!(shards > 0, "{} received no shards", root.display());
+    }
+
+    // Lose two whole devices, matching the parity count.
+    fs::remove_dir_all(&roots[1]).unwrap();
+    fs::remove_dir_all(&roots[4]).unwrap();
+
+    let backend: Arc<dyn ShardBackend> = Arc::new(StripedBackend::local(&roots, false).unwrap());
+    let store = BlockStore::open_with_backend(backend, ROOT_KEY, IoOptions::fast()).unwrap();
+    let recovered = store.read_at(0, original_data.len() as u64).unwrap();
+    assert_eq!(recovered, original_data);
+}
+
+#[test]
+fn test_striped_store_refuses_layouts_wider_than_its_domains() {
+    let domains: Vec<Arc<dyn ShardBackend>> = (0..3)
+        .map(|_| Arc::new(MemoryBackend::new()) as Arc<dyn ShardBackend>)
+        .collect();
+    let backend: Arc<dyn ShardBackend> = Arc::new(StripedBackend::new(domains).unwrap());
+    let mut store =
+        BlockStore::create_with_backend(backend, "narrow.bin", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+
+    store.insert_at(0, b"fits", 2, 1).unwrap();
+    let err = store.insert_at(0, b"too wide", 2, 2).unwrap_err();
+    assert!(
+        err.to_string().contains("needs 4 failure domains"),
+        "{}",
+        err
+    );
+    let err = store
+        .set_redundancy(Redundancy {
+            data_shards: 4,
+            parity_shards: 4,
+        })
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("needs 8 failure domains"),
+        "{}",
+        err
+    );
+    assert_eq!(store.read_at(0, 4).unwrap(), b"fits");
+}
+
+#[test]
+fn test_striped_store_refuses_a_restripe_wider_than_its_domains() {
+    let domains: Vec<Arc<dyn ShardBackend>> = (0..4)
+        .map(|_| Arc::new(MemoryBackend::new()) as Arc<dyn ShardBackend>)
+        .collect();
+    let backend: Arc<dyn ShardBackend> = Arc::new(StripedBackend::new(domains).unwrap());
+    let mut store =
+        BlockStore::create_with_backend(backend, "narrow.bin", ROOT_KEY, IoOptions::fast())
+            .unwrap();
+    store.insert_at(0, b"stays 2+1", 2, 1).unwrap();
+    let epoch = store.manifest.epoch;
+
+    let err = store.restripe_range(0, 9, 4, 2).unwrap_err();
+    assert!(
+        err.to_string().contains("needs 6 failure domains"),
+        "{}",
+        err
+    );
+    let err = store.restripe(3, 3).unwrap_err();
+    assert!(
+        err.to_string().contains("needs 6 failure domains"),
+        "{}",
+        err
+    );
+    assert_eq!(store.manifest.epoch, epoch);
+    assert!(
+        store
+            .blocks()
+            .iter()
+            .all(|block| (block.data_shards, block.parity_shards) == (2, 1))
+    );
+    store.restripe(2, 2).unwrap();
+    assert_eq!(store.read_at(0, 9).unwrap(), b"stays 2+1");
+}